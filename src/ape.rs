@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const FOOTER_SIZE: u64 = 32;
+const ID3V1_SIZE: u64 = 128;
+const MAX_TAG_SIZE: u32 = 1024 * 1024;
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+// Text items of an APEv2 tag, found at the end of the file (before any ID3v1 tag)
+pub struct ApeTag {
+    items: Vec<(String, String)>,
+}
+
+impl ApeTag {
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Option<ApeTag> {
+        let mut file = File::open(path).ok()?;
+        let file_size = file.seek(SeekFrom::End(0)).ok()?;
+
+        // Try right at the end, then before an ID3v1 tag
+        let footer = [file_size, file_size.saturating_sub(ID3V1_SIZE)]
+            .iter()
+            .filter(|&&end| end >= FOOTER_SIZE)
+            .find_map(|&end| {
+                let mut footer = [0; FOOTER_SIZE as usize];
+                file.seek(SeekFrom::Start(end - FOOTER_SIZE)).ok()?;
+                file.read_exact(&mut footer).ok()?;
+                if &footer[..8] == b"APETAGEX" {
+                    Some((end, footer))
+                } else {
+                    None
+                }
+            });
+        let (end, footer) = footer?;
+
+        // The size includes the footer but not the optional header
+        let size = read_u32_le(&footer[12..16]);
+        let count = read_u32_le(&footer[16..20]);
+        if size < FOOTER_SIZE as u32 || size > MAX_TAG_SIZE || end < size as u64 {
+            return None;
+        }
+        let mut bytes = vec![0; (size as u64 - FOOTER_SIZE) as usize];
+        file.seek(SeekFrom::Start(end - size as u64)).ok()?;
+        file.read_exact(&mut bytes).ok()?;
+
+        let mut items = Vec::new();
+        let mut position = 0;
+        for _ in 0..count {
+            if position + 8 > bytes.len() {
+                break;
+            }
+            let value_size = read_u32_le(&bytes[position..]) as usize;
+            let flags = read_u32_le(&bytes[position + 4..]);
+            position += 8;
+
+            let key_end = match bytes[position..].iter().position(|&byte| byte == 0) {
+                Some(length) => position + length,
+                None => break,
+            };
+            let key = String::from_utf8_lossy(&bytes[position..key_end]).to_string();
+            position = key_end + 1;
+            if position + value_size > bytes.len() {
+                break;
+            }
+
+            // Bits 1-2 give the item type, 0 is UTF-8 text
+            if (flags >> 1) & 0x3 == 0 {
+                let value = String::from_utf8_lossy(&bytes[position..position + value_size]);
+                items.push((key, value.to_string()));
+            }
+            position += value_size;
+        }
+
+        Some(ApeTag { items })
+    }
+
+    // Keys are case insensitive
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|(item_key, _)| item_key.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+}
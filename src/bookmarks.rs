@@ -0,0 +1,305 @@
+use gtk::{
+    BoxExt, Button, ButtonExt, ContainerExt, Entry, EntryExt, Inhibit, MenuButton, MenuButtonExt,
+    Orientation, PositionType, RangeExt, Scale, ScaleExt, Separator, WidgetExt,
+};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use config::config_dir;
+use millis_to_minutes;
+use playlist::Playlist;
+use App;
+
+const BOOKMARKS_FILE: &str = "bookmarks";
+
+pub struct Bookmark {
+    pub millis: u64,
+    pub name: String,
+}
+
+// Named positions by file, stored one per line as "millis\tname\tpath"
+pub struct Bookmarks {
+    entries: HashMap<String, Vec<Bookmark>>,
+    file: Option<PathBuf>,
+    // Incremented on every change, so that the views know when to refresh
+    revision: u32,
+}
+
+impl Bookmarks {
+    pub fn load() -> Self {
+        let file = config_dir().map(|dir| dir.join(BOOKMARKS_FILE));
+        let mut entries: HashMap<String, Vec<Bookmark>> = HashMap::new();
+
+        if let Some(reader) = file.as_ref().and_then(|file| File::open(file).ok()) {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                let mut fields = line.splitn(3, '\t');
+                let millis = fields.next().and_then(|field| field.parse().ok());
+                if let (Some(millis), Some(name), Some(path)) =
+                    (millis, fields.next(), fields.next())
+                {
+                    entries.entry(path.to_string()).or_default().push(Bookmark {
+                        millis,
+                        name: name.to_string(),
+                    });
+                }
+            }
+        }
+
+        Bookmarks {
+            entries,
+            file,
+            revision: 0,
+        }
+    }
+
+    fn save(&self) {
+        let file = self.file.as_ref().and_then(|file| File::create(file).ok());
+        if let Some(mut file) = file {
+            for (path, bookmarks) in &self.entries {
+                for bookmark in bookmarks {
+                    let _ = writeln!(file, "{}\t{}\t{}", bookmark.millis, bookmark.name, path);
+                }
+            }
+        }
+    }
+
+    // Sorted by position
+    pub fn get(&self, path: &str) -> &[Bookmark] {
+        self.entries.get(path).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn add(&mut self, path: &str, millis: u64, name: &str) {
+        let bookmarks = self.entries.entry(path.to_string()).or_default();
+        bookmarks.push(Bookmark {
+            millis,
+            name: name.replace('\t', " "),
+        });
+        bookmarks.sort_by_key(|bookmark| bookmark.millis);
+        self.revision += 1;
+        self.save();
+    }
+
+    pub fn remove(&mut self, path: &str, index: usize) {
+        if let Some(bookmarks) = self.entries.get_mut(path) {
+            if index < bookmarks.len() {
+                bookmarks.remove(index);
+            }
+            if bookmarks.is_empty() {
+                self.entries.remove(path);
+            }
+        }
+        self.revision += 1;
+        self.save();
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some(bookmarks) = self.entries.remove(from) {
+            self.entries.insert(to.to_string(), bookmarks);
+            self.revision += 1;
+            self.save();
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+}
+
+// A-B loop buttons and the popover listing the bookmarks of the current track
+pub struct PositionControls {
+    container: gtk::Box,
+    loop_start_button: Button,
+    loop_end_button: Button,
+    clear_loop_button: Button,
+    bookmarks_button: MenuButton,
+    popover: gtk::Popover,
+    list: gtk::Box,
+    name_entry: Entry,
+    add_button: Button,
+}
+
+impl PositionControls {
+    pub fn new() -> Self {
+        let container = gtk::Box::new(Orientation::Horizontal, 4);
+
+        let loop_start_button = Button::new_with_label("A");
+        loop_start_button.set_tooltip_text("Loop from the current position");
+        container.add(&loop_start_button);
+
+        let loop_end_button = Button::new_with_label("B");
+        loop_end_button.set_tooltip_text("Loop up to the current position");
+        container.add(&loop_end_button);
+
+        let clear_loop_button = Button::new_with_label("Clear loop");
+        container.add(&clear_loop_button);
+
+        let bookmarks_button = MenuButton::new();
+        bookmarks_button.add(&gtk::Label::new("Bookmarks"));
+        container.add(&bookmarks_button);
+
+        let popover = gtk::Popover::new(Some(&bookmarks_button));
+        let vbox = gtk::Box::new(Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        let add_box = gtk::Box::new(Orientation::Horizontal, 6);
+        let name_entry = Entry::new();
+        name_entry.set_placeholder_text("Bookmark name");
+        add_box.pack_start(&name_entry, true, true, 0);
+        let add_button = Button::new_with_label("Add");
+        add_box.pack_start(&add_button, false, false, 0);
+        vbox.add(&add_box);
+        vbox.add(&Separator::new(Orientation::Horizontal));
+        let list = gtk::Box::new(Orientation::Vertical, 2);
+        vbox.add(&list);
+        vbox.show_all();
+        popover.add(&vbox);
+        bookmarks_button.set_popover(Some(&popover));
+
+        PositionControls {
+            container,
+            loop_start_button,
+            loop_end_button,
+            clear_loop_button,
+            bookmarks_button,
+            popover,
+            list,
+            name_entry,
+            add_button,
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box {
+        &self.container
+    }
+}
+
+// Rows of the popover: jump to the bookmark, or remove it
+fn fill_bookmarks(
+    controls: &Rc<PositionControls>,
+    bookmarks: &Rc<RefCell<Bookmarks>>,
+    playlist: &Rc<Playlist>,
+) {
+    for child in controls.list.get_children() {
+        controls.list.remove(&child);
+    }
+
+    if let Some(path) = playlist.path() {
+        for (index, bookmark) in bookmarks.borrow().get(&path).iter().enumerate() {
+            let row = gtk::Box::new(Orientation::Horizontal, 6);
+
+            let label = format!("{}  {}", millis_to_minutes(bookmark.millis), bookmark.name);
+            let jump_button = Button::new_with_label(&label);
+            let millis = bookmark.millis;
+            let popover = controls.popover.clone();
+            let jump_playlist = playlist.clone();
+            jump_button.connect_clicked(move |_| {
+                jump_playlist.seek(millis);
+                popover.hide();
+            });
+            row.pack_start(&jump_button, true, true, 0);
+
+            let remove_button = Button::new_with_label("Remove");
+            let path = path.clone();
+            let remove_controls = controls.clone();
+            let remove_bookmarks = bookmarks.clone();
+            let remove_playlist = playlist.clone();
+            remove_button.connect_clicked(move |_| {
+                remove_bookmarks.borrow_mut().remove(&path, index);
+                fill_bookmarks(&remove_controls, &remove_bookmarks, &remove_playlist);
+            });
+            row.pack_start(&remove_button, false, false, 0);
+
+            controls.list.add(&row);
+        }
+    }
+    controls.list.show_all();
+}
+
+// Loop points below the progress scale, bookmarks above
+pub fn set_marks(scale: &Scale, ab_loop: (Option<u64>, Option<u64>), bookmarks: &[Bookmark]) {
+    scale.clear_marks();
+    if let Some(start) = ab_loop.0 {
+        scale.add_mark(start as f64, PositionType::Bottom, "A");
+    }
+    if let Some(end) = ab_loop.1 {
+        scale.add_mark(end as f64, PositionType::Bottom, "B");
+    }
+    for bookmark in bookmarks {
+        scale.add_mark(bookmark.millis as f64, PositionType::Top, None);
+    }
+}
+
+impl App {
+    pub fn connect_position_events(&self) {
+        let playlist = self.playlist.clone();
+        self.scale.connect_change_value(move |_, _, value| {
+            playlist.seek(value.max(0.0) as u64);
+            Inhibit(false)
+        });
+
+        let state = self.state.clone();
+        self.position_controls
+            .loop_start_button
+            .connect_clicked(move |_| {
+                let mut state = state.lock().unwrap();
+                let start = state.current_time;
+                state.ab_loop = match state.ab_loop {
+                    (_, Some(end)) if end > start => (Some(start), Some(end)),
+                    _ => (Some(start), None),
+                };
+            });
+
+        // Without an A point, the loop starts at the beginning of the track
+        let state = self.state.clone();
+        self.position_controls
+            .loop_end_button
+            .connect_clicked(move |_| {
+                let mut state = state.lock().unwrap();
+                let end = state.current_time;
+                state.ab_loop = match state.ab_loop {
+                    (Some(start), _) if start < end => (Some(start), Some(end)),
+                    (Some(_), _) => (None, None),
+                    (None, _) => (Some(0), Some(end)),
+                };
+            });
+
+        let state = self.state.clone();
+        self.position_controls
+            .clear_loop_button
+            .connect_clicked(move |_| {
+                state.lock().unwrap().ab_loop = (None, None);
+            });
+
+        let controls = self.position_controls.clone();
+        let bookmarks = self.bookmarks.clone();
+        let playlist = self.playlist.clone();
+        self.position_controls
+            .bookmarks_button
+            .connect_clicked(move |_| {
+                fill_bookmarks(&controls, &bookmarks, &playlist);
+            });
+
+        let controls = self.position_controls.clone();
+        let bookmarks = self.bookmarks.clone();
+        let playlist = self.playlist.clone();
+        let state = self.state.clone();
+        self.position_controls.add_button.connect_clicked(move |_| {
+            if let Some(path) = playlist.path() {
+                let millis = state.lock().unwrap().current_time;
+                let name = controls.name_entry.get_text().unwrap_or_default();
+                let name = if name.trim().is_empty() {
+                    millis_to_minutes(millis)
+                } else {
+                    name.trim().to_string()
+                };
+                bookmarks.borrow_mut().add(&path, millis, &name);
+                controls.name_entry.set_text("");
+                fill_bookmarks(&controls, &bookmarks, &playlist);
+            }
+        });
+    }
+}
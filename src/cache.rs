@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const DURATIONS_FILE: &str = "durations";
+
+// $XDG_CACHE_HOME/rusic, or ~/.cache/rusic
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    let dir = dir.join("rusic");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+// Modification time (seconds) and size of a file, an entry is stale when they change
+pub fn file_key(path: &str) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((mtime, metadata.len()))
+}
+
+// FNV-1a, which unlike the standard hasher gives the same values with every build, for the
+// hashes kept on disk
+pub fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// Durations in milliseconds, stored one per line as "mtime\tsize\tmillis\tpath". Lines are
+// appended as files are added, the file is rewritten without the replaced ones when loaded.
+pub struct DurationCache {
+    entries: HashMap<String, (u64, u64, u64)>,
+    file: Option<PathBuf>,
+}
+
+impl DurationCache {
+    pub fn load() -> Self {
+        let file = cache_dir().map(|dir| dir.join(DURATIONS_FILE));
+        let mut entries = HashMap::new();
+        let mut lines = 0;
+
+        if let Some(reader) = file.as_ref().and_then(|file| File::open(file).ok()) {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                lines += 1;
+                let mut fields = line.splitn(4, '\t');
+                let mtime = fields.next().and_then(|field| field.parse().ok());
+                let size = fields.next().and_then(|field| field.parse().ok());
+                let millis = fields.next().and_then(|field| field.parse().ok());
+                if let (Some(mtime), Some(size), Some(millis), Some(path)) =
+                    (mtime, size, millis, fields.next())
+                {
+                    // Later lines replace the earlier ones
+                    entries.insert(path.to_string(), (mtime, size, millis));
+                }
+            }
+        }
+
+        let cache = DurationCache { entries, file };
+        if lines > cache.entries.len() {
+            cache.save();
+        }
+        cache
+    }
+
+    // Written to a temporary file first, so that an interrupted save keeps the old entries
+    fn save(&self) {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return,
+        };
+        let temporary = file.with_extension("new");
+        let written = File::create(&temporary).and_then(|output| {
+            let mut output = BufWriter::new(output);
+            for (path, &(mtime, size, millis)) in &self.entries {
+                writeln!(output, "{}\t{}\t{}\t{}", mtime, size, millis, path)?;
+            }
+            output.flush()
+        });
+        if written.is_ok() {
+            let _ = fs::rename(&temporary, file);
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<u64> {
+        let &(mtime, size, millis) = self.entries.get(path)?;
+        if file_key(path) == Some((mtime, size)) {
+            Some(millis)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, path: &str, millis: u64) {
+        if let Some((mtime, size)) = file_key(path) {
+            self.entries.insert(path.to_string(), (mtime, size, millis));
+            if let Some(ref file) = self.file {
+                if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(file) {
+                    let _ = writeln!(file, "{}\t{}\t{}\t{}", mtime, size, millis, path);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crossfade::FadeCurve;
+use equalizer::EqSettings;
+use output::SampleFormat;
+use replaygain::GainMode;
+use resampler::ResamplerQuality;
+use stretch::{MAX_PITCH, MAX_SPEED, MIN_SPEED};
+use visualizer::VisualizerMode;
+
+const CONFIG_FILE: &str = "config";
+
+// Tried in order on the files without tags
+const DEFAULT_TAG_PATTERNS: [&str; 5] = [
+    "%artist% - %album%/%track% - %title%",
+    "%artist%/%album%/%track% - %title%",
+    "%track% - %artist% - %title%",
+    "%track% - %title%",
+    "%artist% - %title%",
+];
+
+pub const MAX_CROSSFADE: u32 = 12;
+pub const MAX_PREAMP: f32 = 15.0;
+// Output format value following the sound card
+pub const AUTOMATIC_FORMAT: &str = "auto";
+
+// $XDG_CONFIG_HOME/rusic, or ~/.config/rusic
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    let dir = dir.join("rusic");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn split_settings(value: &str) -> Option<(String, EqSettings)> {
+    let mut fields = value.splitn(2, '\t');
+    let name = fields.next()?;
+    let settings = EqSettings::from_value(fields.next()?)?;
+    Some((name.to_string(), settings))
+}
+
+// User preferences, stored as "key = value" lines
+#[derive(Clone)]
+pub struct Config {
+    // Seconds, 0 disables the crossfade
+    pub crossfade: u32,
+    pub crossfade_curve: FadeCurve,
+    // Crossfade consecutive tracks of the same album, instead of joining them gaplessly
+    pub crossfade_same_album: bool,
+    pub replaygain_mode: GainMode,
+    // dB added to the ReplayGain value
+    pub replaygain_preamp: f32,
+    // Lower the gain so that the peak does not exceed full scale
+    pub replaygain_prevent_clipping: bool,
+    // None follows the format of the sound card
+    pub output_format: Option<SampleFormat>,
+    // Hz, 0 follows the rate of each file
+    pub output_rate: u32,
+    pub resampler_quality: ResamplerQuality,
+    // Playback speed with the pitch kept, 1 being the normal speed
+    pub speed: f32,
+    // Semitones
+    pub pitch: f32,
+    // Used for every track, or for the tracks without their own settings
+    pub equalizer: EqSettings,
+    pub equalizer_per_track: bool,
+    pub equalizer_presets: Vec<(String, EqSettings)>,
+    // By path
+    pub track_equalizers: HashMap<String, EqSettings>,
+    pub visualizer: VisualizerMode,
+    // Guess the tags of untagged files from their path, see guess.rs
+    pub tag_patterns: Vec<String>,
+    // Show the now playing view instead of the playlist
+    pub now_playing: bool,
+    // Folders indexed by the library
+    pub library_folders: Vec<String>,
+    // Lyrics pane next to the views
+    pub show_lyrics: bool,
+    // Seconds the lyrics of a file are shown later, by path
+    pub lyrics_offsets: HashMap<String, f64>,
+    // Where the mini player window was last closed
+    pub mini_player_position: Option<(i32, i32)>,
+    // Write the play count and rating to the POPM and PCNT frames of the files
+    pub stats_in_tags: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            crossfade: 0,
+            crossfade_curve: FadeCurve::EqualPower,
+            crossfade_same_album: false,
+            replaygain_mode: GainMode::Off,
+            replaygain_preamp: 0.0,
+            replaygain_prevent_clipping: true,
+            output_format: None,
+            output_rate: 0,
+            resampler_quality: ResamplerQuality::Medium,
+            speed: 1.0,
+            pitch: 0.0,
+            equalizer: EqSettings::default(),
+            equalizer_per_track: false,
+            equalizer_presets: Vec::new(),
+            track_equalizers: HashMap::new(),
+            visualizer: VisualizerMode::Spectrum,
+            tag_patterns: DEFAULT_TAG_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            now_playing: false,
+            library_folders: Vec::new(),
+            show_lyrics: false,
+            lyrics_offsets: HashMap::new(),
+            mini_player_position: None,
+            stats_in_tags: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let mut config = Config::default();
+
+        let file = config_dir().and_then(|dir| File::open(dir.join(CONFIG_FILE)).ok());
+        if let Some(file) = file {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                let mut fields = line.splitn(2, '=');
+                if let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+                    config.set(key.trim(), value.trim());
+                }
+            }
+        }
+
+        config
+    }
+
+    pub fn save(&self) {
+        let file = config_dir().and_then(|dir| File::create(dir.join(CONFIG_FILE)).ok());
+        if let Some(mut file) = file {
+            for (key, value) in self.entries() {
+                let _ = writeln!(file, "{} = {}", key, value);
+            }
+        }
+    }
+
+    // Unknown keys and invalid values are ignored, keeping the defaults
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "crossfade" => {
+                if let Ok(crossfade) = value.parse::<u32>() {
+                    self.crossfade = crossfade.min(MAX_CROSSFADE);
+                }
+            }
+            "crossfade_curve" => {
+                if let Some(curve) = FadeCurve::from_name(value) {
+                    self.crossfade_curve = curve;
+                }
+            }
+            "crossfade_same_album" => {
+                if let Some(same_album) = parse_bool(value) {
+                    self.crossfade_same_album = same_album;
+                }
+            }
+            "replaygain_mode" => {
+                if let Some(mode) = GainMode::from_name(value) {
+                    self.replaygain_mode = mode;
+                }
+            }
+            "replaygain_preamp" => {
+                if let Ok(preamp) = value.parse::<f32>() {
+                    self.replaygain_preamp = preamp.clamp(-MAX_PREAMP, MAX_PREAMP);
+                }
+            }
+            "replaygain_prevent_clipping" => {
+                if let Some(prevent_clipping) = parse_bool(value) {
+                    self.replaygain_prevent_clipping = prevent_clipping;
+                }
+            }
+            "output_format" => {
+                if value == AUTOMATIC_FORMAT {
+                    self.output_format = None;
+                } else if let Some(format) = SampleFormat::from_name(value) {
+                    self.output_format = Some(format);
+                }
+            }
+            "output_rate" => {
+                if let Ok(rate) = value.parse::<u32>() {
+                    self.output_rate = rate;
+                }
+            }
+            "resampler_quality" => {
+                if let Some(quality) = ResamplerQuality::from_name(value) {
+                    self.resampler_quality = quality;
+                }
+            }
+            "speed" => {
+                if let Ok(speed) = value.parse::<f32>() {
+                    self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+                }
+            }
+            "pitch" => {
+                if let Ok(pitch) = value.parse::<f32>() {
+                    self.pitch = pitch.clamp(-MAX_PITCH, MAX_PITCH);
+                }
+            }
+            "equalizer" => {
+                if let Some(settings) = EqSettings::from_value(value) {
+                    self.equalizer = settings;
+                }
+            }
+            "equalizer_per_track" => {
+                if let Some(per_track) = parse_bool(value) {
+                    self.equalizer_per_track = per_track;
+                }
+            }
+            // "name\tsettings" and "path\tsettings", one line each
+            "equalizer_preset" => {
+                if let Some((name, settings)) = split_settings(value) {
+                    self.equalizer_presets.retain(|(preset, _)| *preset != name);
+                    self.equalizer_presets.push((name, settings));
+                }
+            }
+            "track_equalizer" => {
+                if let Some((path, settings)) = split_settings(value) {
+                    self.track_equalizers.insert(path, settings);
+                }
+            }
+            // "path\tseconds"
+            "lyrics_offset" => {
+                if let Some((path, offset)) = value.rsplit_once('\t') {
+                    if let Ok(offset) = offset.parse() {
+                        self.lyrics_offsets.insert(path.to_string(), offset);
+                    }
+                }
+            }
+            "visualizer" => {
+                if let Some(mode) = VisualizerMode::from_name(value) {
+                    self.visualizer = mode;
+                }
+            }
+            // Separated by tabs
+            "tag_patterns" => {
+                self.tag_patterns = value
+                    .split('\t')
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect();
+            }
+            "now_playing" => {
+                if let Some(now_playing) = parse_bool(value) {
+                    self.now_playing = now_playing;
+                }
+            }
+            // One line per folder
+            "library_folder" if !self.library_folders.iter().any(|folder| folder == value) => {
+                self.library_folders.push(value.to_string());
+            }
+            "show_lyrics" => {
+                if let Some(show_lyrics) = parse_bool(value) {
+                    self.show_lyrics = show_lyrics;
+                }
+            }
+            "stats_in_tags" => {
+                if let Some(stats_in_tags) = parse_bool(value) {
+                    self.stats_in_tags = stats_in_tags;
+                }
+            }
+            // "x,y"
+            "mini_player_position" => {
+                let mut coordinates = value.splitn(2, ',').map(|value| value.trim().parse());
+                if let (Some(Ok(x)), Some(Ok(y))) = (coordinates.next(), coordinates.next()) {
+                    self.mini_player_position = Some((x, y));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Settings of the track when they are chosen per track, or the global ones
+    pub fn equalizer_for(&self, path: &str) -> &EqSettings {
+        if self.equalizer_per_track {
+            if let Some(settings) = self.track_equalizers.get(path) {
+                return settings;
+            }
+        }
+        &self.equalizer
+    }
+
+    // The settings kept by file follow it when it is renamed. Returns whether there were any.
+    pub fn rename_track(&mut self, from: &str, to: &str) -> bool {
+        let equalizer = self.track_equalizers.remove(from);
+        let offset = self.lyrics_offsets.remove(from);
+        let renamed = equalizer.is_some() || offset.is_some();
+        if let Some(settings) = equalizer {
+            self.track_equalizers.insert(to.to_string(), settings);
+        }
+        if let Some(offset) = offset {
+            self.lyrics_offsets.insert(to.to_string(), offset);
+        }
+        renamed
+    }
+
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("crossfade", self.crossfade.to_string()),
+            ("crossfade_curve", self.crossfade_curve.name().to_string()),
+            (
+                "crossfade_same_album",
+                self.crossfade_same_album.to_string(),
+            ),
+            ("replaygain_mode", self.replaygain_mode.name().to_string()),
+            ("replaygain_preamp", self.replaygain_preamp.to_string()),
+            (
+                "replaygain_prevent_clipping",
+                self.replaygain_prevent_clipping.to_string(),
+            ),
+            (
+                "output_format",
+                self.output_format
+                    .map(|format| format.name())
+                    .unwrap_or(AUTOMATIC_FORMAT)
+                    .to_string(),
+            ),
+            ("output_rate", self.output_rate.to_string()),
+            (
+                "resampler_quality",
+                self.resampler_quality.name().to_string(),
+            ),
+            ("speed", self.speed.to_string()),
+            ("pitch", self.pitch.to_string()),
+            ("equalizer", self.equalizer.to_value()),
+            ("equalizer_per_track", self.equalizer_per_track.to_string()),
+            ("visualizer", self.visualizer.name().to_string()),
+            ("tag_patterns", self.tag_patterns.join("\t")),
+            ("now_playing", self.now_playing.to_string()),
+            ("show_lyrics", self.show_lyrics.to_string()),
+            ("stats_in_tags", self.stats_in_tags.to_string()),
+        ];
+        if let Some((x, y)) = self.mini_player_position {
+            entries.push(("mini_player_position", format!("{},{}", x, y)));
+        }
+        for (name, settings) in &self.equalizer_presets {
+            entries.push((
+                "equalizer_preset",
+                format!("{}\t{}", name, settings.to_value()),
+            ));
+        }
+        for folder in &self.library_folders {
+            entries.push(("library_folder", folder.clone()));
+        }
+        for (path, settings) in &self.track_equalizers {
+            entries.push((
+                "track_equalizer",
+                format!("{}\t{}", path, settings.to_value()),
+            ));
+        }
+        for (path, offset) in &self.lyrics_offsets {
+            entries.push(("lyrics_offset", format!("{}\t{}", path, offset)));
+        }
+        entries
+    }
+}
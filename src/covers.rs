@@ -0,0 +1,287 @@
+use gdk_pixbuf::{Colorspace, InterpType, Pixbuf, PixbufLoader};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use cache::stable_hash;
+use metadata::read_cover;
+
+const IMAGE_SIZE: i32 = 256;
+const THUMBNAIL_SIZE: i32 = 64;
+
+// Decoded images kept, the others are decoded again when needed
+const THUMBNAIL_CACHE_SIZE: usize = 200;
+const IMAGE_CACHE_SIZE: usize = 8;
+
+const COLORSPACE_RGB: Colorspace = 0;
+const INTERP_BILINEAR: InterpType = 2;
+
+pub fn image_key(data: &[u8]) -> u64 {
+    stable_hash(data)
+}
+
+// Scaled to fit in `size` pixels, or at its own size
+fn decode(data: &[u8], size: Option<i32>) -> Option<Pixbuf> {
+    let loader = PixbufLoader::new();
+    if let Some(size) = size {
+        loader.set_size(size, size);
+    }
+    loader.loader_write(data).ok()?;
+    loader.close().ok()?;
+    loader.get_pixbuf()
+}
+
+// Least recently used entries are dropped beyond the capacity
+struct LruCache<V> {
+    entries: HashMap<u64, V>,
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl<V: Clone> LruCache<V> {
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    fn touch(&mut self, key: u64) {
+        if let Some(position) = self.order.iter().position(|&other| other == key) {
+            self.order.remove(position);
+        }
+        self.order.push_back(key);
+    }
+
+    fn get(&mut self, key: u64) -> Option<V> {
+        let value = self.entries.get(&key)?.clone();
+        self.touch(key);
+        Some(value)
+    }
+
+    // Returns the key dropped to make room, if any
+    fn insert(&mut self, key: u64, value: V) -> Option<u64> {
+        self.entries.insert(key, value);
+        self.touch(key);
+        if self.entries.len() <= self.capacity {
+            return None;
+        }
+        let oldest = self.order.pop_front()?;
+        self.entries.remove(&oldest);
+        Some(oldest)
+    }
+}
+
+// Pixels of an image decoded by a loader thread, pixbufs cannot be sent between threads
+struct Pixels {
+    pixels: Vec<u8>,
+    has_alpha: bool,
+    width: i32,
+    height: i32,
+    rowstride: i32,
+}
+
+impl Pixels {
+    fn new(pixbuf: &Pixbuf) -> Pixels {
+        // Nothing else holds the pixbuf, the pixels are not modified while copied
+        let pixels = unsafe { pixbuf.get_pixels().to_vec() };
+        Pixels {
+            pixels,
+            has_alpha: pixbuf.get_has_alpha(),
+            width: pixbuf.get_width(),
+            height: pixbuf.get_height(),
+            rowstride: pixbuf.get_rowstride(),
+        }
+    }
+
+    fn into_pixbuf(self) -> Pixbuf {
+        Pixbuf::new_from_vec(
+            self.pixels,
+            COLORSPACE_RGB,
+            self.has_alpha,
+            8,
+            self.width,
+            self.height,
+            self.rowstride,
+        )
+    }
+}
+
+// Path, key of its cover (None when there is none or it cannot be decoded), and the
+// thumbnail unless the main thread is known to have it
+type CoverResult = (String, Option<u64>, Option<Pixels>);
+
+// Covers of the playlist rows, read and decoded by a background thread when they are
+// requested. Only a bounded number of decoded images are kept, shared by the rows with the
+// same image data, such as the tracks of an album.
+pub struct CoverLoader {
+    requests: Sender<String>,
+    results: Receiver<CoverResult>,
+    // Keys in `thumbnails`, so that the loader thread does not decode them again
+    cached: Arc<Mutex<HashSet<u64>>>,
+    thumbnails: RefCell<LruCache<Pixbuf>>,
+    images: RefCell<LruCache<Pixbuf>>,
+    // Cover key by path, None for the files without a cover
+    keys: RefCell<HashMap<String, Option<u64>>>,
+    pending: RefCell<HashSet<String>>,
+}
+
+impl CoverLoader {
+    pub fn new() -> Self {
+        let (requests, receiver) = channel::<String>();
+        let (sender, results) = channel();
+        let cached = Arc::new(Mutex::new(HashSet::new()));
+
+        let thread_cached = cached.clone();
+        thread::spawn(move || {
+            for path in receiver {
+                let data = read_cover(&path);
+                let mut key = data.as_ref().map(|data| image_key(data));
+                let mut thumbnail = None;
+                if let (Some(cover_key), Some(data)) = (key, data) {
+                    if !thread_cached.lock().unwrap().contains(&cover_key) {
+                        thumbnail = decode(&data, Some(THUMBNAIL_SIZE))
+                            .map(|thumbnail| Pixels::new(&thumbnail));
+                        // Images that cannot be decoded count as no cover, so that they are
+                        // not requested again
+                        if thumbnail.is_none() {
+                            key = None;
+                        }
+                    }
+                }
+                if sender.send((path, key, thumbnail)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        CoverLoader {
+            requests,
+            results,
+            cached,
+            thumbnails: RefCell::new(LruCache::new(THUMBNAIL_CACHE_SIZE)),
+            images: RefCell::new(LruCache::new(IMAGE_CACHE_SIZE)),
+            keys: RefCell::new(HashMap::new()),
+            pending: RefCell::new(HashSet::new()),
+        }
+    }
+
+    // Store what the loader thread sent
+    pub fn receive(&self) {
+        while let Ok((path, key, thumbnail)) = self.results.try_recv() {
+            if let (Some(key), Some(thumbnail)) = (key, thumbnail) {
+                let mut cached = self.cached.lock().unwrap();
+                let dropped = self
+                    .thumbnails
+                    .borrow_mut()
+                    .insert(key, thumbnail.into_pixbuf());
+                if let Some(dropped) = dropped {
+                    cached.remove(&dropped);
+                }
+                cached.insert(key);
+            }
+            self.pending.borrow_mut().remove(&path);
+            self.keys.borrow_mut().insert(path, key);
+        }
+    }
+
+    // The thumbnail when it is decoded. Otherwise it is requested, unless the file is known
+    // to have no cover.
+    pub fn thumbnail(&self, path: &str) -> Option<Pixbuf> {
+        let key = self.keys.borrow().get(path).cloned();
+        match key {
+            Some(None) => return None,
+            Some(Some(key)) => {
+                if let Some(thumbnail) = self.thumbnails.borrow_mut().get(key) {
+                    return Some(thumbnail);
+                }
+            }
+            None => (),
+        }
+
+        if self.pending.borrow_mut().insert(path.to_string()) {
+            let _ = self.requests.send(path.to_string());
+        }
+        None
+    }
+
+    // Full size cover, decoded right away
+    pub fn image(&self, path: &str) -> Option<Pixbuf> {
+        let data = read_cover(path)?;
+        let key = image_key(&data);
+        let mut images = self.images.borrow_mut();
+        if let Some(image) = images.get(key) {
+            return Some(image);
+        }
+        let image = decode(&data, Some(IMAGE_SIZE))?;
+        images.insert(key, image.clone());
+        Some(image)
+    }
+
+    // The cover of the file changed, it is read again when next requested
+    pub fn forget(&self, path: &str) {
+        self.keys.borrow_mut().remove(path);
+    }
+}
+
+// Cover at its own size, for the views scaling it themselves, larger ones being scaled
+// down to `max_size` pixels
+fn original_cover(path: &str, max_size: i32) -> Option<Pixbuf> {
+    let cover = decode(&read_cover(path)?, None)?;
+    let (width, height) = (cover.get_width(), cover.get_height());
+    if width <= max_size && height <= max_size {
+        return Some(cover);
+    }
+    let scale = max_size as f64 / width.max(height) as f64;
+    cover
+        .scale_simple(
+            ((width as f64 * scale).round() as i32).max(1),
+            ((height as f64 * scale).round() as i32).max(1),
+            INTERP_BILINEAR,
+        )
+        .ok()
+}
+
+// Covers at their own size, up to `max_size` pixels, decoded by a background thread that
+// skips the requests already replaced by a newer one
+pub struct OriginalCoverLoader {
+    requests: Sender<String>,
+    results: Receiver<(String, Option<Pixels>)>,
+}
+
+impl OriginalCoverLoader {
+    pub fn new(max_size: i32) -> Self {
+        let (requests, receiver) = channel::<String>();
+        let (sender, results) = channel();
+        thread::spawn(move || {
+            while let Ok(mut path) = receiver.recv() {
+                while let Ok(newer) = receiver.try_recv() {
+                    path = newer;
+                }
+                let cover = original_cover(&path, max_size).map(|cover| Pixels::new(&cover));
+                if sender.send((path, cover)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        OriginalCoverLoader { requests, results }
+    }
+
+    pub fn request(&self, path: &str) {
+        let _ = self.requests.send(path.to_string());
+    }
+
+    // The last cover decoded since the previous call, with the path it is for
+    pub fn receive(&self) -> Option<(String, Option<Pixbuf>)> {
+        let mut latest = None;
+        while let Ok(result) = self.results.try_recv() {
+            latest = Some(result);
+        }
+        latest.map(|(path, cover)| (path, cover.map(Pixels::into_pixbuf)))
+    }
+}
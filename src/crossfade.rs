@@ -0,0 +1,67 @@
+use std::f32::consts::FRAC_PI_2;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FadeCurve {
+    Linear,
+    // Constant perceived loudness for uncorrelated material
+    EqualPower,
+}
+
+impl FadeCurve {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FadeCurve::Linear => "linear",
+            FadeCurve::EqualPower => "equal-power",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FadeCurve> {
+        match name {
+            "linear" => Some(FadeCurve::Linear),
+            "equal-power" => Some(FadeCurve::EqualPower),
+            _ => None,
+        }
+    }
+
+    // Gains of the outgoing and incoming tracks, progress going from 0 to 1
+    fn gains(&self, progress: f32) -> (f32, f32) {
+        match *self {
+            FadeCurve::Linear => (1.0 - progress, progress),
+            FadeCurve::EqualPower => ((progress * FRAC_PI_2).cos(), (progress * FRAC_PI_2).sin()),
+        }
+    }
+}
+
+// Mixes the tail of the current track with the head of the next one
+pub struct Crossfade {
+    curve: FadeCurve,
+    length: usize,
+    position: usize,
+}
+
+impl Crossfade {
+    // length in samples per channel
+    pub fn new(length: usize, curve: FadeCurve) -> Self {
+        Crossfade {
+            curve,
+            length,
+            position: 0,
+        }
+    }
+
+    // Mix `next` into `current`, both holding stereo samples
+    pub fn mix(&mut self, current: &mut [[f32; 2]], next: &[[f32; 2]]) {
+        for (current, next) in current.iter_mut().zip(next) {
+            let progress = if self.position < self.length {
+                self.position as f32 / self.length as f32
+            } else {
+                1.0
+            };
+            let (gain_out, gain_in) = self.curve.gains(progress);
+            for channel in 0..2 {
+                current[channel] = current[channel] * gain_out + next[channel] * gain_in;
+            }
+            self.position += 1;
+        }
+    }
+}
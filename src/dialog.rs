@@ -0,0 +1,28 @@
+use gtk::{
+    CellLayoutExt, CellRendererText, ContainerExt, IsA, PolicyType, ScrolledWindow,
+    ScrolledWindowExt, TreeView, TreeViewColumn, TreeViewColumnExt, TreeViewExt, Widget,
+};
+
+use gtk_sys::{GTK_RESPONSE_ACCEPT, GTK_RESPONSE_APPLY, GTK_RESPONSE_CANCEL};
+
+pub const RESPONSE_ACCEPT: i32 = GTK_RESPONSE_ACCEPT;
+pub const RESPONSE_APPLY: i32 = GTK_RESPONSE_APPLY;
+pub const RESPONSE_CANCEL: i32 = GTK_RESPONSE_CANCEL;
+
+// Text column sharing the width of the view with the others
+pub fn add_column(view: &TreeView, title: &str, column: u32) {
+    let view_column = TreeViewColumn::new();
+    view_column.set_title(title);
+    view_column.set_expand(true);
+    let cell = CellRendererText::new();
+    view_column.pack_start(&cell, true);
+    view_column.add_attribute(&cell, "text", column as i32);
+    view.append_column(&view_column);
+}
+
+pub fn scrolled<W: IsA<Widget>>(widget: &W) -> ScrolledWindow {
+    let window = ScrolledWindow::new(None, None);
+    window.set_policy(PolicyType::Automatic, PolicyType::Automatic);
+    window.add(widget);
+    window
+}
@@ -0,0 +1,338 @@
+use std::f64::consts::PI;
+
+pub const BANDS: usize = 10;
+pub const FREQUENCIES: [f32; BANDS] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+pub const MAX_GAIN: f32 = 12.0;
+// One octave wide bands
+const GRAPHIC_Q: f32 = 1.41;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+impl FilterKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FilterKind::Peaking => "peaking",
+            FilterKind::LowShelf => "low-shelf",
+            FilterKind::HighShelf => "high-shelf",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "peaking" => Some(FilterKind::Peaking),
+            "low-shelf" => Some(FilterKind::LowShelf),
+            "high-shelf" => Some(FilterKind::HighShelf),
+            _ => None,
+        }
+    }
+}
+
+// Parametric band: frequency in Hz, gain in dB
+#[derive(Clone, Copy, PartialEq)]
+pub struct Band {
+    pub kind: FilterKind,
+    pub frequency: f32,
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl Band {
+    pub fn new() -> Self {
+        Band {
+            kind: FilterKind::Peaking,
+            frequency: 1000.0,
+            gain: 0.0,
+            q: 1.0,
+        }
+    }
+
+    // "kind:frequency:gain:q"
+    fn parse(value: &str) -> Option<Band> {
+        let mut fields = value.split(':');
+        let kind = FilterKind::from_name(fields.next()?)?;
+        let frequency = fields.next()?.parse::<f32>().ok()?;
+        let gain = fields.next()?.parse::<f32>().ok()?;
+        let q = fields.next()?.parse::<f32>().ok()?;
+        if frequency <= 0.0 || q <= 0.0 {
+            return None;
+        }
+        Some(Band {
+            kind,
+            frequency,
+            gain: gain.clamp(-MAX_GAIN, MAX_GAIN),
+            q,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct EqSettings {
+    pub enabled: bool,
+    // dB applied before the filters, to leave room for the boosts
+    pub preamp: f32,
+    pub graphic: [f32; BANDS],
+    pub parametric: Vec<Band>,
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        EqSettings {
+            enabled: false,
+            preamp: 0.0,
+            graphic: [0.0; BANDS],
+            parametric: Vec::new(),
+        }
+    }
+}
+
+impl EqSettings {
+    pub fn from_graphic(graphic: [f32; BANDS]) -> Self {
+        EqSettings {
+            enabled: true,
+            graphic,
+            ..EqSettings::default()
+        }
+    }
+
+    // Stored on a single line: "enabled preamp gains bands", the gains separated by commas
+    // and the parametric bands by semicolons
+    pub fn to_value(&self) -> String {
+        let graphic: Vec<String> = self.graphic.iter().map(|gain| gain.to_string()).collect();
+        let parametric: Vec<String> = self
+            .parametric
+            .iter()
+            .map(|band| {
+                format!(
+                    "{}:{}:{}:{}",
+                    band.kind.name(),
+                    band.frequency,
+                    band.gain,
+                    band.q
+                )
+            })
+            .collect();
+        format!(
+            "{} {} {} {}",
+            self.enabled,
+            self.preamp,
+            graphic.join(","),
+            parametric.join(";")
+        )
+    }
+
+    pub fn from_value(value: &str) -> Option<EqSettings> {
+        let mut fields = value.split_whitespace();
+        let enabled = match fields.next()? {
+            "true" => true,
+            "false" => false,
+            _ => return None,
+        };
+        let preamp = fields.next()?.parse::<f32>().ok()?;
+
+        let mut graphic = [0.0; BANDS];
+        let gains: Vec<f32> = fields
+            .next()?
+            .split(',')
+            .filter_map(|gain| gain.parse().ok())
+            .collect();
+        if gains.len() != BANDS {
+            return None;
+        }
+        for (band, gain) in graphic.iter_mut().zip(gains) {
+            *band = gain.clamp(-MAX_GAIN, MAX_GAIN);
+        }
+
+        let parametric = fields
+            .next()
+            .map(|bands| bands.split(';').filter_map(Band::parse).collect())
+            .unwrap_or_default();
+
+        Some(EqSettings {
+            enabled,
+            preamp: preamp.clamp(-MAX_GAIN, MAX_GAIN),
+            graphic,
+            parametric,
+        })
+    }
+
+    fn bands(&self) -> Vec<Band> {
+        let graphic = FREQUENCIES
+            .iter()
+            .zip(self.graphic.iter())
+            .map(|(&frequency, &gain)| Band {
+                kind: FilterKind::Peaking,
+                frequency,
+                gain,
+                q: GRAPHIC_Q,
+            });
+        graphic.chain(self.parametric.iter().cloned()).collect()
+    }
+}
+
+pub fn builtin_presets() -> Vec<(&'static str, [f32; BANDS])> {
+    vec![
+        ("Flat", [0.0; BANDS]),
+        ("Rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+        ("Pop", [-1.0, 0.0, 2.0, 4.0, 5.0, 4.0, 2.0, 0.0, -1.0, -1.0]),
+        ("Jazz", [4.0, 3.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 3.0, 4.0]),
+        (
+            "Classical",
+            [5.0, 4.0, 3.0, 2.0, -1.0, -1.0, 0.0, 2.0, 3.0, 4.0],
+        ),
+        (
+            "Bass boost",
+            [7.0, 6.0, 5.0, 3.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        ),
+        (
+            "Treble boost",
+            [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 3.0, 5.0, 6.0, 7.0],
+        ),
+        (
+            "Vocal",
+            [-2.0, -3.0, -3.0, 1.0, 4.0, 4.0, 3.0, 1.0, 0.0, -2.0],
+        ),
+    ]
+}
+
+// Second order section on both channels, with the cookbook formulas of Robert
+// Bristow-Johnson
+struct Filter {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [[f64; 2]; 2],
+    // Bands without gain or above the Nyquist frequency are left out
+    active: bool,
+}
+
+impl Filter {
+    fn new(band: &Band, rate: u32) -> Self {
+        let mut filter = Filter {
+            b: [1.0, 0.0, 0.0],
+            a: [0.0, 0.0],
+            z: [[0.0; 2]; 2],
+            active: false,
+        };
+        filter.set(band, rate);
+        filter
+    }
+
+    // The state is kept, so that changing a band while playing does not click, but cleared
+    // when the band comes back after being left out
+    fn set(&mut self, band: &Band, rate: u32) {
+        let active = band.gain != 0.0 && band.frequency < rate as f32 * 0.45;
+        if active && !self.active {
+            self.z = [[0.0; 2]; 2];
+        }
+        self.active = active;
+        if !active {
+            return;
+        }
+
+        let a = 10f64.powf(band.gain as f64 / 40.0);
+        let w0 = 2.0 * PI * band.frequency as f64 / rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q as f64);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b, a) = match band.kind {
+            FilterKind::Peaking => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            FilterKind::LowShelf => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + shelf,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - shelf,
+                ],
+            ),
+            FilterKind::HighShelf => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + shelf,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - shelf,
+                ],
+            ),
+        };
+
+        self.b = [b[0] / a[0], b[1] / a[0], b[2] / a[0]];
+        self.a = [a[1] / a[0], a[2] / a[0]];
+    }
+
+    fn process(&mut self, sample: &mut [f32; 2]) {
+        for (channel, z) in sample.iter_mut().zip(self.z.iter_mut()) {
+            let input = *channel as f64;
+            let output = self.b[0] * input + z[0];
+            z[0] = self.b[1] * input - self.a[0] * output + z[1];
+            z[1] = self.b[2] * input - self.a[1] * output;
+            *channel = output as f32;
+        }
+    }
+}
+
+// A filter for each band, updated when the settings or the rate change
+pub struct Equalizer {
+    settings: EqSettings,
+    rate: u32,
+    preamp: f32,
+    filters: Vec<Filter>,
+}
+
+impl Equalizer {
+    pub fn new() -> Self {
+        Equalizer {
+            settings: EqSettings::default(),
+            rate: 0,
+            preamp: 1.0,
+            filters: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, settings: &EqSettings, rate: u32) {
+        if *settings == self.settings && rate == self.rate {
+            return;
+        }
+        self.settings = settings.clone();
+        self.rate = rate;
+        self.preamp = 10f32.powf(settings.preamp / 20.0);
+        let bands = settings.bands();
+        self.filters.truncate(bands.len());
+        for (index, band) in bands.iter().enumerate() {
+            match self.filters.get_mut(index) {
+                Some(filter) => filter.set(band, rate),
+                None => self.filters.push(Filter::new(band, rate)),
+            }
+        }
+    }
+
+    pub fn process(&mut self, settings: &EqSettings, rate: u32, buffer: &mut [[f32; 2]]) {
+        if !settings.enabled {
+            return;
+        }
+        self.update(settings, rate);
+        for sample in buffer.iter_mut() {
+            sample[0] *= self.preamp;
+            sample[1] *= self.preamp;
+            for filter in self.filters.iter_mut().filter(|filter| filter.active) {
+                filter.process(sample);
+            }
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use gtk::{
+    ApplicationWindow, BoxExt, Button, ButtonExt, CheckButton, ComboBoxExt, ComboBoxText,
+    ComboBoxTextExt, ContainerExt, Entry, EntryExt, Grid, GridExt, GtkWindowExt, Label, LabelExt,
+    Orientation, RangeExt, Scale, ScaleExt, SpinButton, SpinButtonExt, SpinButtonSignals,
+    ToggleButtonExt, WidgetExt, Window, WindowType,
+};
+
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use config::Config;
+use equalizer::{builtin_presets, Band, EqSettings, FilterKind, BANDS, FREQUENCIES, MAX_GAIN};
+use playlist::Playlist;
+
+const BUILTIN_PREFIX: &str = "builtin:";
+const USER_PREFIX: &str = "user:";
+
+struct BandRow {
+    kind: ComboBoxText,
+    frequency: SpinButton,
+    gain: SpinButton,
+    q: SpinButton,
+}
+
+// Every change is written to the configuration right away, where the player picks it up
+// for the next buffer
+struct EqualizerWindow {
+    config: Arc<Mutex<Config>>,
+    playlist: Rc<Playlist>,
+    // Playing track the settings shown were read for, followed when they are applied
+    track: RefCell<Option<String>>,
+    target: Label,
+    enabled: CheckButton,
+    per_track: CheckButton,
+    preamp: Scale,
+    graphic: Vec<Scale>,
+    bands_grid: Grid,
+    bands: RefCell<Vec<BandRow>>,
+    presets: ComboBoxText,
+    preset_name: Entry,
+    // Set while the widgets are filled from the settings, so that they are not applied back
+    loading: Cell<bool>,
+}
+
+fn gain_scale(orientation: Orientation) -> Scale {
+    let scale = Scale::new_with_range(orientation, -MAX_GAIN as f64, MAX_GAIN as f64, 0.5);
+    scale.set_digits(1);
+    scale.set_value(0.0);
+    scale
+}
+
+fn frequency_label(frequency: f32) -> String {
+    if frequency >= 1000.0 {
+        format!("{}k", frequency / 1000.0)
+    } else {
+        format!("{}", frequency)
+    }
+}
+
+pub fn show_equalizer_window(
+    parent: &ApplicationWindow,
+    config: &Arc<Mutex<Config>>,
+    playlist: &Rc<Playlist>,
+) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Equalizer");
+    window.set_transient_for(Some(parent));
+    window.set_destroy_with_parent(true);
+
+    let vbox = gtk::Box::new(Orientation::Vertical, 6);
+    vbox.set_border_width(10);
+    window.add(&vbox);
+
+    let header = gtk::Box::new(Orientation::Horizontal, 6);
+    let enabled = CheckButton::new_with_label("Enabled");
+    header.pack_start(&enabled, false, false, 0);
+    let per_track = CheckButton::new_with_label("Settings per track");
+    header.pack_start(&per_track, false, false, 0);
+    let target = Label::new(None);
+    header.pack_end(&target, false, false, 0);
+    vbox.pack_start(&header, false, false, 0);
+
+    let preset_box = gtk::Box::new(Orientation::Horizontal, 6);
+    let presets = ComboBoxText::new();
+    preset_box.pack_start(&presets, true, true, 0);
+    let preset_name = Entry::new();
+    preset_name.set_placeholder_text("Preset name");
+    preset_box.pack_start(&preset_name, false, false, 0);
+    let save_preset = Button::new_with_label("Save preset");
+    preset_box.pack_start(&save_preset, false, false, 0);
+    let delete_preset = Button::new_with_label("Delete preset");
+    preset_box.pack_start(&delete_preset, false, false, 0);
+    vbox.pack_start(&preset_box, false, false, 0);
+
+    let graphic_grid = Grid::new();
+    graphic_grid.set_column_spacing(6);
+    graphic_grid.set_column_homogeneous(true);
+    let preamp = gain_scale(Orientation::Vertical);
+    let mut graphic = Vec::with_capacity(BANDS);
+    for (column, &frequency) in FREQUENCIES.iter().enumerate() {
+        let scale = gain_scale(Orientation::Vertical);
+        scale.set_inverted(true);
+        scale.set_size_request(-1, 160);
+        graphic_grid.attach(&scale, column as i32, 0, 1, 1);
+        graphic_grid.attach(
+            &Label::new(frequency_label(frequency).as_str()),
+            column as i32,
+            1,
+            1,
+            1,
+        );
+        graphic.push(scale);
+    }
+    preamp.set_inverted(true);
+    graphic_grid.attach(&preamp, BANDS as i32, 0, 1, 1);
+    graphic_grid.attach(&Label::new("Preamp"), BANDS as i32, 1, 1, 1);
+    vbox.pack_start(&graphic_grid, true, true, 0);
+
+    vbox.pack_start(&Label::new("Parametric bands"), false, false, 0);
+    let bands_grid = Grid::new();
+    bands_grid.set_row_spacing(6);
+    bands_grid.set_column_spacing(6);
+    vbox.pack_start(&bands_grid, false, false, 0);
+    let band_buttons = gtk::Box::new(Orientation::Horizontal, 6);
+    let add_band = Button::new_with_label("Add band");
+    band_buttons.pack_start(&add_band, false, false, 0);
+    let forget_track = Button::new_with_label("Forget track settings");
+    band_buttons.pack_end(&forget_track, false, false, 0);
+    vbox.pack_start(&band_buttons, false, false, 0);
+
+    let equalizer = Rc::new(EqualizerWindow {
+        config: config.clone(),
+        playlist: playlist.clone(),
+        track: RefCell::new(None),
+        target,
+        enabled,
+        per_track,
+        preamp,
+        graphic,
+        bands_grid,
+        bands: RefCell::new(Vec::new()),
+        presets,
+        preset_name,
+        loading: Cell::new(false),
+    });
+
+    equalizer.fill_presets();
+    equalizer.reload();
+    equalizer.connect_events(&add_band, &save_preset, &delete_preset, &forget_track);
+
+    let config = config.clone();
+    window.connect_destroy(move |_| {
+        config.lock().unwrap().save();
+    });
+
+    window.show_all();
+}
+
+impl EqualizerWindow {
+    fn connect_events(
+        self: &Rc<Self>,
+        add_band: &Button,
+        save_preset: &Button,
+        delete_preset: &Button,
+        forget_track: &Button,
+    ) {
+        let equalizer = self.clone();
+        self.enabled.connect_toggled(move |_| equalizer.apply());
+
+        let equalizer = self.clone();
+        self.preamp
+            .connect_value_changed(move |_| equalizer.apply());
+
+        for scale in &self.graphic {
+            let equalizer = self.clone();
+            scale.connect_value_changed(move |_| equalizer.apply());
+        }
+
+        let equalizer = self.clone();
+        self.per_track.connect_toggled(move |per_track| {
+            if !equalizer.loading.get() {
+                equalizer.config.lock().unwrap().equalizer_per_track = per_track.get_active();
+                equalizer.reload();
+            }
+        });
+
+        let equalizer = self.clone();
+        self.presets.connect_changed(move |presets| {
+            if equalizer.loading.get() {
+                return;
+            }
+            if let Some(settings) = presets.get_active_id().and_then(|id| equalizer.preset(&id)) {
+                equalizer.load(&settings);
+                equalizer.apply();
+            }
+        });
+
+        let equalizer = self.clone();
+        add_band.connect_clicked(move |_| {
+            let mut settings = equalizer.settings();
+            settings.parametric.push(Band::new());
+            equalizer.load(&settings);
+            equalizer.apply();
+        });
+
+        let equalizer = self.clone();
+        save_preset.connect_clicked(move |_| {
+            let name = equalizer.preset_name.get_text().unwrap_or_default();
+            let name = name.trim();
+            if name.is_empty() {
+                return;
+            }
+            let settings = equalizer.settings();
+            {
+                let mut config = equalizer.config.lock().unwrap();
+                config
+                    .equalizer_presets
+                    .retain(|(preset, _)| preset != name);
+                config.equalizer_presets.push((name.to_string(), settings));
+                config.save();
+            }
+            equalizer.fill_presets();
+            equalizer.loading.set(true);
+            equalizer
+                .presets
+                .set_active_id(format!("{}{}", USER_PREFIX, name).as_str());
+            equalizer.loading.set(false);
+        });
+
+        let equalizer = self.clone();
+        delete_preset.connect_clicked(move |_| {
+            let active = equalizer.presets.get_active_id().unwrap_or_default();
+            if let Some(name) = active.strip_prefix(USER_PREFIX) {
+                let mut config = equalizer.config.lock().unwrap();
+                config
+                    .equalizer_presets
+                    .retain(|(preset, _)| preset != name);
+                config.save();
+            }
+            equalizer.fill_presets();
+        });
+
+        let equalizer = self.clone();
+        forget_track.connect_clicked(move |_| {
+            if let Some(track) = equalizer.playlist.path() {
+                equalizer
+                    .config
+                    .lock()
+                    .unwrap()
+                    .track_equalizers
+                    .remove(&track);
+                equalizer.reload();
+            }
+        });
+    }
+
+    fn fill_presets(&self) {
+        self.loading.set(true);
+        self.presets.remove_all();
+        for (name, _) in builtin_presets() {
+            self.presets
+                .append(format!("{}{}", BUILTIN_PREFIX, name).as_str(), name);
+        }
+        for (name, _) in &self.config.lock().unwrap().equalizer_presets {
+            self.presets
+                .append(format!("{}{}", USER_PREFIX, name).as_str(), name);
+        }
+        self.loading.set(false);
+    }
+
+    fn preset(&self, id: &str) -> Option<EqSettings> {
+        if let Some(name) = id.strip_prefix(BUILTIN_PREFIX) {
+            let preamp = self.preamp.get_value() as f32;
+            builtin_presets()
+                .into_iter()
+                .find(|(preset, _)| *preset == name)
+                .map(|(_, graphic)| EqSettings {
+                    preamp,
+                    ..EqSettings::from_graphic(graphic)
+                })
+        } else {
+            let name = id.strip_prefix(USER_PREFIX)?;
+            let config = self.config.lock().unwrap();
+            config
+                .equalizer_presets
+                .iter()
+                .find(|(preset, _)| preset == name)
+                .map(|(_, settings)| EqSettings {
+                    enabled: true,
+                    ..settings.clone()
+                })
+        }
+    }
+
+    // Track being edited, when the settings are per track and a track is playing
+    fn edited_track(&self) -> Option<String> {
+        if self.per_track.get_active() {
+            self.track.borrow().clone()
+        } else {
+            None
+        }
+    }
+
+    fn reload(self: &Rc<Self>) {
+        let track = self.playlist.path();
+        let (settings, per_track) = {
+            let config = self.config.lock().unwrap();
+            let settings = match track {
+                Some(ref track) => config.equalizer_for(track).clone(),
+                None => config.equalizer.clone(),
+            };
+            (settings, config.equalizer_per_track)
+        };
+        *self.track.borrow_mut() = track;
+        self.loading.set(true);
+        self.per_track.set_active(per_track);
+        self.loading.set(false);
+        self.load(&settings);
+        self.show_target();
+    }
+
+    fn show_target(&self) {
+        let target = match self.edited_track() {
+            Some(track) => {
+                let name = Path::new(&track)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                format!("Editing {}", name)
+            }
+            None => "Editing all tracks".to_string(),
+        };
+        self.target.set_text(&target);
+    }
+
+    fn load(self: &Rc<Self>, settings: &EqSettings) {
+        self.loading.set(true);
+        self.enabled.set_active(settings.enabled);
+        self.preamp.set_value(settings.preamp as f64);
+        for (scale, &gain) in self.graphic.iter().zip(settings.graphic.iter()) {
+            scale.set_value(gain as f64);
+        }
+        self.loading.set(false);
+
+        for child in self.bands_grid.get_children() {
+            self.bands_grid.remove(&child);
+        }
+        self.bands.borrow_mut().clear();
+        for (index, band) in settings.parametric.iter().enumerate() {
+            self.add_band_row(index, band);
+        }
+        self.bands_grid.show_all();
+    }
+
+    fn add_band_row(self: &Rc<Self>, index: usize, band: &Band) {
+        let row = index as i32;
+
+        let kind = ComboBoxText::new();
+        for filter in &[
+            FilterKind::Peaking,
+            FilterKind::LowShelf,
+            FilterKind::HighShelf,
+        ] {
+            kind.append(filter.name(), filter.name());
+        }
+        kind.set_active_id(band.kind.name());
+        self.bands_grid.attach(&kind, 0, row, 1, 1);
+
+        let frequency = SpinButton::new_with_range(20.0, 20000.0, 10.0);
+        frequency.set_value(band.frequency as f64);
+        self.bands_grid.attach(&Label::new("Hz"), 2, row, 1, 1);
+        self.bands_grid.attach(&frequency, 1, row, 1, 1);
+
+        let gain = SpinButton::new_with_range(-MAX_GAIN as f64, MAX_GAIN as f64, 0.5);
+        gain.set_digits(1);
+        gain.set_value(band.gain as f64);
+        self.bands_grid.attach(&gain, 3, row, 1, 1);
+        self.bands_grid.attach(&Label::new("dB"), 4, row, 1, 1);
+
+        let q = SpinButton::new_with_range(0.1, 10.0, 0.1);
+        q.set_digits(2);
+        q.set_value(band.q as f64);
+        self.bands_grid.attach(&Label::new("Q"), 5, row, 1, 1);
+        self.bands_grid.attach(&q, 6, row, 1, 1);
+
+        let remove = Button::new_with_label("Remove");
+        self.bands_grid.attach(&remove, 7, row, 1, 1);
+
+        let equalizer = self.clone();
+        kind.connect_changed(move |_| equalizer.apply());
+        for spin in &[&frequency, &gain, &q] {
+            let equalizer = self.clone();
+            spin.connect_value_changed(move |_| equalizer.apply());
+        }
+        let equalizer = self.clone();
+        remove.connect_clicked(move |_| {
+            let mut settings = equalizer.settings();
+            settings.parametric.remove(index);
+            equalizer.load(&settings);
+            equalizer.apply();
+        });
+
+        self.bands.borrow_mut().push(BandRow {
+            kind,
+            frequency,
+            gain,
+            q,
+        });
+    }
+
+    fn settings(&self) -> EqSettings {
+        let mut graphic = [0.0; BANDS];
+        for (gain, scale) in graphic.iter_mut().zip(self.graphic.iter()) {
+            *gain = scale.get_value() as f32;
+        }
+        let parametric = self
+            .bands
+            .borrow()
+            .iter()
+            .map(|row| Band {
+                kind: row
+                    .kind
+                    .get_active_id()
+                    .and_then(|id| FilterKind::from_name(&id))
+                    .unwrap_or(FilterKind::Peaking),
+                frequency: row.frequency.get_value() as f32,
+                gain: row.gain.get_value() as f32,
+                q: row.q.get_value() as f32,
+            })
+            .collect();
+
+        EqSettings {
+            enabled: self.enabled.get_active(),
+            preamp: self.preamp.get_value() as f32,
+            graphic,
+            parametric,
+        }
+    }
+
+    fn apply(&self) {
+        if self.loading.get() {
+            return;
+        }
+        // The shown settings go to the track playing now, if it changed since they were read
+        let track = self.playlist.path();
+        if *self.track.borrow() != track {
+            *self.track.borrow_mut() = track;
+            self.show_target();
+        }
+        let settings = self.settings();
+        let mut config = self.config.lock().unwrap();
+        match self.edited_track() {
+            Some(track) => {
+                config.track_equalizers.insert(track.to_string(), settings);
+            }
+            None => config.equalizer = settings,
+        }
+    }
+}
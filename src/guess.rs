@@ -0,0 +1,217 @@
+use std::path::Path;
+
+use metadata::Metadata;
+use tag_editor::{Field, FIELDS};
+
+// Matches anything, without keeping it
+const IGNORE_KEY: &str = "ignore";
+
+enum Token {
+    Text(String),
+    // None for %ignore%
+    Field(Option<Field>),
+}
+
+// A pattern such as "%artist% - %album%/%track% - %title%", matched against the end of the
+// path without the extension: one component per slash of the pattern
+pub struct Pattern {
+    tokens: Vec<Token>,
+    components: usize,
+}
+
+impl Pattern {
+    // None for an unknown field or a % left open
+    pub fn parse(pattern: &str) -> Option<Pattern> {
+        let mut tokens = Vec::new();
+        let mut rest = pattern.trim();
+        while !rest.is_empty() {
+            if let Some(key) = rest.strip_prefix('%') {
+                let end = key.find('%')?;
+                let key = &key[..end];
+                let field = if key == IGNORE_KEY {
+                    None
+                } else {
+                    Some(*FIELDS.iter().find(|field| field.key() == key)?)
+                };
+                tokens.push(Token::Field(field));
+                rest = &rest[end + 2..];
+            } else {
+                let end = rest.find('%').unwrap_or(rest.len());
+                tokens.push(Token::Text(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        if tokens.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            tokens,
+            components: pattern.trim().matches('/').count() + 1,
+        })
+    }
+
+    pub fn guess(&self, path: &Path) -> Option<Vec<(Field, String)>> {
+        let stem = path.file_stem()?.to_string_lossy().to_string();
+        let mut components: Vec<String> = path
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        components.push(stem);
+        if components.len() < self.components {
+            return None;
+        }
+        let text = components[components.len() - self.components..].join("/");
+
+        let mut values = Vec::new();
+        if match_tokens(&self.tokens, &text, &mut values) {
+            Some(values)
+        } else {
+            None
+        }
+    }
+}
+
+// Each field takes the shortest value letting the rest of the pattern match. Values never
+// span several components, and numeric fields only hold digits.
+fn match_tokens(tokens: &[Token], text: &str, values: &mut Vec<(Field, String)>) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return text.is_empty(),
+    };
+
+    match *token {
+        Token::Text(ref literal) => {
+            text.starts_with(literal.as_str()) && match_tokens(rest, &text[literal.len()..], values)
+        }
+        Token::Field(field) => {
+            let ends = text
+                .char_indices()
+                .skip(1)
+                .map(|(end, _)| end)
+                .chain(Some(text.len()).filter(|&length| length > 0));
+            for end in ends {
+                let value = &text[..end];
+                if value.contains('/') {
+                    return false;
+                }
+                if field.is_some_and(|field| field.is_number())
+                    && !value.trim().chars().all(|c| c.is_ascii_digit())
+                {
+                    return false;
+                }
+
+                let count = values.len();
+                if let Some(field) = field {
+                    values.push((field, value.trim().to_string()));
+                }
+                if match_tokens(rest, &text[end..], values) {
+                    return true;
+                }
+                values.truncate(count);
+            }
+            false
+        }
+    }
+}
+
+// Fields from the first of the patterns matching the path, empty values left out
+pub fn guess_fields(patterns: &[String], path: &Path) -> Option<Vec<(Field, String)>> {
+    patterns
+        .iter()
+        .filter_map(|pattern| Pattern::parse(pattern))
+        .find_map(|pattern| pattern.guess(path))
+        .map(|values| {
+            values
+                .into_iter()
+                .filter(|(_, value)| !value.is_empty())
+                .collect()
+        })
+}
+
+pub fn apply_fields(fields: &[(Field, String)], metadata: &mut Metadata) {
+    for (field, value) in fields {
+        let number = value.parse().ok();
+        match *field {
+            Field::Title => metadata.title = value.clone(),
+            Field::Artist => metadata.artist = value.clone(),
+            Field::Album => metadata.album = value.clone(),
+            Field::AlbumArtist => metadata.album_artist = value.clone(),
+            Field::Genre => metadata.genre = value.clone(),
+            Field::Comment => metadata.comment = value.clone(),
+            Field::Year => metadata.year = value.parse().ok(),
+            Field::Track => metadata.track = number,
+            Field::TotalTracks => metadata.total_tracks = number,
+            Field::Disc => metadata.disc = number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_tokens, Pattern};
+
+    fn matched(pattern: &str, text: &str) -> Option<Vec<(&'static str, String)>> {
+        let pattern = Pattern::parse(pattern).unwrap();
+        let mut values = Vec::new();
+        if match_tokens(&pattern.tokens, text, &mut values) {
+            Some(
+                values
+                    .into_iter()
+                    .map(|(field, value)| (field.key(), value))
+                    .collect(),
+            )
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn adjacent_fields_split_by_digits() {
+        assert_eq!(
+            matched("%title%%track%", "Song 03"),
+            Some(vec![
+                ("title", "Song".to_string()),
+                ("track", "03".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn adjacent_fields_take_the_shortest_value() {
+        assert_eq!(
+            matched("%artist%%title%", "AB"),
+            Some(vec![
+                ("artist", "A".to_string()),
+                ("title", "B".to_string())
+            ])
+        );
+        assert_eq!(matched("%artist%%title%", "A"), None);
+    }
+
+    #[test]
+    fn fields_stay_in_their_component() {
+        assert_eq!(
+            matched("%album%/%track% - %title%", "Album/01 - A - B"),
+            Some(vec![
+                ("album", "Album".to_string()),
+                ("track", "01".to_string()),
+                ("title", "A - B".to_string())
+            ])
+        );
+        assert_eq!(matched("%album% - %title%", "Album/01 - Title"), None);
+    }
+
+    #[test]
+    fn ignored_field() {
+        assert_eq!(
+            matched("%ignore% - %title%", "x - y - Title"),
+            Some(vec![("title", "y - Title".to_string())])
+        );
+    }
+}
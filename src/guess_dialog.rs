@@ -0,0 +1,225 @@
+use gtk::{
+    ApplicationWindow, CheckButton, ContainerExt, Dialog, DialogExt, DialogFlags, GtkWindowExt,
+    Label, LabelExt, ListStore, ListStoreExt, ListStoreExtManual, Orientation, PolicyType,
+    ScrolledWindow, ScrolledWindowExt, TextBufferExt, TextView, TextViewExt, ToValue,
+    ToggleButtonExt, TreeView, Type, WidgetExt,
+};
+
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use config::Config;
+use dialog::{add_column, RESPONSE_ACCEPT, RESPONSE_APPLY, RESPONSE_CANCEL};
+use guess::{guess_fields, Pattern};
+use metadata::Metadata;
+use tag_editor::{show_error, write_fields, Field, FIELDS};
+
+// File name and status, then a column per field
+const STATUS_COLUMN: u32 = 1;
+const FIRST_FIELD_COLUMN: u32 = 2;
+
+// Lines of the text view, blank ones left out
+fn text_patterns(view: &TextView) -> Vec<String> {
+    let buffer = match view.get_buffer() {
+        Some(buffer) => buffer,
+        None => return Vec::new(),
+    };
+    let (start, end) = buffer.get_bounds();
+    buffer
+        .get_text(&start, &end, false)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+struct GuessPreview {
+    // Path and whether the file already has tags
+    files: Vec<(String, bool)>,
+    model: ListStore,
+    error_label: Label,
+}
+
+impl GuessPreview {
+    fn update(&self, patterns: &[String], only_untagged: bool) {
+        let invalid = patterns
+            .iter()
+            .position(|pattern| Pattern::parse(pattern).is_none());
+        match invalid {
+            Some(line) => self.error_label.set_text(&format!(
+                "Line {}: unknown field or unclosed %, the pattern is ignored",
+                line + 1
+            )),
+            None => self.error_label.set_text(""),
+        }
+
+        self.model.clear();
+        for (path, tagged) in &self.files {
+            let row = self.model.append();
+            let name = Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            self.model.set_value(&row, 0, &name.to_value());
+
+            let fields = guess_fields(patterns, Path::new(path));
+            let status = match fields {
+                None => "No match",
+                Some(_) if *tagged && only_untagged => "Has tags, kept",
+                Some(_) => "",
+            };
+            self.model
+                .set_value(&row, STATUS_COLUMN, &status.to_value());
+            for (field, value) in fields.unwrap_or_default() {
+                if let Some(index) = field_index(field) {
+                    self.model
+                        .set_value(&row, FIRST_FIELD_COLUMN + index, &value.to_value());
+                }
+            }
+        }
+    }
+}
+
+fn field_index(field: Field) -> Option<u32> {
+    FIELDS
+        .iter()
+        .position(|&other| other == field)
+        .map(|index| index as u32)
+}
+
+// Edit the patterns used for untagged files, with a preview of what they give for `paths`,
+// and optionally write the guesses as tags. Returns whether the playlist should be refreshed.
+pub fn show_guess_dialog(
+    parent: &ApplicationWindow,
+    config: &Arc<Mutex<Config>>,
+    paths: &[String],
+) -> bool {
+    let dialog = Dialog::new_with_buttons(
+        Some("Guess tags from file names"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("Cancel", RESPONSE_CANCEL),
+            ("Save patterns", RESPONSE_APPLY),
+            ("Write tags", RESPONSE_ACCEPT),
+        ],
+    );
+    dialog.set_default_size(700, 500);
+
+    let vbox = gtk::Box::new(Orientation::Vertical, 6);
+    vbox.set_border_width(10);
+    dialog.get_content_area().add(&vbox);
+
+    let keys: Vec<String> = FIELDS
+        .iter()
+        .map(|field| format!("%{}%", field.key()))
+        .collect();
+    let help = Label::new(
+        format!(
+            "One pattern per line, the first one matching the end of the path is used.\n\
+             Fields: {} %ignore%",
+            keys.join(" ")
+        )
+        .as_str(),
+    );
+    help.set_line_wrap(true);
+    vbox.add(&help);
+
+    let patterns_view = TextView::new();
+    if let Some(buffer) = patterns_view.get_buffer() {
+        buffer.set_text(&config.lock().unwrap().tag_patterns.join("\n"));
+    }
+    let patterns_window = ScrolledWindow::new(None, None);
+    patterns_window.set_policy(PolicyType::Automatic, PolicyType::Automatic);
+    patterns_window.set_min_content_height(100);
+    patterns_window.add(&patterns_view);
+    vbox.add(&patterns_window);
+
+    let error_label = Label::new(None);
+    vbox.add(&error_label);
+
+    let only_untagged = CheckButton::new_with_label("Only write to files without tags");
+    only_untagged.set_active(true);
+    vbox.add(&only_untagged);
+
+    let types = vec![Type::String; FIELDS.len() + FIRST_FIELD_COLUMN as usize];
+    let model = ListStore::new(&types);
+    let preview_view = TreeView::new_with_model(&model);
+    add_column(&preview_view, "File", 0);
+    add_column(&preview_view, "", STATUS_COLUMN);
+    for (index, field) in FIELDS.iter().enumerate() {
+        add_column(
+            &preview_view,
+            field.title(),
+            FIRST_FIELD_COLUMN + index as u32,
+        );
+    }
+    let preview_window = ScrolledWindow::new(None, None);
+    preview_window.set_policy(PolicyType::Automatic, PolicyType::Automatic);
+    preview_window.set_vexpand(true);
+    preview_window.add(&preview_view);
+    vbox.add(&preview_window);
+
+    let preview = Rc::new(GuessPreview {
+        files: paths
+            .iter()
+            .map(|path| (path.clone(), Metadata::read_from_path(path).tagged))
+            .collect(),
+        model,
+        error_label,
+    });
+    preview.update(&text_patterns(&patterns_view), true);
+
+    if let Some(buffer) = patterns_view.get_buffer() {
+        let preview = preview.clone();
+        let view = patterns_view.clone();
+        let only_untagged = only_untagged.clone();
+        buffer.connect_changed(move |_| {
+            preview.update(&text_patterns(&view), only_untagged.get_active());
+        });
+    }
+    let update_preview = preview.clone();
+    let view = patterns_view.clone();
+    only_untagged.connect_toggled(move |only_untagged| {
+        update_preview.update(&text_patterns(&view), only_untagged.get_active());
+    });
+
+    dialog.show_all();
+    let response = dialog.run();
+    if response != RESPONSE_APPLY && response != RESPONSE_ACCEPT {
+        dialog.destroy();
+        return false;
+    }
+
+    let patterns = text_patterns(&patterns_view);
+    {
+        let mut config = config.lock().unwrap();
+        config.tag_patterns = patterns.clone();
+        config.save();
+    }
+
+    if response == RESPONSE_ACCEPT {
+        let mut failures = 0;
+        for (path, tagged) in &preview.files {
+            if *tagged && only_untagged.get_active() {
+                continue;
+            }
+            let fields = guess_fields(&patterns, Path::new(path)).unwrap_or_default();
+            if !fields.is_empty() && write_fields(path, &fields).is_err() {
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            show_error(
+                &dialog,
+                "Could not write the tags",
+                &format!("{} file(s) could not be written", failures),
+            );
+        }
+    }
+    dialog.destroy();
+    true
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cache::{cache_dir, file_key};
+use covers::image_key;
+use metadata::{read_cover, Metadata};
+use mp3::Mp3Decoder;
+use to_millis;
+
+const LIBRARY_FILE: &str = "library";
+const FIELDS: usize = 11;
+// The files read are saved this often during a scan, so that an interrupted scan keeps them
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// What the library knows about a file, read again when its modification time or size change
+#[derive(Clone)]
+pub struct Track {
+    pub path: String,
+    pub mtime: u64,
+    pub size: u64,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    // Milliseconds
+    pub duration: Option<u64>,
+    // Hash of the cover image, the same for the tracks of an album
+    pub cover: Option<u64>,
+}
+
+impl Track {
+    fn read(path: &str, (mtime, size): (u64, u64)) -> Track {
+        let metadata = Metadata::read_from_path(path);
+        let duration = File::open(path)
+            .ok()
+            .and_then(|file| Mp3Decoder::compute_duration(BufReader::new(file)))
+            .map(to_millis);
+        Track {
+            path: path.to_string(),
+            mtime,
+            size,
+            title: metadata.title,
+            artist: metadata.artist,
+            album: metadata.album,
+            genre: metadata.genre,
+            year: metadata.year,
+            track: metadata.track,
+            duration,
+            cover: read_cover(path).map(|data| image_key(&data)),
+        }
+    }
+
+    // The file name stands for a missing title
+    pub fn title(&self) -> String {
+        if !self.title.is_empty() {
+            return self.title.clone();
+        }
+        Path::new(&self.path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    // Tab separated, the path last. Missing numbers are empty.
+    fn to_line(&self) -> String {
+        let number = |value: Option<String>| value.unwrap_or_default();
+        let text = |value: &str| value.replace(['\t', '\n'], " ");
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.mtime,
+            self.size,
+            number(self.year.map(|year| year.to_string())),
+            number(self.track.map(|track| track.to_string())),
+            number(self.duration.map(|duration| duration.to_string())),
+            number(self.cover.map(|cover| cover.to_string())),
+            text(&self.title),
+            text(&self.artist),
+            text(&self.album),
+            text(&self.genre),
+            self.path
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Track> {
+        let fields: Vec<&str> = line.splitn(FIELDS, '\t').collect();
+        if fields.len() != FIELDS {
+            return None;
+        }
+        Some(Track {
+            mtime: fields[0].parse().ok()?,
+            size: fields[1].parse().ok()?,
+            year: fields[2].parse().ok(),
+            track: fields[3].parse().ok(),
+            duration: fields[4].parse().ok(),
+            cover: fields[5].parse().ok(),
+            title: fields[6].to_string(),
+            artist: fields[7].to_string(),
+            album: fields[8].to_string(),
+            genre: fields[9].to_string(),
+            path: fields[10].to_string(),
+        })
+    }
+}
+
+// Index of the MP3 files in the library folders, kept in the cache directory so that
+// only the new and modified files are read when scanning again
+pub struct Library {
+    tracks: HashMap<String, Track>,
+    file: Option<PathBuf>,
+    // Folders and subfolders found by the last scan
+    directories: Vec<PathBuf>,
+    // Incremented when a scan changes the tracks, for the views to reload
+    revision: u64,
+    scanning: bool,
+    // Folders to scan again once the running scan is over
+    rescan: Option<Vec<String>>,
+}
+
+impl Library {
+    pub fn load() -> Self {
+        let file = cache_dir().map(|dir| dir.join(LIBRARY_FILE));
+        let mut tracks = HashMap::new();
+        if let Some(reader) = file.as_ref().and_then(|file| File::open(file).ok()) {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                if let Some(track) = Track::from_line(&line) {
+                    tracks.insert(track.path.clone(), track);
+                }
+            }
+        }
+
+        Library {
+            tracks,
+            file,
+            directories: Vec::new(),
+            revision: 0,
+            scanning: false,
+            rescan: None,
+        }
+    }
+
+    // Written to a temporary file first, so that an interrupted save keeps the old index
+    fn save(&self) {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return,
+        };
+        let temporary = file.with_extension("new");
+        let written = File::create(&temporary).and_then(|output| {
+            let mut output = BufWriter::new(output);
+            for track in self.tracks.values() {
+                writeln!(output, "{}", track.to_line())?;
+            }
+            output.flush()
+        });
+        if written.is_ok() {
+            let _ = fs::rename(&temporary, file);
+        }
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.values()
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Track> {
+        self.tracks.get(path)
+    }
+
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scanning
+    }
+}
+
+// Index the folders in the background: unchanged files keep their entry, new and modified
+// ones are read, and those no longer found are dropped. Requested during a scan, it runs
+// again once it is over.
+pub fn scan(library: &Arc<Mutex<Library>>, folders: Vec<String>) {
+    {
+        let mut library = library.lock().unwrap();
+        if library.scanning {
+            library.rescan = Some(folders);
+            return;
+        }
+        library.scanning = true;
+    }
+
+    let library = library.clone();
+    thread::spawn(move || {
+        let mut folders = folders;
+        loop {
+            scan_folders(&library, &folders);
+            let mut library = library.lock().unwrap();
+            match library.rescan.take() {
+                Some(next) => folders = next,
+                None => {
+                    library.scanning = false;
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn scan_folders(library: &Mutex<Library>, folders: &[String]) {
+    let mut paths = Vec::new();
+    let mut directories = Vec::new();
+    for folder in folders {
+        find_files(Path::new(folder), &mut paths, &mut directories);
+    }
+
+    let mut tracks = HashMap::new();
+    let mut changed = false;
+    // Read since the last save
+    let mut unsaved = Vec::new();
+    let mut last_save = Instant::now();
+    for path in paths {
+        let key = match file_key(&path) {
+            Some(key) => key,
+            None => continue,
+        };
+        let known = library
+            .lock()
+            .unwrap()
+            .tracks
+            .get(&path)
+            .filter(|track| (track.mtime, track.size) == key)
+            .cloned();
+        let track = known.unwrap_or_else(|| {
+            changed = true;
+            let track = Track::read(&path, key);
+            unsaved.push(track.clone());
+            track
+        });
+        tracks.insert(path, track);
+
+        if !unsaved.is_empty() && last_save.elapsed() >= SAVE_INTERVAL {
+            let mut library = library.lock().unwrap();
+            for track in unsaved.drain(..) {
+                library.tracks.insert(track.path.clone(), track);
+            }
+            library.save();
+            last_save = Instant::now();
+        }
+    }
+
+    let mut library = library.lock().unwrap();
+    changed |= tracks.len() != library.tracks.len() || directories != library.directories;
+    library.tracks = tracks;
+    library.directories = directories;
+    if changed {
+        library.revision += 1;
+        library.save();
+    }
+}
+
+// MP3 files in the folder and its subfolders, links to folders left out to avoid cycles
+fn find_files(folder: &Path, paths: &mut Vec<String>, directories: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    directories.push(folder.to_path_buf());
+    for entry in entries.map_while(Result::ok) {
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            find_files(&path, paths, directories);
+        } else if is_mp3_file(&path) {
+            if let Some(path) = path.to_str() {
+                paths.push(path.to_string());
+            }
+        }
+    }
+}
+
+pub fn is_mp3_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"))
+}
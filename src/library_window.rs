@@ -0,0 +1,414 @@
+use gtk::{
+    ApplicationWindow, BoxExt, Button, ButtonExt, ComboBoxExt, ComboBoxText, ComboBoxTextExt,
+    ContainerExt, Continue, DialogExt, FileChooserAction, FileChooserDialog, FileChooserExt,
+    GtkWindowExt, Label, LabelExt, ListStore, ListStoreExt, ListStoreExtManual, Orientation,
+    SelectionMode, TreeModelExt, TreeSelectionExt, TreeView, TreeViewExt, Type, WidgetExt, Window,
+    WindowType,
+};
+
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use config::Config;
+use dialog::{add_column, scrolled, RESPONSE_ACCEPT, RESPONSE_CANCEL};
+use library::{scan, Library, Track};
+use millis_to_minutes;
+use playlist::Playlist;
+
+// Columns of the browse panes: shown text and the value filtered on
+const NAME_COLUMN: u32 = 0;
+const VALUE_COLUMN: u32 = 1;
+
+const TITLE_COLUMN: u32 = 0;
+const ARTIST_COLUMN: u32 = 1;
+const ALBUM_COLUMN: u32 = 2;
+const DURATION_COLUMN: u32 = 3;
+const PATH_COLUMN: u32 = 4;
+
+#[derive(Clone, Copy)]
+enum Category {
+    Artist,
+    Album,
+    Genre,
+    Year,
+}
+
+const CATEGORIES: [Category; 4] = [
+    Category::Artist,
+    Category::Album,
+    Category::Genre,
+    Category::Year,
+];
+
+impl Category {
+    fn title(self) -> &'static str {
+        match self {
+            Category::Artist => "Artist",
+            Category::Album => "Album",
+            Category::Genre => "Genre",
+            Category::Year => "Year",
+        }
+    }
+
+    fn value(self, track: &Track) -> String {
+        match self {
+            Category::Artist => track.artist.clone(),
+            Category::Album => track.album.clone(),
+            Category::Genre => track.genre.clone(),
+            Category::Year => track.year.map(|year| year.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+// Panes listing the values of each category, each one narrowed down by the selections in the
+// panes before it, and the tracks matching all the selections
+struct Browser {
+    library: Arc<Mutex<Library>>,
+    panes: Vec<(Category, TreeView, ListStore)>,
+    tracks_view: TreeView,
+    tracks_model: ListStore,
+    status: Label,
+    revision: Cell<Option<u64>>,
+    // The selection changes made while refilling the panes are ignored
+    filling: Cell<bool>,
+}
+
+impl Browser {
+    // None when the first row, for all the values, is selected
+    fn filter(&self, pane: usize) -> Option<String> {
+        let (model, iter) = self.panes[pane].1.get_selection().get_selected()?;
+        if model.get_path(&iter)?.get_indices().first() == Some(&0) {
+            return None;
+        }
+        model.get_value(&iter, VALUE_COLUMN as i32).get::<String>()
+    }
+
+    fn matches(&self, track: &Track, filters: &[(Category, Option<String>)]) -> bool {
+        filters.iter().all(|(category, filter)| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| *filter == category.value(track))
+        })
+    }
+
+    // Refill the panes from `first` on, and the tracks
+    fn fill_panes(&self, first: usize) {
+        self.filling.set(true);
+        let library = self.library.lock().unwrap();
+        for index in first..self.panes.len() {
+            let filters: Vec<(Category, Option<String>)> = (0..index)
+                .map(|pane| (self.panes[pane].0, self.filter(pane)))
+                .collect();
+            let (category, ref view, ref model) = self.panes[index];
+            let mut values: Vec<String> = library
+                .tracks()
+                .filter(|track| self.matches(track, &filters))
+                .map(|track| category.value(track))
+                .collect();
+            values.sort();
+            values.dedup();
+            // Missing values last
+            if values.first().is_some_and(String::is_empty) {
+                values.remove(0);
+                values.push(String::new());
+            }
+
+            model.clear();
+            let all = format!("All ({})", values.len());
+            model.insert_with_values(None, &[NAME_COLUMN, VALUE_COLUMN], &[&all, &""]);
+            for value in &values {
+                let name = if value.is_empty() { "Unknown" } else { value };
+                model.insert_with_values(None, &[NAME_COLUMN, VALUE_COLUMN], &[&name, value]);
+            }
+            if let Some(iter) = model.get_iter_first() {
+                view.get_selection().select_iter(&iter);
+            }
+        }
+        drop(library);
+        self.filling.set(false);
+        self.fill_tracks();
+    }
+
+    fn fill_tracks(&self) {
+        let filters: Vec<(Category, Option<String>)> = (0..self.panes.len())
+            .map(|pane| (self.panes[pane].0, self.filter(pane)))
+            .collect();
+        let library = self.library.lock().unwrap();
+        let mut tracks: Vec<&Track> = library
+            .tracks()
+            .filter(|track| self.matches(track, &filters))
+            .collect();
+        tracks.sort_by(|first, second| {
+            (&first.artist, &first.album, first.track, &first.path).cmp(&(
+                &second.artist,
+                &second.album,
+                second.track,
+                &second.path,
+            ))
+        });
+
+        self.tracks_model.clear();
+        for track in &tracks {
+            let duration = track.duration.map(millis_to_minutes).unwrap_or_default();
+            self.tracks_model.insert_with_values(
+                None,
+                &[
+                    TITLE_COLUMN,
+                    ARTIST_COLUMN,
+                    ALBUM_COLUMN,
+                    DURATION_COLUMN,
+                    PATH_COLUMN,
+                ],
+                &[
+                    &track.title(),
+                    &track.artist,
+                    &track.album,
+                    &duration,
+                    &track.path,
+                ],
+            );
+        }
+    }
+
+    // Called periodically, the panes are refilled when a scan changed the library
+    fn reload(&self) {
+        let (revision, scanning, count) = {
+            let library = self.library.lock().unwrap();
+            (
+                library.revision(),
+                library.is_scanning(),
+                library.tracks().count(),
+            )
+        };
+        let status = if scanning {
+            format!("{} tracks, scanning…", count)
+        } else {
+            format!("{} tracks", count)
+        };
+        self.status.set_text(&status);
+
+        if self.revision.get() != Some(revision) {
+            self.revision.set(Some(revision));
+            self.fill_panes(0);
+        }
+    }
+
+    // The selected tracks, or all those shown
+    fn selected_paths(&self) -> Vec<String> {
+        let (paths, _) = self.tracks_view.get_selection().get_selected_rows();
+        let iters: Vec<_> = if paths.is_empty() {
+            let mut iters = Vec::new();
+            if let Some(iter) = self.tracks_model.get_iter_first() {
+                loop {
+                    iters.push(iter.clone());
+                    if !self.tracks_model.iter_next(&iter) {
+                        break;
+                    }
+                }
+            }
+            iters
+        } else {
+            paths
+                .iter()
+                .filter_map(|path| self.tracks_model.get_iter(path))
+                .collect()
+        };
+        iters
+            .iter()
+            .filter_map(|iter| {
+                self.tracks_model
+                    .get_value(iter, PATH_COLUMN as i32)
+                    .get::<String>()
+            })
+            .collect()
+    }
+}
+
+fn fill_folders(folders: &ComboBoxText, config: &Arc<Mutex<Config>>) {
+    folders.remove_all();
+    for folder in &config.lock().unwrap().library_folders {
+        folders.append_text(folder);
+    }
+    folders.set_active(0);
+}
+
+fn choose_folder(parent: &Window) -> Option<String> {
+    let dialog = FileChooserDialog::new(
+        Some("Add a music folder"),
+        Some(parent),
+        FileChooserAction::SelectFolder,
+    );
+    dialog.add_button("Cancel", RESPONSE_CANCEL);
+    dialog.add_button("Add", RESPONSE_ACCEPT);
+    let folder = if dialog.run() == RESPONSE_ACCEPT {
+        dialog
+            .get_filename()
+            .and_then(|folder| folder.to_str().map(str::to_string))
+    } else {
+        None
+    };
+    dialog.destroy();
+    folder
+}
+
+pub fn show_library_window(
+    parent: &ApplicationWindow,
+    config: &Arc<Mutex<Config>>,
+    library: &Arc<Mutex<Library>>,
+    playlist: &Rc<Playlist>,
+) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Library");
+    window.set_transient_for(Some(parent));
+    window.set_destroy_with_parent(true);
+    window.set_default_size(900, 600);
+
+    let vbox = gtk::Box::new(Orientation::Vertical, 6);
+    vbox.set_border_width(10);
+    window.add(&vbox);
+
+    let folder_box = gtk::Box::new(Orientation::Horizontal, 6);
+    folder_box.pack_start(&Label::new("Folders"), false, false, 0);
+    let folders = ComboBoxText::new();
+    folder_box.pack_start(&folders, true, true, 0);
+    let add_folder = Button::new_with_label("Add folder…");
+    folder_box.pack_start(&add_folder, false, false, 0);
+    let remove_folder = Button::new_with_label("Remove folder");
+    folder_box.pack_start(&remove_folder, false, false, 0);
+    let rescan = Button::new_with_label("Rescan");
+    folder_box.pack_start(&rescan, false, false, 0);
+    vbox.pack_start(&folder_box, false, false, 0);
+    fill_folders(&folders, config);
+
+    let panes_box = gtk::Box::new(Orientation::Horizontal, 6);
+    panes_box.set_homogeneous(true);
+    panes_box.set_size_request(-1, 200);
+    let panes: Vec<(Category, TreeView, ListStore)> = CATEGORIES
+        .iter()
+        .map(|&category| {
+            let model = ListStore::new(&[Type::String, Type::String]);
+            let view = TreeView::new_with_model(&model);
+            add_column(&view, category.title(), NAME_COLUMN);
+            panes_box.add(&scrolled(&view));
+            (category, view, model)
+        })
+        .collect();
+    vbox.pack_start(&panes_box, false, false, 0);
+
+    let tracks_model = ListStore::new(&[Type::String; 5]);
+    let tracks_view = TreeView::new_with_model(&tracks_model);
+    tracks_view
+        .get_selection()
+        .set_mode(SelectionMode::Multiple);
+    add_column(&tracks_view, "Title", TITLE_COLUMN);
+    add_column(&tracks_view, "Artist", ARTIST_COLUMN);
+    add_column(&tracks_view, "Album", ALBUM_COLUMN);
+    add_column(&tracks_view, "Duration", DURATION_COLUMN);
+    vbox.pack_start(&scrolled(&tracks_view), true, true, 0);
+
+    let bottom = gtk::Box::new(Orientation::Horizontal, 6);
+    let status = Label::new(None);
+    bottom.pack_start(&status, false, false, 0);
+    let add_tracks = Button::new_with_label("Add to playlist");
+    bottom.pack_end(&add_tracks, false, false, 0);
+    vbox.pack_start(&bottom, false, false, 0);
+
+    let browser = Rc::new(Browser {
+        library: library.clone(),
+        panes,
+        tracks_view,
+        tracks_model,
+        status,
+        revision: Cell::new(None),
+        filling: Cell::new(false),
+    });
+
+    for (index, (_, view, _)) in browser.panes.iter().enumerate() {
+        let browser = browser.clone();
+        view.get_selection().connect_changed(move |_| {
+            if !browser.filling.get() {
+                browser.fill_panes(index + 1);
+            }
+        });
+    }
+
+    let add_browser = browser.clone();
+    let add_playlist = playlist.clone();
+    add_tracks.connect_clicked(move |_| {
+        for path in add_browser.selected_paths() {
+            add_playlist.add(Path::new(&path));
+        }
+    });
+
+    let activated_playlist = playlist.clone();
+    browser
+        .tracks_view
+        .connect_row_activated(move |view, path, _| {
+            let path = view.get_model().and_then(|model| {
+                let iter = model.get_iter(path)?;
+                model.get_value(&iter, PATH_COLUMN as i32).get::<String>()
+            });
+            if let Some(path) = path {
+                activated_playlist.add(Path::new(&path));
+            }
+        });
+
+    let folder_window = window.clone();
+    let folder_config = config.clone();
+    let folder_library = library.clone();
+    let folder_list = folders.clone();
+    add_folder.connect_clicked(move |_| {
+        if let Some(folder) = choose_folder(&folder_window) {
+            let folders = {
+                let mut config = folder_config.lock().unwrap();
+                if !config.library_folders.contains(&folder) {
+                    config.library_folders.push(folder);
+                }
+                config.save();
+                config.library_folders.clone()
+            };
+            fill_folders(&folder_list, &folder_config);
+            scan(&folder_library, folders);
+        }
+    });
+
+    // The tracks of the folder leave the library with the next scan
+    let remove_config = config.clone();
+    let remove_library = library.clone();
+    let remove_list = folders.clone();
+    remove_folder.connect_clicked(move |_| {
+        let folder = match remove_list.get_active_text() {
+            Some(folder) => folder,
+            None => return,
+        };
+        let folders = {
+            let mut config = remove_config.lock().unwrap();
+            config.library_folders.retain(|other| *other != folder);
+            config.save();
+            config.library_folders.clone()
+        };
+        fill_folders(&remove_list, &remove_config);
+        scan(&remove_library, folders);
+    });
+
+    let rescan_config = config.clone();
+    let rescan_library = library.clone();
+    rescan.connect_clicked(move |_| {
+        let folders = rescan_config.lock().unwrap().library_folders.clone();
+        scan(&rescan_library, folders);
+    });
+
+    window.show_all();
+
+    browser.reload();
+    let timeout_window = window.clone();
+    gtk::timeout_add(500, move || {
+        if !timeout_window.is_visible() {
+            return Continue(false);
+        }
+        browser.reload();
+        Continue(true)
+    });
+}
@@ -0,0 +1,262 @@
+use std::f64::consts::PI;
+
+// Loudness of a block of full scale sine wave at 997 Hz is -3.01 LUFS
+const LOUDNESS_OFFSET: f64 = -0.691;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+// Blocks of 400 ms overlapping by 75%, so built from 100 ms steps
+const STEPS_PER_BLOCK: usize = 4;
+const STEPS_PER_SECOND: u32 = 10;
+// True peak measured on a 4 times oversampled signal
+const OVERSAMPLING: usize = 4;
+const INTERPOLATION_TAPS: usize = 16;
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    LOUDNESS_OFFSET + 10.0 * energy.log10()
+}
+
+fn loudness_to_energy(loudness: f64) -> f64 {
+    10f64.powf((loudness - LOUDNESS_OFFSET) / 10.0)
+}
+
+// Integrated loudness in LUFS of the mean square energies of 400 ms blocks, following
+// the gating of ITU-R BS.1770. Blocks of several tracks can be chained to measure an album.
+pub fn gated_loudness<'a, I>(blocks: I) -> Option<f64>
+where
+    I: Iterator<Item = &'a f64> + Clone,
+{
+    let absolute_gate = loudness_to_energy(ABSOLUTE_GATE);
+    let mean = |gate: f64| {
+        let (sum, count) = blocks
+            .clone()
+            .filter(|&&energy| energy > gate)
+            .fold((0.0, 0), |(sum, count), &energy| (sum + energy, count + 1));
+        if count > 0 {
+            Some(sum / count as f64)
+        } else {
+            None
+        }
+    };
+
+    let relative_gate = mean(absolute_gate)? * 10f64.powf(RELATIVE_GATE / 10.0);
+    mean(absolute_gate.max(relative_gate)).map(energy_to_loudness)
+}
+
+// Second order IIR filter, transposed direct form II
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.z[0];
+        self.z[0] = self.b[1] * input - self.a[1] * output + self.z[1];
+        self.z[1] = self.b[2] * input - self.a[2] * output;
+        output
+    }
+}
+
+// K-weighting: a high shelf modelling the head, then a high pass (RLB)
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+// Windowed sinc coefficients for the intermediate positions between two samples
+fn interpolation_filter() -> Vec<[f64; INTERPOLATION_TAPS]> {
+    (1..OVERSAMPLING)
+        .map(|phase| {
+            let mut taps = [0.0; INTERPOLATION_TAPS];
+            let half = (INTERPOLATION_TAPS / 2) as f64;
+            for (tap, coefficient) in taps.iter_mut().enumerate() {
+                let x = half - 1.0 + phase as f64 / OVERSAMPLING as f64 - tap as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 + 0.5 * (PI * x / half).cos();
+                *coefficient = sinc * window;
+            }
+            taps
+        })
+        .collect()
+}
+
+struct Channel {
+    filters: [Biquad; 2],
+    history: [f64; INTERPOLATION_TAPS],
+    position: usize,
+}
+
+// EBU R128 meter: integrated loudness and true peak of a whole track
+pub struct LoudnessMeter {
+    channels: Vec<Channel>,
+    interpolation: Vec<[f64; INTERPOLATION_TAPS]>,
+    step_size: usize,
+    step_position: usize,
+    step_energy: f64,
+    steps: Vec<f64>,
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+    pub fn new(rate: u32, channels: usize) -> Self {
+        let channels = (0..channels)
+            .map(|_| Channel {
+                filters: k_weighting(rate),
+                history: [0.0; INTERPOLATION_TAPS],
+                position: 0,
+            })
+            .collect();
+
+        LoudnessMeter {
+            channels,
+            interpolation: interpolation_filter(),
+            step_size: (rate / STEPS_PER_SECOND) as usize,
+            step_position: 0,
+            step_energy: 0.0,
+            steps: Vec::with_capacity(STEPS_PER_BLOCK),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    // One sample per channel, full scale being 1.0
+    pub fn add_frame(&mut self, frame: &[f64]) {
+        for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+            let filtered = channel
+                .filters
+                .iter_mut()
+                .fold(sample, |sample, filter| filter.process(sample));
+            // Left, right and center channels all weigh 1.0
+            self.step_energy += filtered * filtered;
+
+            channel.history[channel.position] = sample;
+            channel.position = (channel.position + 1) % INTERPOLATION_TAPS;
+            self.peak = self.peak.max(sample.abs());
+            for taps in &self.interpolation {
+                let interpolated: f64 = taps
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, coefficient)| {
+                        coefficient * channel.history[(channel.position + tap) % INTERPOLATION_TAPS]
+                    })
+                    .sum();
+                self.peak = self.peak.max(interpolated.abs());
+            }
+        }
+
+        self.step_position += 1;
+        if self.step_position == self.step_size {
+            if self.steps.len() == STEPS_PER_BLOCK {
+                self.steps.remove(0);
+            }
+            self.steps.push(self.step_energy);
+            if self.steps.len() == STEPS_PER_BLOCK {
+                let energy: f64 = self.steps.iter().sum();
+                self.blocks
+                    .push(energy / (self.step_size * STEPS_PER_BLOCK) as f64);
+            }
+            self.step_position = 0;
+            self.step_energy = 0.0;
+        }
+    }
+
+    // Mean square energies of the 400 ms blocks
+    pub fn blocks(&self) -> &[f64] {
+        &self.blocks
+    }
+
+    pub fn loudness(&self) -> Option<f64> {
+        gated_loudness(self.blocks.iter())
+    }
+
+    // Linear amplitude, 1.0 being full scale
+    pub fn true_peak(&self) -> f64 {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gated_loudness, loudness_to_energy, LoudnessMeter};
+    use std::f64::consts::PI;
+
+    fn sine_meter(rate: u32, seconds: u32) -> LoudnessMeter {
+        let mut meter = LoudnessMeter::new(rate, 1);
+        for i in 0..rate * seconds {
+            let sample = (2.0 * PI * 997.0 * i as f64 / rate as f64).sin();
+            meter.add_frame(&[sample]);
+        }
+        meter
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        for &rate in &[44100, 48000] {
+            let meter = sine_meter(rate, 3);
+            let loudness = meter.loudness().unwrap();
+            assert!((loudness + 3.01).abs() < 0.05, "{} Hz: {}", rate, loudness);
+            assert!((meter.true_peak() - 1.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn absolute_gate() {
+        let blocks = [loudness_to_energy(-80.0); 10];
+        assert_eq!(gated_loudness(blocks.iter()), None);
+
+        let mut blocks = vec![loudness_to_energy(-20.0); 10];
+        blocks.extend(vec![loudness_to_energy(-80.0); 10]);
+        let loudness = gated_loudness(blocks.iter()).unwrap();
+        assert!((loudness + 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn relative_gate() {
+        // The mean of both is about -23 LUFS, so the quiet blocks fall under the gate
+        let mut blocks = vec![loudness_to_energy(-20.0); 10];
+        blocks.extend(vec![loudness_to_energy(-40.0); 10]);
+        let loudness = gated_loudness(blocks.iter()).unwrap();
+        assert!((loudness + 20.0).abs() < 1e-9);
+
+        // Blocks close to the mean are kept
+        let mut blocks = vec![loudness_to_energy(-20.0); 10];
+        blocks.extend(vec![loudness_to_energy(-25.0); 10]);
+        let loudness = gated_loudness(blocks.iter()).unwrap();
+        assert!(loudness < -21.0 && loudness > -25.0);
+    }
+}
@@ -0,0 +1,278 @@
+use id3::Tag;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// SYLT timestamps in milliseconds, rather than MPEG frames
+const SYLT_MILLISECONDS: u8 = 2;
+
+pub struct Line {
+    // Milliseconds from the start of the track, None for lyrics without timing
+    pub time: Option<u64>,
+    pub text: String,
+}
+
+pub struct Lyrics {
+    // Ordered by time when synchronized
+    pub lines: Vec<Line>,
+}
+
+impl Lyrics {
+    // Last line started at `time`
+    pub fn line_at(&self, time: i64) -> Option<usize> {
+        self.lines
+            .iter()
+            .rposition(|line| line.time.is_some_and(|start| start as i64 <= time))
+    }
+}
+
+// A .lrc file next to the track, then the synchronized lyrics of its tag, then the plain ones
+pub fn load_lyrics(path: &str) -> Option<Lyrics> {
+    let mut text = String::new();
+    let lrc_file = File::open(Path::new(path).with_extension("lrc")).ok();
+    if let Some(mut file) = lrc_file {
+        if file.read_to_string(&mut text).is_ok() {
+            if let Some(lyrics) = parse_lrc(&text) {
+                return Some(lyrics);
+            }
+        }
+    }
+
+    let tag = Tag::read_from_path(path).ok()?;
+    if let Some(lyrics) = tag
+        .get("SYLT")
+        .and_then(|frame| frame.content().unknown())
+        .and_then(parse_sylt)
+    {
+        return Some(lyrics);
+    }
+    // Some taggers store LRC text in USLT
+    let text = &tag.lyrics().next()?.text;
+    parse_lrc(text).or_else(|| {
+        Some(Lyrics {
+            lines: text
+                .lines()
+                .map(|line| Line {
+                    time: None,
+                    text: line.trim().to_string(),
+                })
+                .collect(),
+        })
+    })
+}
+
+// "[mm:ss.xx]text", a line possibly having several times. "[offset:ms]" makes the lyrics
+// appear that much earlier, the other tags are ignored. None without any timed line.
+pub fn parse_lrc(text: &str) -> Option<Lyrics> {
+    let mut offset = 0;
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut times = Vec::new();
+        while let Some(tag) = rest.strip_prefix('[') {
+            let end = match tag.find(']') {
+                Some(end) => end,
+                None => break,
+            };
+            let tag = &tag[..end];
+            if let Some(value) = tag.strip_prefix("offset:") {
+                offset = value.trim().parse().unwrap_or(0);
+            } else if let Some(time) = parse_time(tag) {
+                times.push(time);
+            }
+            rest = &rest[end + 2..];
+        }
+        for &time in &times {
+            lines.push((time, rest.trim().to_string()));
+        }
+    }
+    if lines.is_empty() {
+        return None;
+    }
+
+    lines.sort_by_key(|&(time, _)| time);
+    Some(Lyrics {
+        lines: lines
+            .into_iter()
+            .map(|(time, text)| Line {
+                time: Some((time - offset).max(0) as u64),
+                text,
+            })
+            .collect(),
+    })
+}
+
+// "mm:ss", "mm:ss.xx" or "mm:ss.xxx", in milliseconds
+fn parse_time(tag: &str) -> Option<i64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: i64 = minutes.trim().parse().ok()?;
+    let seconds: f64 = seconds.trim().parse().ok()?;
+    if !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    Some(minutes * 60_000 + (seconds * 1_000.0).round() as i64)
+}
+
+// Encoding, language, timestamp format, content type and description, then each text
+// followed by its big endian timestamp
+fn parse_sylt(data: &[u8]) -> Option<Lyrics> {
+    let encoding = *data.first()?;
+    if *data.get(4)? != SYLT_MILLISECONDS {
+        return None;
+    }
+    let mut rest = data.get(6..)?;
+    let (_, after) = split_text(rest, encoding)?;
+    rest = after;
+
+    let mut lines = Vec::new();
+    while !rest.is_empty() {
+        let (text, after) = split_text(rest, encoding)?;
+        let time = after.get(..4)?;
+        let time = u32::from_be_bytes([time[0], time[1], time[2], time[3]]);
+        // Lines usually start with a line feed, ending the previous one
+        lines.push(Line {
+            time: Some(time as u64),
+            text: text.trim().to_string(),
+        });
+        rest = &after[4..];
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    lines.sort_by_key(|line| line.time);
+    Some(Lyrics { lines })
+}
+
+// Text up to its terminator in the given ID3 encoding, and what follows it
+fn split_text(data: &[u8], encoding: u8) -> Option<(String, &[u8])> {
+    match encoding {
+        // UTF-16 with a byte order mark, and UTF-16BE: terminated by two null bytes
+        1 | 2 => {
+            let end = (0..data.len() / 2)
+                .find(|&index| data[index * 2] == 0 && data[index * 2 + 1] == 0)?
+                * 2;
+            let (mut bytes, mut big_endian) = (&data[..end], encoding == 2);
+            if bytes.starts_with(&[0xFF, 0xFE]) {
+                bytes = &bytes[2..];
+                big_endian = false;
+            } else if bytes.starts_with(&[0xFE, 0xFF]) {
+                bytes = &bytes[2..];
+                big_endian = true;
+            }
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .filter(|unit| unit.len() == 2)
+                .map(|unit| {
+                    if big_endian {
+                        u16::from_be_bytes([unit[0], unit[1]])
+                    } else {
+                        u16::from_le_bytes([unit[0], unit[1]])
+                    }
+                })
+                .collect();
+            Some((String::from_utf16_lossy(&units), &data[end + 2..]))
+        }
+        // ISO-8859-1 and UTF-8, terminated by a null byte
+        _ => {
+            let end = data.iter().position(|&byte| byte == 0)?;
+            let text = if encoding == 3 {
+                String::from_utf8_lossy(&data[..end]).to_string()
+            } else {
+                data[..end].iter().map(|&byte| byte as char).collect()
+            };
+            Some((text, &data[end + 1..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_lrc, parse_sylt, split_text};
+
+    fn lines(text: &str) -> Vec<(Option<u64>, String)> {
+        parse_lrc(text)
+            .unwrap()
+            .lines
+            .into_iter()
+            .map(|line| (line.time, line.text))
+            .collect()
+    }
+
+    #[test]
+    fn lrc_line_with_several_times() {
+        assert_eq!(
+            lines("[00:01.00][00:03.50]Chorus\n[00:02.00] Verse "),
+            vec![
+                (Some(1_000), "Chorus".to_string()),
+                (Some(2_000), "Verse".to_string()),
+                (Some(3_500), "Chorus".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn lrc_offset_shows_lines_earlier() {
+        assert_eq!(
+            lines("[ar:Someone]\n[offset:500]\n[01:00.000]Late\n[00:00.20]Early"),
+            vec![
+                (Some(0), "Early".to_string()),
+                (Some(59_500), "Late".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn lrc_without_times() {
+        assert!(parse_lrc("Just words\n[ti:Title]").is_none());
+    }
+
+    #[test]
+    fn utf16_byte_order_mark_per_string() {
+        let data = [
+            0xFF, 0xFE, b'A', 0x00, 0x00, 0x00, 0xFE, 0xFF, 0x00, b'B', 0x00, 0x00, 0x01,
+        ];
+        let (first, rest) = split_text(&data, 1).unwrap();
+        assert_eq!(first, "A");
+        let (second, rest) = split_text(rest, 1).unwrap();
+        assert_eq!(second, "B");
+        assert_eq!(rest, &[0x01]);
+    }
+
+    #[test]
+    fn latin1_and_unterminated_text() {
+        assert_eq!(
+            split_text(&[0xE9, 0x00], 0),
+            Some(("\u{e9}".to_string(), &[][..]))
+        );
+        assert_eq!(split_text(&[b'A', 0x00], 1), None);
+    }
+
+    #[test]
+    fn sylt_lines_ordered_by_time() {
+        let mut data = vec![1, b'e', b'n', b'g', 2, 1, 0xFF, 0xFE, 0x00, 0x00];
+        data.extend_from_slice(&[0xFF, 0xFE, b'\n', 0x00, b'H', 0x00, b'i', 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&1_000u32.to_be_bytes());
+        data.extend_from_slice(&[0xFE, 0xFF, 0x00, b'Y', 0x00, b'o', 0x00, 0x00]);
+        data.extend_from_slice(&500u32.to_be_bytes());
+
+        let lines: Vec<(Option<u64>, String)> = parse_sylt(&data)
+            .unwrap()
+            .lines
+            .into_iter()
+            .map(|line| (line.time, line.text))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (Some(500), "Yo".to_string()),
+                (Some(1_000), "Hi".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn sylt_in_mpeg_frames() {
+        assert!(parse_sylt(&[3, b'e', b'n', b'g', 1, 1, 0, b'A', 0, 0, 0, 0, 1]).is_none());
+    }
+}
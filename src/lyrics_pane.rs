@@ -0,0 +1,217 @@
+use gtk::{
+    CellLayoutExt, CellRendererText, ContainerExt, Label, ListStore, ListStoreExt,
+    ListStoreExtManual, Orientation, PolicyType, ScrolledWindow, ScrolledWindowExt, SpinButton,
+    SpinButtonExt, SpinButtonSignals, ToValue, TreeModelExt, TreePath, TreeView, TreeViewColumn,
+    TreeViewExt, Type, WidgetExt,
+};
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use config::Config;
+use lyrics::{load_lyrics, Lyrics};
+use save_later;
+use App;
+
+const TEXT_COLUMN: u32 = 0;
+const WEIGHT_COLUMN: u32 = 1;
+
+const WEIGHT_NORMAL: i32 = 400;
+const WEIGHT_BOLD: i32 = 700;
+
+// Seconds
+const MAX_OFFSET: f64 = 60.0;
+
+// Lyrics of the current track next to the views, the current line in bold when they are
+// synchronized. Clicking a line seeks to it.
+pub struct LyricsPane {
+    container: gtk::Box,
+    treeview: TreeView,
+    model: ListStore,
+    // Kept by file in the configuration
+    offset: SpinButton,
+    config: Arc<Mutex<Config>>,
+    // Set while the offset of another track is shown, so that it is not saved back
+    loading: Cell<bool>,
+    lyrics: RefCell<Option<Lyrics>>,
+    // Track the lyrics are from, and the line in bold
+    track: RefCell<Option<String>>,
+    current_line: Cell<Option<usize>>,
+}
+
+impl LyricsPane {
+    pub fn new(config: &Arc<Mutex<Config>>) -> Self {
+        let container = gtk::Box::new(Orientation::Vertical, 6);
+        container.set_size_request(300, -1);
+
+        let model = ListStore::new(&[Type::String, Type::I32]);
+        let treeview = TreeView::new_with_model(&model);
+        treeview.set_headers_visible(false);
+        treeview.set_activate_on_single_click(true);
+        let view_column = TreeViewColumn::new();
+        let cell = CellRendererText::new();
+        view_column.pack_start(&cell, true);
+        view_column.add_attribute(&cell, "text", TEXT_COLUMN as i32);
+        view_column.add_attribute(&cell, "weight", WEIGHT_COLUMN as i32);
+        treeview.append_column(&view_column);
+
+        let window = ScrolledWindow::new(None, None);
+        window.set_policy(PolicyType::Never, PolicyType::Automatic);
+        window.set_vexpand(true);
+        window.add(&treeview);
+        container.add(&window);
+
+        // Positive values show the lines later
+        let hbox = gtk::Box::new(Orientation::Horizontal, 6);
+        hbox.add(&Label::new("Offset (s)"));
+        let offset = SpinButton::new_with_range(-MAX_OFFSET, MAX_OFFSET, 0.1);
+        offset.set_digits(1);
+        hbox.add(&offset);
+        container.add(&hbox);
+
+        LyricsPane {
+            container,
+            treeview,
+            model,
+            offset,
+            config: config.clone(),
+            loading: Cell::new(false),
+            lyrics: RefCell::new(None),
+            track: RefCell::new(None),
+            current_line: Cell::new(None),
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    fn offset_millis(&self) -> i64 {
+        (self.offset.get_value() * 1_000.0) as i64
+    }
+
+    // Called periodically, while shown
+    pub fn update(&self, track: Option<String>, current_time: u64) {
+        if !self.container.is_visible() {
+            return;
+        }
+        if track != *self.track.borrow() {
+            self.set_track(&track);
+            *self.track.borrow_mut() = track;
+        }
+
+        let line = self
+            .lyrics
+            .borrow()
+            .as_ref()
+            .and_then(|lyrics| lyrics.line_at(current_time as i64 - self.offset_millis()));
+        if line == self.current_line.get() {
+            return;
+        }
+        for (index, weight) in [
+            (self.current_line.get(), WEIGHT_NORMAL),
+            (line, WEIGHT_BOLD),
+        ] {
+            let iter = index.and_then(|index| self.model.iter_nth_child(None, index as i32));
+            if let Some(iter) = iter {
+                self.model
+                    .set_value(&iter, WEIGHT_COLUMN, &weight.to_value());
+            }
+        }
+        if let Some(line) = line {
+            let path = TreePath::new_from_indicesv(&[line as i32]);
+            self.treeview
+                .scroll_to_cell(Some(&path), None, true, 0.5, 0.0);
+        }
+        self.current_line.set(line);
+    }
+
+    fn set_track(&self, track: &Option<String>) {
+        self.model.clear();
+        self.current_line.set(None);
+        let offset = track
+            .as_ref()
+            .and_then(|path| {
+                self.config
+                    .lock()
+                    .unwrap()
+                    .lyrics_offsets
+                    .get(path)
+                    .cloned()
+            })
+            .unwrap_or(0.0);
+        self.loading.set(true);
+        self.offset.set_value(offset);
+        self.loading.set(false);
+        let lyrics = track.as_ref().and_then(|path| load_lyrics(path));
+        match lyrics {
+            Some(ref lyrics) => {
+                for line in &lyrics.lines {
+                    self.model.insert_with_values(
+                        None,
+                        &[TEXT_COLUMN, WEIGHT_COLUMN],
+                        &[&line.text, &WEIGHT_NORMAL],
+                    );
+                }
+            }
+            None if track.is_some() => {
+                self.model.insert_with_values(
+                    None,
+                    &[TEXT_COLUMN, WEIGHT_COLUMN],
+                    &[&"No lyrics found", &WEIGHT_NORMAL],
+                );
+            }
+            None => (),
+        }
+        *self.lyrics.borrow_mut() = lyrics;
+    }
+
+    // Milliseconds where the line starts in the track, the offset applied
+    fn line_time(&self, index: usize) -> Option<u64> {
+        let lyrics = self.lyrics.borrow();
+        let start = lyrics.as_ref()?.lines.get(index)?.time?;
+        Some((start as i64 + self.offset_millis()).max(0) as u64)
+    }
+}
+
+impl App {
+    pub fn connect_lyrics_events(&self) {
+        let lyrics_pane = self.lyrics_pane.clone();
+        let config = self.config.clone();
+        let save_pending = Rc::new(Cell::new(false));
+        self.lyrics_pane
+            .offset
+            .connect_value_changed(move |offset| {
+                if lyrics_pane.loading.get() {
+                    return;
+                }
+                if let Some(ref track) = *lyrics_pane.track.borrow() {
+                    let offset = offset.get_value();
+                    {
+                        let offsets = &mut config.lock().unwrap().lyrics_offsets;
+                        if offset == 0.0 {
+                            offsets.remove(track);
+                        } else {
+                            offsets.insert(track.clone(), offset);
+                        }
+                    }
+                    save_later(&config, &save_pending);
+                }
+            });
+
+        let lyrics_pane = self.lyrics_pane.clone();
+        let playlist = self.playlist.clone();
+        self.lyrics_pane
+            .treeview
+            .connect_row_activated(move |_, path, _| {
+                let time = path
+                    .get_indices()
+                    .first()
+                    .and_then(|&index| lyrics_pane.line_time(index as usize));
+                if let Some(time) = time {
+                    playlist.seek(time);
+                }
+            });
+    }
+}
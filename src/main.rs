@@ -1,50 +1,130 @@
+extern crate cairo;
 extern crate crossbeam;
+extern crate gdk;
 extern crate gdk_pixbuf; // Show and manipulate images
 extern crate gio;
 extern crate gtk;
 extern crate gtk_sys;
 extern crate id3; // Metadata from MP3 files
+extern crate libc;
+extern crate libpulse_sys;
 extern crate pulse_simple;
 extern crate simplemad;
 
+mod ape;
+mod bookmarks;
+mod cache;
+mod config;
+mod covers;
+mod crossfade;
+mod dialog;
+mod equalizer;
+mod equalizer_window;
+mod guess;
+mod guess_dialog;
+mod library;
+mod library_window;
+mod loudness;
+mod lyrics;
+mod lyrics_pane;
+mod metadata;
+mod mini_player;
 mod mp3;
+mod now_playing;
+mod output;
 mod player;
 mod playlist;
+mod preferences;
+mod replaygain;
+mod resampler;
+mod ring;
+mod scan;
+mod scrobbler;
+mod smart_playlist;
+mod smart_playlist_window;
+mod stats;
+mod stretch;
+mod tag_editor;
 mod toolbar;
+mod visualizer;
+mod watcher;
+mod waveform;
 
 use gtk::{
     Adjustment, AdjustmentExt, Application, ApplicationWindow, ContainerExt, Continue,
-    GtkWindowExt, Image, Label, LabelExt, Scale, ScaleExt, WidgetExt,
+    GtkWindowExt, Image, Label, LabelExt, ProgressBar, ProgressBarExt, Scale, ScaleExt, SpinButton,
+    SpinButtonExt, SpinButtonSignals, Stack, StackExt, TreeSortableExt, WidgetExt,
 };
 
 use gio::{ApplicationExt, ApplicationExtManual, ApplicationFlags};
 use gtk::Orientation::{Horizontal, Vertical};
 use std::env;
 
+use bookmarks::{set_marks, Bookmarks, PositionControls};
+use config::Config;
+use library::Library;
+use lyrics_pane::LyricsPane;
+use mini_player::MiniPlayer;
+use now_playing::{set_view, NowPlaying, NOW_PLAYING_VIEW, PLAYLIST_VIEW};
 use playlist::Playlist;
-use toolbar::{set_image_icon, MusicToolbar, PAUSE_ICON, PLAY_ICON};
+use smart_playlist::SmartPlaylists;
+use stats::Stats;
+use stretch::{MAX_PITCH, MAX_SPEED, MIN_SPEED};
+use toolbar::{set_cover, set_image_icon, MusicToolbar, PAUSE_ICON, PLAY_ICON};
+use visualizer::Visualizer;
+use watcher::Watcher;
+use waveform::WaveformBar;
 
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+// Milliseconds a setting changed with a spin button is left before saving
+const SAVE_DELAY: u32 = 500;
+
 struct State {
     current_time: u64,
     durations: HashMap<String, u64>,
     stopped: bool,
+    // Set by the player when it moves on to the queued track by itself
+    track_changed: Option<String>,
+    // A and B points in the current track, the player loops once both are set
+    ab_loop: (Option<u64>, Option<u64>),
+    // Track being played and the milliseconds of it heard, the parts skipped by seeking
+    // left out
+    listened: Option<(String, u64)>,
 }
 
 struct App {
+    config: Arc<Mutex<Config>>,
+    library: Arc<Mutex<Library>>,
+    watcher: Rc<Watcher>,
     toolbar: MusicToolbar,
     window: ApplicationWindow,
     cover: Image,
+    // The playlist or the now playing view
+    views: Stack,
+    now_playing: Rc<NowPlaying>,
+    mini_player: Rc<MiniPlayer>,
+    lyrics_pane: Rc<LyricsPane>,
     adjustment: Adjustment,
+    scale: Scale,
+    position_controls: Rc<PositionControls>,
+    bookmarks: Rc<RefCell<Bookmarks>>,
+    smart_playlists: Rc<RefCell<SmartPlaylists>>,
+    stats: Rc<RefCell<Stats>>,
+    visualizer: Rc<Visualizer>,
+    waveform: Rc<WaveformBar>,
     playlist: Rc<Playlist>, // Reference counting pointer
+    scan_progress: ProgressBar,
     state: Arc<Mutex<State>>,
     current_time_label: Label,
     duration_label: Label,
+    speed: SpinButton,
+    pitch: SpinButton,
 }
 
 impl App {
@@ -59,19 +139,67 @@ impl App {
         let toolbar = MusicToolbar::new();
         vbox.add(toolbar.toolbar());
 
+        // Only shown while a loudness scan runs
+        let scan_progress = ProgressBar::new();
+        scan_progress.set_show_text(true);
+        vbox.add(&scan_progress);
+
         let current_time = 0;
         let durations = HashMap::new();
         let state = Arc::new(Mutex::new(State {
             current_time,
             durations,
             stopped: true,
+            track_changed: None,
+            ab_loop: (None, None),
+            listened: None,
         }));
 
-        let playlist = Rc::new(Playlist::new(state.clone()));
-        vbox.add(playlist.view());
+        let config = Arc::new(Mutex::new(Config::load()));
+
+        // Files added or modified since the last run are indexed again
+        let library = Arc::new(Mutex::new(Library::load()));
+        let folders = config.lock().unwrap().library_folders.clone();
+        if !folders.is_empty() {
+            library::scan(&library, folders);
+        }
+
+        let views_box = gtk::Box::new(Horizontal, 0);
+        vbox.add(&views_box);
+
+        let views = Stack::new();
+        views.set_hexpand(true);
+        views_box.add(&views);
+
+        let playlist_view = gtk::Box::new(Vertical, 0);
+        views.add_named(&playlist_view, PLAYLIST_VIEW);
+
+        let watcher = Rc::new(Watcher::new());
+        let stats = Rc::new(RefCell::new(Stats::load()));
+        let bookmarks = Rc::new(RefCell::new(Bookmarks::load()));
+        let playlist = Rc::new(Playlist::new(
+            state.clone(),
+            config.clone(),
+            watcher.clone(),
+            stats.clone(),
+            bookmarks.clone(),
+        ));
+        playlist_view.add(playlist.view());
 
         let cover = Image::new();
-        vbox.add(&cover);
+        playlist_view.add(&cover);
+
+        let now_playing = Rc::new(NowPlaying::new());
+        views.add_named(now_playing.container(), NOW_PLAYING_VIEW);
+
+        let lyrics_pane = Rc::new(LyricsPane::new(&config));
+        views_box.add(lyrics_pane.container());
+
+        let visualizer = Rc::new(Visualizer::new(config.lock().unwrap().visualizer));
+        vbox.add(visualizer.area());
+
+        let waveform = Rc::new(WaveformBar::new());
+        vbox.add(waveform.area());
 
         let hbox = gtk::Box::new(Horizontal, 10);
         vbox.add(&hbox);
@@ -82,6 +210,9 @@ impl App {
         scale.set_hexpand(true);
         hbox.add(&scale);
 
+        // Shares the position slider
+        let mini_player = Rc::new(MiniPlayer::new(&adjustment));
+
         let current_time_label = Label::new(None);
         hbox.add(&current_time_label);
 
@@ -89,55 +220,200 @@ impl App {
         hbox.add(&slash_label);
 
         let duration_label = Label::new(None);
-        duration_label.set_margin_right(10);
         hbox.add(&duration_label);
 
+        let position_controls = Rc::new(PositionControls::new());
+        hbox.add(position_controls.container());
+
+        hbox.add(visualizer.mode_combo());
+
+        let (current_speed, current_pitch) = {
+            let config = config.lock().unwrap();
+            (config.speed, config.pitch)
+        };
+
+        hbox.add(&Label::new("Speed"));
+        let speed = SpinButton::new_with_range(MIN_SPEED as f64, MAX_SPEED as f64, 0.05);
+        speed.set_digits(2);
+        speed.set_value(current_speed as f64);
+        hbox.add(&speed);
+
+        hbox.add(&Label::new("Pitch"));
+        let pitch = SpinButton::new_with_range(-MAX_PITCH as f64, MAX_PITCH as f64, 0.5);
+        pitch.set_digits(1);
+        pitch.set_value(current_pitch as f64);
+        pitch.set_margin_right(10);
+        hbox.add(&pitch);
+
         window.show_all();
+        scan_progress.hide();
+        let mode = config.lock().unwrap().visualizer;
+        visualizer.set_mode(mode);
+        // Children can only be made visible once shown
+        set_view(&views, config.lock().unwrap().now_playing);
+        lyrics_pane
+            .container()
+            .set_visible(config.lock().unwrap().show_lyrics);
 
         let app = App {
+            config,
+            library,
+            watcher,
             toolbar,
             window,
             cover,
+            views,
+            now_playing,
+            mini_player,
+            lyrics_pane,
             adjustment,
+            scale,
+            position_controls,
+            bookmarks,
+            smart_playlists: Rc::new(RefCell::new(SmartPlaylists::load())),
+            stats,
+            visualizer,
+            waveform,
             playlist,
+            scan_progress,
             state,
             current_time_label,
             duration_label,
+            speed,
+            pitch,
         };
 
         app.connect_events();
         app.connect_toolbar_events();
+        app.connect_position_events();
+        app.connect_visualizer_events();
+        app.connect_waveform_events();
+        app.connect_now_playing_events();
+        app.connect_mini_player_events();
+        app.connect_lyrics_events();
+        app.connect_watcher_events();
+        app.connect_smart_playlist_events();
+        app.connect_stats_events();
 
         app
     }
 
     fn connect_events(&self) {
+        // Picked up by the player on the next buffer, and saved once the value stops changing
+        let config = self.config.clone();
+        let save_pending = Rc::new(Cell::new(false));
+        let pending = save_pending.clone();
+        self.speed.connect_value_changed(move |speed| {
+            config.lock().unwrap().speed = speed.get_value() as f32;
+            save_later(&config, &pending);
+        });
+
+        let config = self.config.clone();
+        let pending = save_pending.clone();
+        self.pitch.connect_value_changed(move |pitch| {
+            config.lock().unwrap().pitch = pitch.get_value() as f32;
+            save_later(&config, &pending);
+        });
+
+        // Not to lose a change made just before quitting
+        let config = self.config.clone();
+        self.window.connect_destroy(move |_| {
+            if save_pending.get() {
+                config.lock().unwrap().save();
+            }
+        });
+
+        // Sorting by a column moves the rows, and the one after the current track with them.
+        // They are sorted after the signal.
+        let playlist = self.playlist.clone();
+        self.playlist.model().connect_sort_column_changed(move |_| {
+            let playlist = playlist.clone();
+            gtk::idle_add(move || {
+                playlist.requeue();
+                Continue(false)
+            });
+        });
+
         let current_time_label = self.current_time_label.clone();
         let duration_label = self.duration_label.clone();
         let playlist = self.playlist.clone();
         let adjustment = self.adjustment.clone();
         let state = self.state.clone();
         let play_image = self.toolbar.play_image.clone();
+        let cover = self.cover.clone();
+        let scale = self.scale.clone();
+        let bookmarks = self.bookmarks.clone();
+        let waveform = self.waveform.clone();
+        let now_playing = self.now_playing.clone();
+        let mini_player = self.mini_player.clone();
+        let lyrics_pane = self.lyrics_pane.clone();
+        let mut marks = None;
         gtk::timeout_add(100, move || {
-            let state = state.lock().unwrap();
-            if let Some(path) = playlist.path() {
-                if let Some(&duration) = state.durations.get(&path) {
-                    adjustment.set_upper(duration as f64);
-                    duration_label.set_text(&millis_to_minutes(duration));
-                }
+            let track_changed = state.lock().unwrap().track_changed.take();
+            if let Some(path) = track_changed {
+                playlist.set_current(&path);
+                set_cover(&cover, &playlist);
+            }
+
+            // The player takes the lock for every buffer, it is not held while the views
+            // read files or decode images
+            let path = playlist.path();
+            let (current_time, stopped, ab_loop, duration) = {
+                let state = state.lock().unwrap();
+                let duration = path
+                    .as_ref()
+                    .and_then(|path| state.durations.get(path))
+                    .cloned();
+                (state.current_time, state.stopped, state.ab_loop, duration)
+            };
+            if let Some(duration) = duration {
+                adjustment.set_upper(duration as f64);
+                duration_label.set_text(&millis_to_minutes(duration));
             }
-            if state.stopped {
+            if stopped {
                 set_image_icon(&play_image, PLAY_ICON);
             } else {
                 set_image_icon(&play_image, PAUSE_ICON);
-                current_time_label.set_text(&millis_to_minutes(state.current_time));
+                current_time_label.set_text(&millis_to_minutes(current_time));
             }
-            adjustment.set_value(state.current_time as f64);
+            adjustment.set_value(current_time as f64);
+
+            let current_marks = Some((path.clone(), ab_loop, bookmarks.borrow().revision()));
+            if current_marks != marks {
+                let bookmarks = bookmarks.borrow();
+                let track_bookmarks = path.as_ref().map(|path| bookmarks.get(path)).unwrap_or(&[]);
+                set_marks(&scale, ab_loop, track_bookmarks);
+                marks = current_marks;
+            }
+
+            let progress = duration
+                .map(|duration| current_time as f64 / duration.max(1) as f64)
+                .unwrap_or(0.0);
+            now_playing.update(&playlist, current_time, duration);
+            mini_player.update(&playlist, stopped);
+            lyrics_pane.update(path.clone(), current_time);
+            waveform.set_track(path);
+            waveform.update(progress);
+            playlist.update_thumbnails();
             Continue(true)
         });
     }
 }
 
+// Save the configuration after SAVE_DELAY, once for all the changes made meanwhile
+fn save_later(config: &Arc<Mutex<Config>>, pending: &Rc<Cell<bool>>) {
+    if pending.replace(true) {
+        return;
+    }
+    let config = config.clone();
+    let pending = pending.clone();
+    gtk::timeout_add(SAVE_DELAY, move || {
+        pending.set(false);
+        config.lock().unwrap().save();
+        Continue(false)
+    });
+}
+
 fn main() {
     // gio application
     let application = Application::new("com.github.eligero-rusic", ApplicationFlags::empty())
@@ -156,6 +432,14 @@ fn to_millis(duration: Duration) -> u64 {
     duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
 }
 
+// Seconds since the epoch
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
 fn millis_to_minutes(millis: u64) -> String {
     let mut seconds = millis / 1_000;
     let minutes = seconds / 60;
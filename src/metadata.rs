@@ -0,0 +1,269 @@
+use id3::frame::{Comment, Picture, PictureType};
+use id3::{self, Tag};
+
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use ape::ApeTag;
+use mp3::read_stream_info;
+
+// Images next to the tracks, by order of preference, with any of the extensions
+const FOLDER_COVER_NAMES: [&str; 3] = ["cover", "folder", "front"];
+const FOLDER_COVER_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "gif"];
+
+// Everything shown in the playlist about a file, missing values being empty
+#[derive(Default)]
+pub struct Metadata {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_artist: String,
+    pub genre: String,
+    pub composer: String,
+    pub comment: String,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    pub total_tracks: Option<u32>,
+    pub disc: Option<u32>,
+    pub total_discs: Option<u32>,
+    // kbps
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub file_size: u64,
+    // Title, artist or album read from a tag. Files without any, or whose tag only holds
+    // other frames such as ReplayGain values, can have them guessed from their path.
+    pub tagged: bool,
+}
+
+impl Metadata {
+    // ID3v2 first, then APEv2 and ID3v1 for files without one
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Metadata {
+        let path = path.as_ref();
+        let mut metadata = if let Ok(tag) = Tag::read_from_path(path) {
+            Metadata::from_id3(&tag)
+        } else if let Some(tag) = ApeTag::read_from_path(path) {
+            Metadata::from_ape(&tag)
+        } else if let Some(tag) = File::open(path)
+            .ok()
+            .and_then(|file| id3::v1::Tag::read_from(file).ok())
+        {
+            Metadata::from_id3(&tag.into())
+        } else {
+            Metadata::default()
+        };
+        metadata.tagged =
+            !(metadata.title.is_empty() && metadata.artist.is_empty() && metadata.album.is_empty());
+
+        if let Some((bitrate, sample_rate)) = File::open(path)
+            .ok()
+            .and_then(|file| read_stream_info(BufReader::new(file)))
+        {
+            metadata.bitrate = Some(bitrate);
+            metadata.sample_rate = Some(sample_rate);
+        }
+        metadata.file_size = fs::metadata(path).map(|file| file.len()).unwrap_or(0);
+        metadata
+    }
+
+    fn from_id3(tag: &Tag) -> Metadata {
+        let text = |value: Option<&str>| value.unwrap_or_default().trim().to_string();
+        Metadata {
+            title: text(tag.title()),
+            artist: text(tag.artist()),
+            album: text(tag.album()),
+            album_artist: text(tag.album_artist()),
+            genre: decode_genre(tag.genre().unwrap_or_default()),
+            composer: text(tag.get("TCOM").and_then(|frame| frame.content().text())),
+            comment: text(comment(tag).map(|comment| comment.text.as_str())),
+            // ID3v2.4 files store it in the recording time instead
+            year: tag
+                .year()
+                .or_else(|| tag.date_recorded().map(|date| date.year)),
+            track: tag.track(),
+            total_tracks: tag.total_tracks(),
+            disc: tag.disc(),
+            total_discs: tag.total_discs(),
+            ..Metadata::default()
+        }
+    }
+
+    fn from_ape(tag: &ApeTag) -> Metadata {
+        let text = |key: &str| tag.get(key).unwrap_or_default().trim().to_string();
+        let (track, total_tracks) = number_pair(tag.get("Track"));
+        let (disc, total_discs) = number_pair(tag.get("Disc"));
+        Metadata {
+            title: text("Title"),
+            artist: text("Artist"),
+            album: text("Album"),
+            album_artist: text("Album Artist"),
+            genre: decode_genre(&text("Genre")),
+            composer: text("Composer"),
+            comment: text("Comment"),
+            // May be a full date
+            year: tag
+                .get("Year")
+                .and_then(|year| year.trim().get(..4))
+                .and_then(|year| year.parse().ok()),
+            track,
+            total_tracks,
+            disc,
+            total_discs,
+            ..Metadata::default()
+        }
+    }
+}
+
+// Image data of the embedded cover, or of an image in the folder of the file
+pub fn read_cover<P: AsRef<Path>>(path: P) -> Option<Vec<u8>> {
+    let path = path.as_ref();
+    if let Ok(tag) = Tag::read_from_path(path) {
+        if let Some(picture) = cover_picture(&tag) {
+            return Some(picture.data.clone());
+        }
+    }
+    let mut data = Vec::new();
+    File::open(folder_cover(path)?)
+        .ok()?
+        .read_to_end(&mut data)
+        .ok()?;
+    Some(data)
+}
+
+// The comment without description, or the first one
+pub fn comment(tag: &Tag) -> Option<&Comment> {
+    tag.comments()
+        .find(|comment| comment.description.is_empty())
+        .or_else(|| tag.comments().next())
+}
+
+// The front cover, or the first picture of another type
+pub fn cover_picture(tag: &Tag) -> Option<&Picture> {
+    tag.pictures()
+        .find(|picture| picture.picture_type == PictureType::CoverFront)
+        .or_else(|| tag.pictures().next())
+}
+
+// Image with one of the usual names in the directory of the file, the case being ignored
+fn folder_cover(path: &Path) -> Option<PathBuf> {
+    let images: Vec<(usize, PathBuf)> = fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| {
+            let image = entry.ok()?.path();
+            let stem = image.file_stem()?.to_string_lossy().to_lowercase();
+            let extension = image.extension()?.to_string_lossy().to_lowercase();
+            let rank = FOLDER_COVER_NAMES.iter().position(|&name| name == stem)?;
+            if FOLDER_COVER_EXTENSIONS.contains(&extension.as_str()) {
+                Some((rank, image))
+            } else {
+                None
+            }
+        })
+        .collect();
+    images
+        .into_iter()
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, image)| image)
+}
+
+// "3/12" as written for the track and disc numbers
+fn number_pair(value: Option<&str>) -> (Option<u32>, Option<u32>) {
+    let mut numbers = value
+        .unwrap_or_default()
+        .splitn(2, '/')
+        .map(|number| number.trim().parse().ok());
+    (
+        numbers.next().and_then(|number| number),
+        numbers.next().and_then(|number| number),
+    )
+}
+
+fn genre_name(id: &str) -> Option<String> {
+    match id {
+        "RX" => Some("Remix".to_string()),
+        "CR" => Some("Cover".to_string()),
+        _ => {
+            let genre_id = id.parse().ok()?;
+            let tag = id3::v1::Tag {
+                genre_id,
+                ..id3::v1::Tag::default()
+            };
+            tag.genre().map(str::to_string)
+        }
+    }
+}
+
+// ID3 genres can be references to the ID3v1 list: "17", "(17)", "(17)(20)", or "(17)Rock" where
+// the text refines the reference. ID3v2.4 separates several genres with null characters.
+pub fn decode_genre(genre: &str) -> String {
+    let genres: Vec<String> = genre
+        .split('\0')
+        .map(str::trim)
+        .filter(|genre| !genre.is_empty())
+        .map(|genre| {
+            if let Some(name) = genre_name(genre) {
+                return name;
+            }
+
+            let mut names = Vec::new();
+            let mut rest = genre;
+            while rest.starts_with('(') && !rest.starts_with("((") {
+                let end = match rest.find(')') {
+                    Some(end) => end,
+                    None => break,
+                };
+                match genre_name(&rest[1..end]) {
+                    Some(name) => names.push(name),
+                    None => break,
+                }
+                rest = &rest[end + 1..];
+            }
+
+            // "((" escapes a text starting with a parenthesis
+            let rest = rest.trim();
+            let rest = if rest.starts_with("((") {
+                &rest[1..]
+            } else {
+                rest
+            };
+            if !rest.is_empty() || names.is_empty() {
+                rest.to_string()
+            } else {
+                names.join(", ")
+            }
+        })
+        .collect();
+    genres.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_genre;
+
+    #[test]
+    fn genre_references() {
+        assert_eq!(decode_genre("17"), "Rock");
+        assert_eq!(decode_genre("(17)"), "Rock");
+        assert_eq!(decode_genre("(17)(20)"), "Rock, Alternative");
+        assert_eq!(decode_genre("(RX)"), "Remix");
+    }
+
+    #[test]
+    fn genre_refined_by_text() {
+        assert_eq!(decode_genre("(17)Rock"), "Rock");
+        assert_eq!(decode_genre("(17)Hard Rock"), "Hard Rock");
+    }
+
+    #[test]
+    fn genre_escaped_parenthesis() {
+        assert_eq!(decode_genre("((Live) Rock"), "(Live) Rock");
+        assert_eq!(decode_genre("(17)((Live)"), "(Live)");
+    }
+
+    #[test]
+    fn genre_text_and_several_genres() {
+        assert_eq!(decode_genre("Jazz"), "Jazz");
+        assert_eq!(decode_genre("(Unknown)"), "(Unknown)");
+        assert_eq!(decode_genre("Jazz\0(17)\0"), "Jazz, Rock");
+    }
+}
@@ -0,0 +1,202 @@
+use gdk_pixbuf::InterpType;
+use gtk::{
+    Adjustment, ApplicationWindow, Button, ButtonExt, ContainerExt, GtkWindowExt, Image, ImageExt,
+    Inhibit, Label, LabelExt, Orientation, RangeExt, Scale, ScaleExt, ToolButtonExt, WidgetExt,
+    Window, WindowType,
+};
+
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, Mutex};
+
+use config::Config;
+use playlist::Playlist;
+use toolbar::{set_image_icon, PAUSE_ICON, PLAY_ICON};
+use App;
+
+const THUMBNAIL_SIZE: i32 = 64;
+
+const INTERP_HYPER: InterpType = 3;
+
+// Small window kept above the others, used instead of the main one. It controls the same
+// playlist through the buttons of the main toolbar, and shares its position slider.
+pub struct MiniPlayer {
+    window: Window,
+    cover: Image,
+    title_label: Label,
+    scale: Scale,
+    previous_button: Button,
+    play_button: Button,
+    play_image: Image,
+    next_button: Button,
+    restore_button: Button,
+    // Track the cover and title are about
+    track: RefCell<Option<String>>,
+    // State the play button shows, its icon being loaded from a file
+    stopped: Cell<Option<bool>>,
+}
+
+impl MiniPlayer {
+    pub fn new(adjustment: &Adjustment) -> Self {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Rusic");
+        window.set_keep_above(true);
+        window.set_resizable(false);
+        window.set_default_size(360, -1);
+
+        let hbox = gtk::Box::new(Orientation::Horizontal, 10);
+        hbox.set_border_width(6);
+        window.add(&hbox);
+
+        let cover = Image::new();
+        cover.set_size_request(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+        hbox.add(&cover);
+
+        let vbox = gtk::Box::new(Orientation::Vertical, 4);
+        vbox.set_hexpand(true);
+        hbox.add(&vbox);
+
+        let title_label = Label::new(None);
+        title_label.set_max_width_chars(40);
+        title_label.set_line_wrap(true);
+        vbox.add(&title_label);
+
+        let scale = Scale::new(Orientation::Horizontal, adjustment);
+        scale.set_draw_value(false);
+        vbox.add(&scale);
+
+        let buttons = gtk::Box::new(Orientation::Horizontal, 4);
+        vbox.add(&buttons);
+        let (previous_button, _) = new_button("gtk-media-previous");
+        buttons.add(&previous_button);
+        let (play_button, play_image) = new_button(PLAY_ICON);
+        buttons.add(&play_button);
+        let (next_button, _) = new_button("gtk-media-next");
+        buttons.add(&next_button);
+        let (restore_button, _) = new_button("restore");
+        restore_button.set_tooltip_text("Back to the full window");
+        buttons.add(&restore_button);
+
+        MiniPlayer {
+            window,
+            cover,
+            title_label,
+            scale,
+            previous_button,
+            play_button,
+            play_image,
+            next_button,
+            restore_button,
+            track: RefCell::new(None),
+            stopped: Cell::new(None),
+        }
+    }
+
+    // Called periodically, while shown
+    pub fn update(&self, playlist: &Playlist, stopped: bool) {
+        if !self.window.is_visible() {
+            return;
+        }
+        if self.stopped.replace(Some(stopped)) != Some(stopped) {
+            let icon = if stopped { PLAY_ICON } else { PAUSE_ICON };
+            set_image_icon(&self.play_image, icon);
+        }
+
+        let track = playlist.path();
+        if track == *self.track.borrow() {
+            return;
+        }
+        let (title, artist, _) = track
+            .as_ref()
+            .and_then(|path| playlist.titles(path))
+            .unwrap_or_default();
+        if artist.is_empty() {
+            self.title_label.set_text(&title);
+        } else {
+            self.title_label
+                .set_text(&format!("{} - {}", title, artist));
+        }
+        let thumbnail = track
+            .as_ref()
+            .and_then(|path| playlist.cover(path))
+            .and_then(|cover| {
+                cover
+                    .scale_simple(THUMBNAIL_SIZE, THUMBNAIL_SIZE, INTERP_HYPER)
+                    .ok()
+            });
+        self.cover.set_from_pixbuf(thumbnail.as_ref());
+        *self.track.borrow_mut() = track;
+    }
+}
+
+fn new_button(icon: &str) -> (Button, Image) {
+    let image = Image::new_from_file(format!("assets/{}.png", icon));
+    let button = Button::new();
+    button.set_image(&image);
+    (button, image)
+}
+
+// The mini player takes the place of the main window, where it was last closed
+pub fn show_mini_player(
+    main_window: &ApplicationWindow,
+    mini_player: &MiniPlayer,
+    config: &Arc<Mutex<Config>>,
+) {
+    if let Some((x, y)) = config.lock().unwrap().mini_player_position {
+        mini_player.window.move_(x, y);
+    }
+    mini_player.window.show_all();
+    main_window.hide();
+}
+
+fn restore_main_window(
+    main_window: &ApplicationWindow,
+    mini_player: &MiniPlayer,
+    config: &Arc<Mutex<Config>>,
+) {
+    let mut config = config.lock().unwrap();
+    config.mini_player_position = Some(mini_player.window.get_position());
+    config.save();
+    mini_player.window.hide();
+    main_window.show();
+}
+
+impl App {
+    pub fn connect_mini_player_events(&self) {
+        let buttons = [
+            (
+                &self.mini_player.previous_button,
+                &self.toolbar.previous_button,
+            ),
+            (&self.mini_player.play_button, &self.toolbar.play_button),
+            (&self.mini_player.next_button, &self.toolbar.next_button),
+        ];
+        for &(button, toolbar_button) in &buttons {
+            let toolbar_button = toolbar_button.clone();
+            button.connect_clicked(move |_| toolbar_button.emit_clicked());
+        }
+
+        let playlist = self.playlist.clone();
+        self.mini_player
+            .scale
+            .connect_change_value(move |_, _, value| {
+                playlist.seek(value.max(0.0) as u64);
+                Inhibit(false)
+            });
+
+        let main_window = self.window.clone();
+        let mini_player = self.mini_player.clone();
+        let config = self.config.clone();
+        self.mini_player.restore_button.connect_clicked(move |_| {
+            restore_main_window(&main_window, &mini_player, &config);
+        });
+
+        // Closing the mini player brings the main window back rather than quitting
+        let main_window = self.window.clone();
+        let mini_player = self.mini_player.clone();
+        let config = self.config.clone();
+        self.mini_player.window.connect_delete_event(move |_, _| {
+            restore_main_window(&main_window, &mini_player, &config);
+            Inhibit(true)
+        });
+    }
+}
@@ -2,7 +2,265 @@ use std::io::{Read, Seek, SeekFrom};
 use std::time::Duration;
 
 use simplemad;
-use to_millis;
+
+// Bytes searched for the first frame after the ID3v2 tag
+const MAX_SYNC_SEARCH: usize = 64 * 1024;
+// Largest Layer III frame: 320 kbps at 32 kHz with padding
+const MAX_FRAME_SIZE: usize = 1441;
+
+const XING_FRAMES_FLAG: u32 = 0x1;
+const XING_BYTES_FLAG: u32 = 0x2;
+const XING_TOC_FLAG: u32 = 0x4;
+const XING_QUALITY_FLAG: u32 = 0x8;
+
+const MPEG1_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const MPEG1_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+// Samples of delay added by the libmad synthesis filter, on top of the encoder delay
+const DECODER_DELAY: u32 = 529;
+// Seconds decoded before a seek position, so that the first frame starts before it
+const SEEK_MARGIN: f64 = 0.05;
+
+// Frame count and gapless information stored in the first frame of the file
+// by the encoder (Xing/Info or VBRI header, optionally followed by a LAME tag)
+pub struct VbrHeader {
+    pub frames: u32,
+    pub samples_per_frame: u32,
+    pub sample_rate: u32,
+    pub encoder_delay: u32,
+    pub encoder_padding: u32,
+}
+
+impl VbrHeader {
+    // Samples per channel once the encoder delay and padding are removed
+    pub fn total_samples(&self) -> u64 {
+        (self.frames as u64 * self.samples_per_frame as u64)
+            .saturating_sub(self.encoder_delay as u64 + self.encoder_padding as u64)
+    }
+
+    pub fn duration(&self) -> Duration {
+        let samples = self.total_samples();
+        let rate = self.sample_rate as u64;
+        Duration::new(
+            samples / rate,
+            ((samples % rate) * 1_000_000_000 / rate) as u32,
+        )
+    }
+}
+
+struct FrameHeader {
+    mpeg1: bool,
+    mono: bool,
+    // kbps
+    bitrate: u32,
+    sample_rate: u32,
+    samples_per_frame: u32,
+    frame_size: usize,
+}
+
+fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    // 3: MPEG 1, 2: MPEG 2, 0: MPEG 2.5
+    let version = (bytes[1] >> 3) & 0x3;
+    // Only Layer III
+    let layer = (bytes[1] >> 1) & 0x3;
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    let sample_rate_index = ((bytes[2] >> 2) & 0x3) as usize;
+    if version == 1
+        || layer != 1
+        || bitrate_index == 0
+        || bitrate_index == 15
+        || sample_rate_index == 3
+    {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let (bitrate, sample_rate, samples_per_frame) = match version {
+        3 => (
+            MPEG1_BITRATES[bitrate_index],
+            MPEG1_SAMPLE_RATES[sample_rate_index],
+            1152,
+        ),
+        2 => (
+            MPEG2_BITRATES[bitrate_index],
+            MPEG1_SAMPLE_RATES[sample_rate_index] / 2,
+            576,
+        ),
+        _ => (
+            MPEG2_BITRATES[bitrate_index],
+            MPEG1_SAMPLE_RATES[sample_rate_index] / 4,
+            576,
+        ),
+    };
+    let padding = ((bytes[2] >> 1) & 0x1) as usize;
+    let frame_size = (samples_per_frame / 8 * bitrate * 1000 / sample_rate) as usize + padding;
+
+    Some(FrameHeader {
+        mpeg1,
+        mono: bytes[3] >> 6 == 3,
+        bitrate,
+        sample_rate,
+        samples_per_frame,
+        frame_size,
+    })
+}
+
+fn read_u16(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 8 | bytes[1] as u32
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+// Move the stream past an ID3v2 tag, if any
+fn skip_id3v2<R: Read + Seek>(data: &mut R) -> Option<()> {
+    let start = data.stream_position().ok()?;
+    let mut header = [0; 10];
+    if data.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        data.seek(SeekFrom::Start(start)).ok()?;
+        return Some(());
+    }
+
+    // Synchsafe integer, 7 bits per byte
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, &byte| size << 7 | (byte & 0x7F) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    data.seek(SeekFrom::Start(start + 10 + size + footer))
+        .ok()?;
+    Some(())
+}
+
+fn find_first_frame(bytes: &[u8]) -> Option<(usize, FrameHeader)> {
+    (0..bytes.len()).find_map(|position| {
+        let header = parse_frame_header(&bytes[position..])?;
+        // A second frame right after this one confirms the synchronization
+        let next = position + header.frame_size;
+        if next + 4 <= bytes.len() && parse_frame_header(&bytes[next..]).is_none() {
+            return None;
+        }
+        Some((position, header))
+    })
+}
+
+fn parse_xing(frame: &[u8], header: &FrameHeader) -> Option<VbrHeader> {
+    let offset = 4 + match (header.mpeg1, header.mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = frame.get(offset..)?;
+    if xing.len() < 8 || (&xing[..4] != b"Xing" && &xing[..4] != b"Info") {
+        return None;
+    }
+
+    let flags = read_u32(&xing[4..8]);
+    if flags & XING_FRAMES_FLAG == 0 {
+        return None;
+    }
+    let frames = read_u32(xing.get(8..12)?);
+
+    let mut lame_offset = 12;
+    for &(flag, size) in &[
+        (XING_BYTES_FLAG, 4),
+        (XING_TOC_FLAG, 100),
+        (XING_QUALITY_FLAG, 4),
+    ] {
+        if flags & flag != 0 {
+            lame_offset += size;
+        }
+    }
+
+    // LAME tag: 9 bytes of encoder version, then the delay and padding
+    // are packed as two 12 bits values 21 bytes into the tag
+    let (encoder_delay, encoder_padding) = match xing.get(lame_offset..lame_offset + 24) {
+        Some(lame) if &lame[..4] == b"LAME" || &lame[..4] == b"Lavf" || &lame[..4] == b"Lavc" => {
+            let delay = (lame[21] as u32) << 4 | (lame[22] as u32) >> 4;
+            let padding = (lame[22] as u32 & 0xF) << 8 | lame[23] as u32;
+            (delay, padding)
+        }
+        _ => (0, 0),
+    };
+
+    Some(VbrHeader {
+        frames,
+        samples_per_frame: header.samples_per_frame,
+        sample_rate: header.sample_rate,
+        encoder_delay,
+        encoder_padding,
+    })
+}
+
+fn parse_vbri(frame: &[u8], header: &FrameHeader) -> Option<VbrHeader> {
+    // Always 32 bytes after the frame header, whatever the channel mode
+    let vbri = frame.get(36..54)?;
+    if &vbri[..4] != b"VBRI" {
+        return None;
+    }
+
+    Some(VbrHeader {
+        frames: read_u32(&vbri[14..18]),
+        samples_per_frame: header.samples_per_frame,
+        sample_rate: header.sample_rate,
+        encoder_delay: read_u16(&vbri[6..8]),
+        encoder_padding: 0,
+    })
+}
+
+// The stream position is restored before returning
+pub fn read_vbr_header<R>(mut data: R) -> Option<VbrHeader>
+where
+    R: Read + Seek,
+{
+    let stream_pos = data.stream_position().ok()?;
+    let vbr_header = skip_id3v2(&mut data).and_then(|_| {
+        let mut bytes = Vec::with_capacity(MAX_SYNC_SEARCH + MAX_FRAME_SIZE);
+        data.by_ref()
+            .take((MAX_SYNC_SEARCH + MAX_FRAME_SIZE) as u64)
+            .read_to_end(&mut bytes)
+            .ok()?;
+        let (position, header) = find_first_frame(&bytes)?;
+        let frame = &bytes[position..];
+        parse_xing(frame, &header).or_else(|| parse_vbri(frame, &header))
+    });
+    data.seek(SeekFrom::Start(stream_pos)).ok()?;
+    vbr_header.filter(|header| header.frames > 0 && header.sample_rate > 0)
+}
+
+// Bitrate in kbps, averaged over the file when the encoder wrote a VBR header, and sample rate
+pub fn read_stream_info<R>(mut data: R) -> Option<(u32, u32)>
+where
+    R: Read + Seek,
+{
+    skip_id3v2(&mut data)?;
+    let start = data.stream_position().ok()?;
+    let mut bytes = Vec::with_capacity(MAX_SYNC_SEARCH + MAX_FRAME_SIZE);
+    data.by_ref()
+        .take((MAX_SYNC_SEARCH + MAX_FRAME_SIZE) as u64)
+        .read_to_end(&mut bytes)
+        .ok()?;
+    let (position, header) = find_first_frame(&bytes)?;
+    let frame = &bytes[position..];
+
+    let bitrate = match parse_xing(frame, &header).or_else(|| parse_vbri(frame, &header)) {
+        Some(vbr_header) if vbr_header.total_samples() > 0 => {
+            let end = data.seek(SeekFrom::End(0)).ok()?;
+            let audio_bytes = end.saturating_sub(start + position as u64);
+            (audio_bytes * 8 * vbr_header.sample_rate as u64 / vbr_header.total_samples() / 1000)
+                as u32
+        }
+        _ => header.bitrate,
+    };
+    Some((bitrate, header.sample_rate))
+}
 
 fn is_mp3<R>(mut data: R) -> bool
 where
@@ -29,25 +287,35 @@ fn next_frame<R: Read>(decoder: &mut simplemad::Decoder<R>) -> simplemad::Frame
         })
 }
 
-fn next_sample<R: Read>(decoder: &mut Mp3Decoder<R>) -> Option<i16> {
-    if decoder.current_frame.samples[0].len() == 0 {
+fn load_next_frame<R: Read>(decoder: &mut Mp3Decoder<R>) {
+    decoder.current_frame = next_frame(&mut decoder.reader);
+    decoder.current_frame_channel = 0;
+    decoder.current_frame_sample_pos = 0;
+}
+
+// Drop the first samples of the stream (encoder and decoder delay)
+fn skip_samples<R: Read>(decoder: &mut Mp3Decoder<R>, mut count: usize) {
+    while !decoder.current_frame.samples[0].is_empty() {
+        let available = decoder.current_frame.samples[0].len() - decoder.current_frame_sample_pos;
+        if count < available {
+            decoder.current_frame_sample_pos += count;
+            return;
+        }
+        count -= available;
+        load_next_frame(decoder);
+    }
+}
+
+fn next_sample<R: Read>(decoder: &mut Mp3Decoder<R>) -> Option<f32> {
+    if decoder.current_frame.samples[0].len() == 0 || decoder.remaining_samples == Some(0) {
         return None;
     }
 
-    // Getting the sample and converting it form fixed step to i16
+    // Getting the sample and converting it from fixed point to float, full scale being 1.0.
+    // Quantization to the output format is left to the player.
     let sample = decoder.current_frame.samples[decoder.current_frame_channel]
         [decoder.current_frame_sample_pos];
-    let sample = sample.to_i32() + (1 << (28 - 16));
-    let sample = if sample >= 0x10000000 {
-        0x10000000 - 1
-    } else if sample <= -0x10000000 {
-        -0x10000000
-    } else {
-        sample
-    };
-
-    let sample = sample >> (28 + 1 - 16);
-    let sample = sample as i16;
+    let sample = sample.to_f32();
 
     decoder.current_frame_channel += 1;
 
@@ -57,15 +325,16 @@ fn next_sample<R: Read>(decoder: &mut Mp3Decoder<R>) -> Option<i16> {
 
     decoder.current_frame_channel = 0;
     decoder.current_frame_sample_pos += 1;
+    decoder.position += 1;
+    if let Some(ref mut remaining_samples) = decoder.remaining_samples {
+        *remaining_samples -= 1;
+    }
 
     if decoder.current_frame_sample_pos < decoder.current_frame.samples[0].len() {
         return Some(sample);
     }
 
-    decoder.current_frame = next_frame(&mut decoder.reader);
-    decoder.current_frame_channel = 0;
-    decoder.current_frame_sample_pos = 0;
-    decoder.current_time += to_millis(decoder.current_frame.duration);
+    load_next_frame(decoder);
 
     return Some(sample);
 }
@@ -78,37 +347,93 @@ where
     current_frame: simplemad::Frame,
     current_frame_channel: usize,
     current_frame_sample_pos: usize,
-    current_time: u64,
+    // Samples per channel returned so far, the encoder and decoder delay excluded
+    position: u64,
+    // Of the first frame, the frame after the end of the stream being empty
+    sample_rate: u32,
+    // Samples per channel left before the encoder padding, when known from the LAME tag
+    remaining_samples: Option<u64>,
 }
 
 impl<R> Mp3Decoder<R>
 where
     R: Read + Seek,
 {
-    pub fn new(mut data: R) -> Result<Mp3Decoder<R>, R> {
+    pub fn new(data: R) -> Result<Mp3Decoder<R>, R> {
+        Mp3Decoder::new_at(data, 0)
+    }
+
+    // Start at `millis` of media time. The frames before are skipped from their headers,
+    // without being decoded.
+    pub fn new_at(mut data: R, millis: u64) -> Result<Mp3Decoder<R>, R> {
         if !is_mp3(data.by_ref()) {
             return Err(data);
         }
 
-        let mut reader = simplemad::Decoder::decode(data).unwrap();
+        let vbr_header = read_vbr_header(data.by_ref());
+        // Samples of the stream before the media: the frame holding the VBR header, which
+        // only contains silence, then the encoder and decoder delay
+        let (offset, trimmed) = match vbr_header {
+            Some(ref header) if header.encoder_delay > 0 || header.encoder_padding > 0 => (
+                header.samples_per_frame + header.encoder_delay + DECODER_DELAY,
+                true,
+            ),
+            Some(ref header) => (header.samples_per_frame, false),
+            None => (0, false),
+        };
+
+        let mut reader = if millis > 0 {
+            let offset = vbr_header
+                .as_ref()
+                .map(|header| offset as f64 / header.sample_rate as f64)
+                .unwrap_or(0.0);
+            let start = (millis as f64 / 1000.0 + offset - SEEK_MARGIN).max(0.0);
+            simplemad::Decoder::decode_interval(data, Duration::from_secs_f64(start), Duration::MAX)
+        } else {
+            simplemad::Decoder::decode(data)
+        }
+        .unwrap();
         let current_frame = next_frame(&mut reader);
-        let current_time = to_millis(current_frame.duration);
+        let sample_rate = current_frame.sample_rate;
 
-        Ok(Mp3Decoder {
+        // Media samples before the first decoded frame, negative within the offset
+        let frame_start = (current_frame.position.as_secs_f64() * sample_rate as f64).round()
+            as i64
+            - offset as i64;
+        let target = (millis * sample_rate as u64 / 1000) as i64;
+
+        let mut decoder = Mp3Decoder {
             reader,
             current_frame,
             current_frame_channel: 0,
             current_frame_sample_pos: 0,
-            current_time,
-        })
+            position: 0,
+            sample_rate,
+            remaining_samples: None,
+        };
+
+        skip_samples(&mut decoder, (target - frame_start).max(0) as usize);
+        decoder.position = target.max(frame_start) as u64;
+        if trimmed {
+            decoder.remaining_samples =
+                vbr_header.map(|header| header.total_samples().saturating_sub(decoder.position));
+        }
+
+        Ok(decoder)
     }
 
+    // Media time of the next sample, in milliseconds
     pub fn current_time(&self) -> u64 {
-        self.current_time
+        self.position * 1000 / self.samples_rate() as u64
     }
 
     pub fn samples_rate(&self) -> u32 {
-        self.current_frame.sample_rate
+        self.sample_rate
+    }
+
+    // Samples of a frame are interleaved, one per channel
+    pub fn channels(&self) -> usize {
+        self.current_frame.samples.len()
     }
 
     pub fn compute_duration(mut data: R) -> Option<Duration> {
@@ -116,7 +441,11 @@ where
             return None;
         }
 
-        // decode_headers, only frame duration is needed
+        if let Some(vbr_header) = read_vbr_header(data.by_ref()) {
+            return Some(vbr_header.duration());
+        }
+
+        // No header from the encoder: decode_headers, only frame duration is needed
         let decoder = simplemad::Decoder::decode_headers(data).unwrap();
         Some(
             decoder
@@ -133,9 +462,9 @@ impl<R> Iterator for Mp3Decoder<R>
 where
     R: Read,
 {
-    type Item = i16;
+    type Item = f32;
 
-    fn next(&mut self) -> Option<i16> {
+    fn next(&mut self) -> Option<f32> {
         next_sample(self)
     }
 
@@ -143,3 +472,140 @@ where
         (self.current_frame.samples[0].len(), None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_frame_header, parse_vbri, parse_xing, read_vbr_header};
+    use std::io::Cursor;
+
+    // MPEG 1 Layer III, 128 kbps, 44.1 kHz, joint stereo
+    const STEREO: [u8; 4] = [0xFF, 0xFB, 0x90, 0x40];
+    // Same, mono
+    const MONO: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC0];
+
+    // Header followed by zeros up to the frame size
+    fn frame(header: [u8; 4]) -> Vec<u8> {
+        let size = parse_frame_header(&header).unwrap().frame_size;
+        let mut frame = header.to_vec();
+        frame.resize(size, 0);
+        frame
+    }
+
+    fn put(frame: &mut [u8], offset: usize, bytes: &[u8]) {
+        frame[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // Every field of the Xing header, then a LAME tag with a delay of 576 and a padding
+    // of 1000 samples
+    fn xing_frame() -> Vec<u8> {
+        let mut frame = frame(STEREO);
+        put(&mut frame, 36, b"Xing");
+        put(&mut frame, 40, &[0, 0, 0, 0xF]);
+        put(&mut frame, 44, &1000u32.to_be_bytes());
+        let lame = 36 + 8 + 4 + 4 + 100 + 4;
+        put(&mut frame, lame, b"LAME3.100");
+        put(&mut frame, lame + 21, &[0x24, 0x03, 0xE8]);
+        frame
+    }
+
+    #[test]
+    fn frame_headers() {
+        let header = parse_frame_header(&STEREO).unwrap();
+        assert!(header.mpeg1 && !header.mono);
+        assert_eq!(header.bitrate, 128);
+        assert_eq!(header.sample_rate, 44100);
+        assert_eq!(header.samples_per_frame, 1152);
+        assert_eq!(header.frame_size, 417);
+        assert!(parse_frame_header(&MONO).unwrap().mono);
+
+        // Padding bit
+        let header = parse_frame_header(&[0xFF, 0xFB, 0x92, 0x40]).unwrap();
+        assert_eq!(header.frame_size, 418);
+
+        // MPEG 2, 64 kbps, 22.05 kHz
+        let header = parse_frame_header(&[0xFF, 0xF3, 0x80, 0x40]).unwrap();
+        assert!(!header.mpeg1);
+        assert_eq!(header.bitrate, 64);
+        assert_eq!(header.sample_rate, 22050);
+        assert_eq!(header.samples_per_frame, 576);
+        assert_eq!(header.frame_size, 208);
+
+        // MPEG 2.5, 8 kbps, 11.025 kHz
+        let header = parse_frame_header(&[0xFF, 0xE3, 0x10, 0x40]).unwrap();
+        assert_eq!(header.bitrate, 8);
+        assert_eq!(header.sample_rate, 11025);
+    }
+
+    #[test]
+    fn invalid_frame_headers() {
+        // No sync, reserved version, Layer II, free and bad bitrates, reserved sample rate
+        assert!(parse_frame_header(&[0xFE, 0xFB, 0x90, 0x40]).is_none());
+        assert!(parse_frame_header(&[0xFF, 0xEB, 0x90, 0x40]).is_none());
+        assert!(parse_frame_header(&[0xFF, 0xFD, 0x90, 0x40]).is_none());
+        assert!(parse_frame_header(&[0xFF, 0xFB, 0x00, 0x40]).is_none());
+        assert!(parse_frame_header(&[0xFF, 0xFB, 0xF0, 0x40]).is_none());
+        assert!(parse_frame_header(&[0xFF, 0xFB, 0x9C, 0x40]).is_none());
+        assert!(parse_frame_header(&STEREO[..3]).is_none());
+    }
+
+    #[test]
+    fn xing_with_lame_tag() {
+        let frame = xing_frame();
+        let header = parse_frame_header(&frame).unwrap();
+        let vbr_header = parse_xing(&frame, &header).unwrap();
+        assert_eq!(vbr_header.frames, 1000);
+        assert_eq!(vbr_header.encoder_delay, 576);
+        assert_eq!(vbr_header.encoder_padding, 1000);
+        assert_eq!(vbr_header.total_samples(), 1000 * 1152 - 576 - 1000);
+        assert_eq!(vbr_header.duration().as_millis(), 26086);
+    }
+
+    #[test]
+    fn info_without_lame_tag() {
+        // Mono: the header starts 17 bytes after the frame header, only the frame count set
+        let mut frame = frame(MONO);
+        put(&mut frame, 21, b"Info");
+        put(&mut frame, 25, &[0, 0, 0, 1]);
+        put(&mut frame, 29, &500u32.to_be_bytes());
+        let header = parse_frame_header(&frame).unwrap();
+        let vbr_header = parse_xing(&frame, &header).unwrap();
+        assert_eq!(vbr_header.frames, 500);
+        assert_eq!(vbr_header.encoder_delay, 0);
+        assert_eq!(vbr_header.encoder_padding, 0);
+
+        // Without the frame count, the header is of no use
+        put(&mut frame, 25, &[0, 0, 0, 0]);
+        assert!(parse_xing(&frame, &header).is_none());
+        assert!(parse_vbri(&frame, &header).is_none());
+    }
+
+    #[test]
+    fn vbri() {
+        let mut frame = frame(STEREO);
+        put(&mut frame, 36, b"VBRI");
+        put(&mut frame, 40, &[0, 1]);
+        put(&mut frame, 42, &[0x02, 0x40]);
+        put(&mut frame, 50, &2000u32.to_be_bytes());
+        let header = parse_frame_header(&frame).unwrap();
+        assert!(parse_xing(&frame, &header).is_none());
+        let vbr_header = parse_vbri(&frame, &header).unwrap();
+        assert_eq!(vbr_header.frames, 2000);
+        assert_eq!(vbr_header.encoder_delay, 576);
+        assert_eq!(vbr_header.encoder_padding, 0);
+    }
+
+    #[test]
+    fn vbr_header_after_id3v2_tag() {
+        // Tag of 20 bytes, then the header frame and a second frame confirming the sync
+        let mut bytes = vec![b'I', b'D', b'3', 4, 0, 0, 0, 0, 0, 20];
+        bytes.extend(vec![0; 20]);
+        bytes.extend(xing_frame());
+        bytes.extend(frame(STEREO));
+
+        let mut data = Cursor::new(bytes);
+        let vbr_header = read_vbr_header(&mut data).unwrap();
+        assert_eq!(vbr_header.frames, 1000);
+        assert_eq!(vbr_header.encoder_delay, 576);
+        assert_eq!(data.position(), 0);
+    }
+}
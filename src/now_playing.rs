@@ -0,0 +1,228 @@
+use cairo::Context;
+use gdk::ContextExt;
+use gdk_pixbuf::Pixbuf;
+use gtk::{
+    Align, ContainerExt, DrawingArea, Inhibit, Label, LabelExt, Orientation, Stack, StackExt,
+    WidgetExt,
+};
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use covers::OriginalCoverLoader;
+use millis_to_minutes;
+use playlist::Playlist;
+use App;
+
+const MIN_COVER_SIZE: i32 = 200;
+const MAX_COVER_SIZE: i32 = 1024;
+
+// Names of the children of the views stack
+pub const PLAYLIST_VIEW: &str = "playlist";
+pub const NOW_PLAYING_VIEW: &str = "now-playing";
+
+// Shown instead of the playlist: the cover as large as the window allows, next to what is
+// playing and what comes next
+pub struct NowPlaying {
+    container: gtk::Box,
+    cover_area: DrawingArea,
+    cover: RefCell<Option<Pixbuf>>,
+    // Track of the cover, only requested while the view is shown
+    cover_track: RefCell<Option<String>>,
+    cover_loader: OriginalCoverLoader,
+    title_label: Label,
+    artist_label: Label,
+    album_label: Label,
+    time_label: Label,
+    next_label: Label,
+    // Current and next tracks the labels are about
+    tracks: RefCell<(Option<String>, Option<String>)>,
+}
+
+impl NowPlaying {
+    pub fn new() -> Self {
+        let container = gtk::Box::new(Orientation::Horizontal, 20);
+        container.set_border_width(20);
+
+        let cover_area = DrawingArea::new();
+        cover_area.set_size_request(MIN_COVER_SIZE, MIN_COVER_SIZE);
+        cover_area.set_hexpand(true);
+        cover_area.set_vexpand(true);
+        container.add(&cover_area);
+
+        let vbox = gtk::Box::new(Orientation::Vertical, 10);
+        vbox.set_valign(Align::Center);
+        vbox.set_size_request(300, -1);
+        container.add(&vbox);
+
+        let new_label = || {
+            let label = Label::new(None);
+            label.set_halign(Align::Start);
+            label.set_line_wrap(true);
+            vbox.add(&label);
+            label
+        };
+        let title_label = new_label();
+        let artist_label = new_label();
+        let album_label = new_label();
+        let time_label = new_label();
+        time_label.set_margin_top(10);
+        let next_label = new_label();
+        next_label.set_margin_top(20);
+
+        NowPlaying {
+            container,
+            cover_area,
+            cover: RefCell::new(None),
+            cover_track: RefCell::new(None),
+            cover_loader: OriginalCoverLoader::new(MAX_COVER_SIZE),
+            title_label,
+            artist_label,
+            album_label,
+            time_label,
+            next_label,
+            tracks: RefCell::new((None, None)),
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    // Called periodically, the labels and cover only change with the tracks
+    pub fn update(&self, playlist: &Playlist, current_time: u64, duration: Option<u64>) {
+        let tracks = (playlist.path(), playlist.next_path());
+        if tracks != *self.tracks.borrow() {
+            self.set_tracks(playlist, &tracks);
+            *self.tracks.borrow_mut() = tracks;
+        }
+
+        // The cover of another track is dropped even while hidden
+        let current = self.tracks.borrow().0.clone();
+        if current != *self.cover_track.borrow() {
+            let shown = self.container.get_mapped();
+            if shown || self.cover.borrow().is_some() {
+                *self.cover.borrow_mut() = None;
+                if shown {
+                    if let Some(ref path) = current {
+                        self.cover_loader.request(path);
+                    }
+                    *self.cover_track.borrow_mut() = current;
+                }
+                self.cover_area.queue_draw();
+            }
+        }
+        if let Some((path, cover)) = self.cover_loader.receive() {
+            if self.cover_track.borrow().as_ref() == Some(&path) {
+                *self.cover.borrow_mut() = cover;
+                self.cover_area.queue_draw();
+            }
+        }
+
+        let text = match (&self.tracks.borrow().0, duration) {
+            (None, _) => String::new(),
+            (Some(_), Some(duration)) => format!(
+                "{} / -{}",
+                millis_to_minutes(current_time),
+                millis_to_minutes(duration.saturating_sub(current_time))
+            ),
+            (Some(_), None) => millis_to_minutes(current_time),
+        };
+        self.time_label.set_text(&text);
+    }
+
+    fn set_tracks(&self, playlist: &Playlist, tracks: &(Option<String>, Option<String>)) {
+        let (current, next) = tracks;
+
+        let (title, artist, album) = current
+            .as_ref()
+            .map(|path| titles(playlist, path))
+            .unwrap_or_default();
+        self.title_label.set_markup(&format!(
+            "<span size=\"xx-large\" weight=\"bold\">{}</span>",
+            escape(&title)
+        ));
+        self.artist_label.set_markup(&format!(
+            "<span size=\"x-large\">{}</span>",
+            escape(&artist)
+        ));
+        self.album_label.set_markup(&format!(
+            "<span size=\"large\" style=\"italic\">{}</span>",
+            escape(&album)
+        ));
+
+        let next_text = next
+            .as_ref()
+            .map(|path| {
+                let (title, artist, _) = titles(playlist, path);
+                if artist.is_empty() {
+                    format!("Next: {}", title)
+                } else {
+                    format!("Next: {} - {}", title, artist)
+                }
+            })
+            .unwrap_or_default();
+        self.next_label.set_text(&next_text);
+    }
+
+    // Scaled to fit the area, keeping its proportions, and centered
+    fn draw(&self, area: &DrawingArea, context: &Context) {
+        let cover = self.cover.borrow();
+        let cover = match *cover {
+            Some(ref cover) => cover,
+            None => return,
+        };
+        let width = area.get_allocated_width() as f64;
+        let height = area.get_allocated_height() as f64;
+        let cover_width = cover.get_width().max(1) as f64;
+        let cover_height = cover.get_height().max(1) as f64;
+        let scale = (width / cover_width).min(height / cover_height);
+
+        context.translate(
+            (width - cover_width * scale) / 2.0,
+            (height - cover_height * scale) / 2.0,
+        );
+        context.scale(scale, scale);
+        context.set_source_pixbuf(cover, 0.0, 0.0);
+        context.paint();
+    }
+}
+
+// The file name stands for a missing title
+fn titles(playlist: &Playlist, path: &str) -> (String, String, String) {
+    let (mut title, artist, album) = playlist.titles(path).unwrap_or_default();
+    if title.is_empty() {
+        title = Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+    }
+    (title, artist, album)
+}
+
+// Tags are shown as text in the markup
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn set_view(views: &Stack, now_playing: bool) {
+    if now_playing {
+        views.set_visible_child_name(NOW_PLAYING_VIEW);
+    } else {
+        views.set_visible_child_name(PLAYLIST_VIEW);
+    }
+}
+
+impl App {
+    pub fn connect_now_playing_events(&self) {
+        let now_playing = self.now_playing.clone();
+        self.now_playing
+            .cover_area
+            .connect_draw(move |area, context| {
+                now_playing.draw(area, context);
+                Inhibit(false)
+            });
+    }
+}
@@ -0,0 +1,319 @@
+use libc::{c_char, c_void};
+use libpulse_sys::{
+    pa_context, pa_context_connect, pa_context_disconnect, pa_context_get_sink_info_by_name,
+    pa_context_get_state, pa_context_new, pa_context_state_t, pa_context_unref, pa_mainloop,
+    pa_mainloop_free, pa_mainloop_get_api, pa_mainloop_iterate, pa_mainloop_new,
+    pa_operation_get_state, pa_operation_state_t, pa_operation_unref, pa_sample_format_t,
+    pa_sink_info, PA_CONTEXT_NOAUTOSPAWN,
+};
+use pulse_simple::Playback;
+
+use std::ptr;
+use std::time::Instant;
+
+const S16_SCALE: f32 = 32768.0;
+const S24_SCALE: f32 = 8388608.0;
+
+// Highest rate offered in the preferences
+pub const MAX_RATE: u32 = 96000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    // 16 bits with TPDF dither
+    S16,
+    // 24 bits with TPDF dither, in the most significant bytes of 32 bits samples
+    S24,
+    F32,
+}
+
+impl SampleFormat {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SampleFormat::S16 => "s16",
+            SampleFormat::S24 => "s24",
+            SampleFormat::F32 => "f32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SampleFormat> {
+        match name {
+            "s16" => Some(SampleFormat::S16),
+            "s24" => Some(SampleFormat::S24),
+            "f32" => Some(SampleFormat::F32),
+            _ => None,
+        }
+    }
+
+    // The closest format keeping the precision of the sink
+    fn of_sink(format: pa_sample_format_t) -> SampleFormat {
+        match format {
+            pa_sample_format_t::F32le | pa_sample_format_t::F32be => SampleFormat::F32,
+            pa_sample_format_t::S24le
+            | pa_sample_format_t::S24be
+            | pa_sample_format_t::S24_32le
+            | pa_sample_format_t::S24_32be
+            | pa_sample_format_t::S32le
+            | pa_sample_format_t::S32be => SampleFormat::S24,
+            _ => SampleFormat::S16,
+        }
+    }
+}
+
+extern "C" fn sink_info_received(
+    _: *mut pa_context,
+    info: *const pa_sink_info,
+    _: i32,
+    format: *mut c_void,
+) {
+    // Called a last time without the info, at the end of the list
+    if !info.is_null() {
+        unsafe {
+            *(format as *mut Option<pa_sample_format_t>) = Some((*info).sample_spec.format);
+        }
+    }
+}
+
+unsafe fn wait_until_ready(mainloop: *mut pa_mainloop, context: *mut pa_context) -> bool {
+    loop {
+        match pa_context_get_state(context) {
+            pa_context_state_t::Ready => return true,
+            pa_context_state_t::Failed | pa_context_state_t::Terminated => return false,
+            _ => {
+                if pa_mainloop_iterate(mainloop, 1, ptr::null_mut()) < 0 {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+unsafe fn default_sink_format() -> Option<pa_sample_format_t> {
+    let mainloop = pa_mainloop_new();
+    if mainloop.is_null() {
+        return None;
+    }
+    let context = pa_context_new(
+        pa_mainloop_get_api(mainloop),
+        b"MP3\0".as_ptr() as *const c_char,
+    );
+    let mut format = None;
+    if !context.is_null() {
+        let connected =
+            pa_context_connect(context, ptr::null(), PA_CONTEXT_NOAUTOSPAWN, ptr::null()) >= 0;
+        if connected && wait_until_ready(mainloop, context) {
+            let operation = pa_context_get_sink_info_by_name(
+                context,
+                b"@DEFAULT_SINK@\0".as_ptr() as *const c_char,
+                Some(sink_info_received),
+                &mut format as *mut Option<pa_sample_format_t> as *mut c_void,
+            );
+            if !operation.is_null() {
+                while let pa_operation_state_t::Running = pa_operation_get_state(operation) {
+                    if pa_mainloop_iterate(mainloop, 1, ptr::null_mut()) < 0 {
+                        break;
+                    }
+                }
+                pa_operation_unref(operation);
+            }
+        }
+        pa_context_disconnect(context);
+        pa_context_unref(context);
+    }
+    pa_mainloop_free(mainloop);
+    format
+}
+
+// Format played when none is chosen in the preferences, following the default sink of the
+// server. 16 bits when the server cannot be asked.
+pub fn sink_format() -> SampleFormat {
+    unsafe { default_sink_format() }
+        .map(SampleFormat::of_sink)
+        .unwrap_or(SampleFormat::S16)
+}
+
+// Triangular probability density function dither: the sum of two uniform noises of one
+// least significant bit each, decorrelating the quantization error from the signal
+struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    fn new() -> Self {
+        Dither { state: 0x9E37_79B9 }
+    }
+
+    // xorshift32, uniform in [-0.5, 0.5)
+    fn uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / 4_294_967_296.0 - 0.5
+    }
+
+    fn triangular(&mut self) -> f32 {
+        self.uniform() + self.uniform()
+    }
+
+    // Round the scaled sample to an integer, clipping to full scale
+    fn quantize(&mut self, sample: f32, scale: f32) -> i32 {
+        let sample = (sample * scale + self.triangular()).round();
+        sample.clamp(-scale, scale - 1.0) as i32
+    }
+
+    // 24 bits in the most significant bytes of a 32 bits sample
+    fn quantize_s24(&mut self, sample: f32) -> i32 {
+        self.quantize(sample, S24_SCALE) << 8
+    }
+}
+
+enum Stream {
+    S16(Playback<[i16; 2]>, Vec<[i16; 2]>),
+    S24(Playback<[i32; 2]>, Vec<[i32; 2]>),
+    F32(Playback<[f32; 2]>, Vec<[f32; 2]>),
+}
+
+// PulseAudio stream converting the float samples of the player to the chosen format.
+// PulseAudio itself adapts the stream to whatever the sink uses, so the format only
+// decides how much precision is handed over.
+pub struct Output {
+    format: SampleFormat,
+    rate: u32,
+    stream: Stream,
+    dither: Dither,
+    // Frames written since the stream last started playing, and when it did
+    frames: u64,
+    started: Instant,
+}
+
+impl Output {
+    pub fn new(format: SampleFormat, rate: u32) -> Self {
+        let stream = match format {
+            SampleFormat::S16 => {
+                Stream::S16(Playback::new("MP3", "MP3 Playback", None, rate), Vec::new())
+            }
+            SampleFormat::S24 => {
+                Stream::S24(Playback::new("MP3", "MP3 Playback", None, rate), Vec::new())
+            }
+            SampleFormat::F32 => {
+                Stream::F32(Playback::new("MP3", "MP3 Playback", None, rate), Vec::new())
+            }
+        };
+
+        Output {
+            format,
+            rate,
+            stream,
+            dither: Dither::new(),
+            frames: 0,
+            started: Instant::now(),
+        }
+    }
+
+    // Frames written but not played yet. The stream plays in real time from its first write
+    // until it runs out of frames, the writes blocking while its buffer is full.
+    pub fn queued(&self) -> u64 {
+        let elapsed = self.started.elapsed();
+        let played = elapsed.as_secs() * self.rate as u64
+            + elapsed.subsec_nanos() as u64 * self.rate as u64 / 1_000_000_000;
+        self.frames.saturating_sub(played)
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn write(&mut self, buffer: &[[f32; 2]]) {
+        if self.queued() == 0 {
+            self.frames = 0;
+            self.started = Instant::now();
+        }
+        self.frames += buffer.len() as u64;
+
+        let dither = &mut self.dither;
+        match self.stream {
+            Stream::S16(ref playback, ref mut samples) => {
+                samples.clear();
+                samples.extend(buffer.iter().map(|frame| {
+                    [
+                        dither.quantize(frame[0], S16_SCALE) as i16,
+                        dither.quantize(frame[1], S16_SCALE) as i16,
+                    ]
+                }));
+                playback.write(samples);
+            }
+            Stream::S24(ref playback, ref mut samples) => {
+                samples.clear();
+                samples.extend(
+                    buffer.iter().map(|frame| {
+                        [dither.quantize_s24(frame[0]), dither.quantize_s24(frame[1])]
+                    }),
+                );
+                playback.write(samples);
+            }
+            Stream::F32(ref playback, ref mut samples) => {
+                samples.clear();
+                samples.extend(
+                    buffer
+                        .iter()
+                        .map(|frame| [frame[0].clamp(-1.0, 1.0), frame[1].clamp(-1.0, 1.0)]),
+                );
+                playback.write(samples);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dither, S16_SCALE};
+
+    #[test]
+    fn triangular_dither() {
+        let mut dither = Dither::new();
+        let noise: Vec<f32> = (0..100_000).map(|_| dither.triangular()).collect();
+        assert!(noise.iter().all(|&noise| noise > -1.0 && noise < 1.0));
+        let mean = noise.iter().sum::<f32>() / noise.len() as f32;
+        assert!(mean.abs() < 0.01);
+        // Variance of the sum of two uniform noises of one step: 1/6
+        let variance = noise.iter().map(|noise| noise * noise).sum::<f32>() / noise.len() as f32;
+        assert!((variance - 1.0 / 6.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn quantize_clips_to_full_scale() {
+        let mut dither = Dither::new();
+        for _ in 0..1000 {
+            assert_eq!(dither.quantize(1.5, S16_SCALE), 32767);
+            assert_eq!(dither.quantize(-1.5, S16_SCALE), -32768);
+            let sample = dither.quantize(0.5, S16_SCALE);
+            assert!((16383..=16385).contains(&sample));
+        }
+    }
+
+    #[test]
+    fn dither_keeps_levels_below_one_step() {
+        // A quarter of a step, rounded to 0 without dither, is kept on average
+        let mut dither = Dither::new();
+        let count = 100_000;
+        let sum: i32 = (0..count)
+            .map(|_| dither.quantize(0.25 / S16_SCALE, S16_SCALE))
+            .sum();
+        assert!((sum as f32 / count as f32 - 0.25).abs() < 0.02);
+    }
+
+    #[test]
+    fn s24_packing() {
+        let mut dither = Dither::new();
+        for _ in 0..1000 {
+            assert_eq!(dither.quantize_s24(1.0), 0x7FFF_FF00);
+            assert_eq!(dither.quantize_s24(-1.001), i32::MIN);
+            let sample = dither.quantize_s24(0.5);
+            assert_eq!(sample & 0xFF, 0);
+            assert!(((sample >> 8) - 0x40_0000).abs() <= 1);
+        }
+    }
+}
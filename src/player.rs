@@ -1,7 +1,14 @@
 use self::Action::*;
+use config::Config;
 use crossbeam::sync::SegQueue; // lock-free queue, atomic ops
+use crossfade::Crossfade;
+use equalizer::Equalizer;
 use mp3::Mp3Decoder;
-use pulse_simple::Playback;
+use output::{sink_format, Output};
+use replaygain::ReplayGain;
+use resampler::Resampler;
+use ring::SampleRing;
+use scrobbler::Scrobbler;
 use std::cell::Cell;
 use std::fs::File;
 use std::io::BufReader;
@@ -9,12 +16,21 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::Duration;
+use stretch::{pitch_ratio, TimeStretch};
+use visualizer::RING_CAPACITY;
 
 const BUFFER_SIZE: usize = 1000;
+// Stereo samples written at once
+const WRITE_SIZE: usize = BUFFER_SIZE / 2;
 const DEFAULT_RATE: u32 = 44100;
+// Longest A-B loop whose samples are kept to be played again, the others seek every time
+const MAX_LOOP_SECONDS: usize = 30;
 
 enum Action {
     Load(PathBuf),
+    Queue(Option<(PathBuf, bool)>),
+    // Milliseconds of media time in the current track
+    Seek(u64),
     Stop,
 }
 
@@ -39,16 +55,20 @@ pub struct Player {
     app_state: Arc<Mutex<super::State>>,
     event_loop: EventLoop,
     paused: Cell<bool>,
+    // Copy of the samples played, for the visualizer
+    tap: Arc<SampleRing>,
 }
 
 impl Player {
-    pub(crate) fn new(app_state: Arc<Mutex<super::State>>) -> Self {
+    pub(crate) fn new(app_state: Arc<Mutex<super::State>>, config: Arc<Mutex<Config>>) -> Self {
         let event_loop = EventLoop::new();
+        let tap = Arc::new(SampleRing::new(RING_CAPACITY));
 
         {
             let app_state = app_state.clone();
             let event_loop = event_loop.clone();
             let condition_variable = event_loop.condition_variable.clone();
+            let tap = tap.clone();
             thread::spawn(move || {
                 let block = || {
                     let (ref lock, ref condition_variable) = *condition_variable;
@@ -59,39 +79,157 @@ impl Player {
                     }
                 };
 
-                let mut buffer = [[0; 2]; BUFFER_SIZE];
-                let mut playback = Playback::new("MP3", "MP3 Playback", None, DEFAULT_RATE);
-                let mut source = None;
+                let mut buffer = Vec::with_capacity(WRITE_SIZE);
+                let mut next_buffer = Vec::with_capacity(WRITE_SIZE);
+                // Asked again for each track, the sound card may have changed
+                let mut sink = sink_format();
+                let mut output = Output::new(
+                    config.lock().unwrap().output_format.unwrap_or(sink),
+                    DEFAULT_RATE,
+                );
+                let mut source: Option<Track> = None;
+                // Pre-opened track played right after the current one, and whether
+                // it belongs to the same album
+                let mut next: Option<(Track, bool)> = None;
+                let mut crossfade: Option<Crossfade> = None;
+                let mut scrobbler = Scrobbler::new(app_state.clone());
+                // Only the last of the seeks queued, as while dragging the slider, is done
+                let mut seek_to = None;
 
                 loop {
                     if let Some(action) = event_loop.queue.try_pop() {
                         match action {
                             Load(path) => {
-                                let file = File::open(path).unwrap();
-                                source = Some(Mp3Decoder::new(BufReader::new(file)).unwrap());
-                                let rate = source
-                                    .as_ref()
-                                    .map(|source| source.samples_rate())
-                                    .unwrap_or(DEFAULT_RATE);
-                                playback = Playback::new("MP3", "MP3 Playback", None, rate);
-                                app_state.lock().unwrap().stopped = false;
+                                crossfade = None;
+                                seek_to = None;
+                                let track = open(&path);
+                                sink = sink_format();
+                                match track {
+                                    Some(ref track) => scrobbler.start(&track.path),
+                                    None => scrobbler.finish(),
+                                }
+                                let mut app_state = app_state.lock().unwrap();
+                                app_state.track_changed = None;
+                                app_state.ab_loop = (None, None);
+                                // Removed or unreadable since it was added
+                                let track = match track {
+                                    Some(track) => track,
+                                    None => {
+                                        source = None;
+                                        next = None;
+                                        app_state.stopped = true;
+                                        continue;
+                                    }
+                                };
+                                let rate = output_rate(&config, &track);
+                                let format = config.lock().unwrap().output_format.unwrap_or(sink);
+                                output = Output::new(format, rate);
+                                source = Some(track);
+                                app_state.stopped = false;
+                            }
+                            Queue(queued) => {
+                                next = queued.and_then(|(path, same_album)| {
+                                    Some((open(&path)?, same_album))
+                                });
+                                crossfade = None;
+                            }
+                            Seek(millis) => {
+                                seek_to = Some(millis);
+                                crossfade = None;
                             }
                             Stop => {
+                                seek_to = None;
                                 source = None;
+                                next = None;
+                                crossfade = None;
+                                scrobbler.finish();
                             }
                         }
+                    } else if let Some(millis) = seek_to.take() {
+                        if let Some(ref mut source) = source {
+                            source.seek(millis);
+                            app_state.lock().unwrap().current_time = source.current_time();
+                        }
                     } else if *event_loop.playing.lock().unwrap() {
+                        // The format and the fixed rate can be changed in the preferences
+                        // while playing
+                        let (format, rate) = {
+                            let config = config.lock().unwrap();
+                            let rate = match config.output_rate {
+                                0 => output.rate(),
+                                rate => rate,
+                            };
+                            (config.output_format.unwrap_or(sink), rate)
+                        };
+                        if format != output.format() || rate != output.rate() {
+                            output = Output::new(format, rate);
+                        }
+
+                        let ab_loop = match app_state.lock().unwrap().ab_loop {
+                            (Some(start), Some(end)) if start < end => Some((start, end)),
+                            _ => None,
+                        };
+
+                        if crossfade.is_none() && ab_loop.is_none() {
+                            if let (Some(source), Some(next)) = (&source, &next) {
+                                crossfade =
+                                    start_crossfade(&app_state, &config, rate, source, next);
+                            }
+                        }
+
                         let mut written = false;
                         if let Some(ref mut source) = source {
-                            let size = iter_to_buffer(source, &mut buffer);
-                            if size > 0 {
-                                app_state.lock().unwrap().current_time = source.current_time();
-                                playback.write(&buffer[..size]);
+                            let position = source.current_time();
+                            source.read(&mut buffer, rate, &config);
+                            scrobbler.listened(source.current_time().saturating_sub(position));
+                            if !buffer.is_empty() {
+                                apply_gain(&mut buffer, gain(&config, source));
+                                equalize(&config, source, &mut buffer, rate);
+                                if let (Some(crossfade), Some((next_source, _))) =
+                                    (crossfade.as_mut(), next.as_mut())
+                                {
+                                    next_source.read(&mut next_buffer, rate, &config);
+                                    apply_gain(&mut next_buffer, gain(&config, next_source));
+                                    equalize(&config, next_source, &mut next_buffer, rate);
+                                    // The current track may end in the middle of the buffer
+                                    if next_buffer.len() > buffer.len() {
+                                        buffer.resize(next_buffer.len(), [0.0; 2]);
+                                    }
+                                    crossfade.mix(&mut buffer, &next_buffer);
+                                }
+                                output.write(&buffer);
+                                tap.write(&buffer, rate, output.queued() as usize);
                                 written = true;
+
+                                if let Some((start, end)) = ab_loop {
+                                    if source.current_time() >= end {
+                                        source.loop_to(start);
+                                    }
+                                }
+                                app_state.lock().unwrap().current_time = source.current_time();
                             }
                         }
 
                         if !written {
+                            let crossfaded = crossfade.take().is_some();
+                            if let Some((next_source, _)) = next.take() {
+                                // Keep the same stream open so that both tracks join without a gap.
+                                // Following the rate of the file means reopening it, unless the
+                                // next track is already playing through the resampler.
+                                let rate = output_rate(&config, &next_source);
+                                if rate != output.rate() && !crossfaded {
+                                    output = Output::new(output.format(), rate);
+                                }
+                                scrobbler.start(&next_source.path);
+                                let mut app_state = app_state.lock().unwrap();
+                                app_state.current_time = next_source.current_time();
+                                app_state.track_changed = Some(next_source.path.clone());
+                                app_state.ab_loop = (None, None);
+                                source = Some(next_source);
+                                continue;
+                            }
+
+                            scrobbler.finish();
                             app_state.lock().unwrap().stopped = true;
                             *event_loop.playing.lock().unwrap() = false;
                             source = None;
@@ -108,15 +246,30 @@ impl Player {
             app_state,
             event_loop,
             paused: Cell::new(false),
+            tap,
         }
     }
 
+    pub fn tap(&self) -> Arc<SampleRing> {
+        self.tap.clone()
+    }
+
     pub fn load<P: AsRef<Path>>(&self, path: P) {
         let pathbuf = path.as_ref().to_path_buf();
         self.emit(Load(pathbuf));
         self.set_playing(true);
     }
 
+    // Track to pre-open and play gaplessly, or crossfaded, once the current one ends
+    pub fn queue(&self, path: Option<PathBuf>, same_album: bool) {
+        self.emit(Queue(path.map(|path| (path, same_album))));
+    }
+
+    pub fn seek(&self, millis: u64) {
+        self.emit(Seek(millis));
+        self.wake();
+    }
+
     pub fn is_paused(&self) -> bool {
         self.paused.get()
     }
@@ -143,6 +296,13 @@ impl Player {
         }
     }
 
+    // Handle the pending actions while paused
+    fn wake(&self) {
+        let (ref lock, ref condition_variable) = *self.event_loop.condition_variable;
+        *lock.lock().unwrap() = true;
+        condition_variable.notify_one();
+    }
+
     pub fn stop(&self) {
         self.paused.set(false);
         self.app_state.lock().unwrap().stopped = true;
@@ -151,7 +311,7 @@ impl Player {
     }
 
     pub fn compute_duration<P: AsRef<Path>>(path: P) -> Option<Duration> {
-        let file = File::open(path).unwrap();
+        let file = File::open(path).ok()?;
         Mp3Decoder::compute_duration(BufReader::new(file))
     }
 
@@ -160,9 +320,283 @@ impl Player {
     }
 }
 
-fn iter_to_buffer<I: Iterator<Item = i16>>(
+// Decoded samples from the start of the A-B loop, recorded on the first pass
+struct LoopSamples {
+    // Milliseconds asked for, and the media time in seconds of the first sample
+    start: u64,
+    played: f64,
+    samples: Vec<[f32; 2]>,
+    // Next sample played again, None while recording
+    replayed: Option<usize>,
+}
+
+// An opened file, its loudness information and the conversion to the output rate
+struct Track {
+    path: String,
+    decoder: Mp3Decoder<BufReader<File>>,
+    replay_gain: ReplayGain,
+    decoded: [[f32; 2]; BUFFER_SIZE],
+    // Created the first time the speed or the pitch changes
+    stretch: Option<TimeStretch>,
+    stretched: Vec<[f32; 2]>,
+    resampler: Option<Resampler>,
+    equalizer: Equalizer,
+    // Samples at the output rate not written yet
+    pending: Vec<[f32; 2]>,
+    flushed: bool,
+    // Media time of the samples written, in seconds
+    played: f64,
+    loop_samples: Option<LoopSamples>,
+}
+
+impl Track {
+    // Up to WRITE_SIZE samples at the output rate, fewer at the end of the file.
+    // The pitch is shifted by stretching the time by the inverse ratio, then resampling
+    // as if the file had been recorded at a rate multiplied by the ratio. The resampler
+    // is bypassed when the rates already match.
+    fn read(&mut self, buffer: &mut Vec<[f32; 2]>, rate: u32, config: &Mutex<Config>) {
+        let (quality, speed, pitch) = {
+            let config = config.lock().unwrap();
+            (
+                config.resampler_quality,
+                config.speed,
+                pitch_ratio(config.pitch),
+            )
+        };
+        let tempo = speed / pitch;
+        let file_rate = self.decoder.samples_rate();
+        let input_rate = (file_rate as f32 * pitch).round() as u32;
+
+        if input_rate == rate {
+            self.resampler = None;
+        } else if !self
+            .resampler
+            .as_ref()
+            .is_some_and(|resampler| resampler.matches(input_rate, rate, quality))
+        {
+            self.resampler = Some(Resampler::new(input_rate, rate, quality));
+        }
+        if self.stretch.is_none() && tempo != 1.0 {
+            self.stretch = Some(TimeStretch::new(file_rate));
+        }
+
+        while self.pending.len() < WRITE_SIZE && !self.flushed {
+            let size = self.decode();
+            let decoded = &self.decoded[..size];
+
+            let stretched = match self.stretch {
+                Some(ref mut stretch) => {
+                    self.stretched.clear();
+                    if size == 0 {
+                        stretch.flush(tempo, &mut self.stretched);
+                    } else {
+                        stretch.process(decoded, tempo, &mut self.stretched);
+                    }
+                    &self.stretched[..]
+                }
+                None => decoded,
+            };
+
+            match self.resampler {
+                Some(ref mut resampler) => {
+                    resampler.process(stretched, &mut self.pending);
+                    if size == 0 {
+                        resampler.flush(&mut self.pending);
+                    }
+                }
+                None => self.pending.extend_from_slice(stretched),
+            }
+            self.flushed = size == 0;
+        }
+
+        let size = self.pending.len().min(WRITE_SIZE);
+        buffer.clear();
+        buffer.extend(self.pending.drain(..size));
+        self.played += size as f64 * speed as f64 / rate as f64;
+    }
+
+    // Samples from the decoder, or those kept of the A-B loop when it is played again
+    fn decode(&mut self) -> usize {
+        if let Some(ref mut loop_samples) = self.loop_samples {
+            if let Some(ref mut replayed) = loop_samples.replayed {
+                let size = (loop_samples.samples.len() - *replayed).min(BUFFER_SIZE / 2);
+                if size > 0 {
+                    self.decoded[..size]
+                        .copy_from_slice(&loop_samples.samples[*replayed..*replayed + size]);
+                    *replayed += size;
+                    return size;
+                }
+            }
+        }
+
+        // Played past the samples kept, as when the loop was cleared: the decoder goes on
+        // from there
+        let past_loop = self.loop_samples.as_ref().and_then(|loop_samples| {
+            loop_samples.replayed.map(|_| {
+                let seconds =
+                    loop_samples.samples.len() as f64 / self.decoder.samples_rate() as f64;
+                ((loop_samples.played + seconds) * 1000.0) as u64
+            })
+        });
+        if let Some(millis) = past_loop {
+            self.loop_samples = None;
+            if let Some(decoder) = self.open_decoder(millis) {
+                self.decoder = decoder;
+            }
+        }
+
+        let size = iter_to_buffer(&mut self.decoder, &mut self.decoded);
+        let max_samples = MAX_LOOP_SECONDS * self.decoder.samples_rate() as usize;
+        let decoded = &self.decoded[..size];
+        let too_long = self.loop_samples.as_mut().is_some_and(|loop_samples| {
+            if loop_samples.samples.len() + decoded.len() > max_samples {
+                return true;
+            }
+            loop_samples.samples.extend_from_slice(decoded);
+            false
+        });
+        if too_long {
+            self.loop_samples = None;
+        }
+        size
+    }
+
+    fn open_decoder(&self, millis: u64) -> Option<Mp3Decoder<BufReader<File>>> {
+        let file = File::open(&self.path).ok()?;
+        Mp3Decoder::new_at(BufReader::new(file), millis).ok()
+    }
+
+    // Reopen the file at the position, dropping what is still in the pipeline
+    fn seek(&mut self, millis: u64) {
+        self.loop_samples = None;
+        if let Some(decoder) = self.open_decoder(millis) {
+            self.decoder = decoder;
+            self.restart(self.decoder.current_time() as f64 / 1000.0);
+        }
+    }
+
+    // Back to the start of the A-B loop. The first pass is recorded, so that the next ones
+    // are played again without seeking.
+    fn loop_to(&mut self, start: u64) {
+        let recorded = self
+            .loop_samples
+            .as_mut()
+            .filter(|loop_samples| loop_samples.start == start && !loop_samples.samples.is_empty());
+        if let Some(loop_samples) = recorded {
+            loop_samples.replayed = Some(0);
+            let played = loop_samples.played;
+            self.restart(played);
+            return;
+        }
+
+        self.seek(start);
+        self.loop_samples = Some(LoopSamples {
+            start,
+            played: self.played,
+            // Not to grow while playing
+            samples: Vec::with_capacity(MAX_LOOP_SECONDS * self.decoder.samples_rate() as usize),
+            replayed: None,
+        });
+    }
+
+    fn restart(&mut self, played: f64) {
+        self.played = played;
+        self.stretch = None;
+        self.resampler = None;
+        self.pending.clear();
+        self.flushed = false;
+    }
+
+    // Milliseconds, behind the decoder by what is still in the pipeline
+    fn current_time(&self) -> u64 {
+        (self.played * 1000.0) as u64
+    }
+}
+
+fn open(path: &Path) -> Option<Track> {
+    let file = File::open(path).ok()?;
+    let decoder = Mp3Decoder::new(BufReader::new(file)).ok()?;
+    Some(Track {
+        path: path.to_string_lossy().to_string(),
+        played: decoder.current_time() as f64 / 1000.0,
+        decoder,
+        replay_gain: ReplayGain::read_from_path(path),
+        decoded: [[0.0; 2]; BUFFER_SIZE],
+        stretch: None,
+        stretched: Vec::new(),
+        resampler: None,
+        equalizer: Equalizer::new(),
+        pending: Vec::with_capacity(WRITE_SIZE),
+        flushed: false,
+        loop_samples: None,
+    })
+}
+
+// Fixed rate from the preferences, or the rate of the file
+fn output_rate(config: &Mutex<Config>, track: &Track) -> u32 {
+    match config.lock().unwrap().output_rate {
+        0 => track.decoder.samples_rate(),
+        rate => rate,
+    }
+}
+
+// Read on every buffer so that changes to the preferences apply right away
+fn gain(config: &Mutex<Config>, track: &Track) -> f32 {
+    let config = config.lock().unwrap();
+    track.replay_gain.scale(
+        config.replaygain_mode,
+        config.replaygain_preamp,
+        config.replaygain_prevent_clipping,
+    )
+}
+
+// Each track keeps its own filters so that a crossfade can mix two settings
+fn equalize(config: &Mutex<Config>, track: &mut Track, buffer: &mut [[f32; 2]], rate: u32) {
+    let config = config.lock().unwrap();
+    track
+        .equalizer
+        .process(config.equalizer_for(&track.path), rate, buffer);
+}
+
+// Samples going over full scale are clipped by the output
+fn apply_gain(buffer: &mut [[f32; 2]], gain: f32) {
+    if gain == 1.0 {
+        return;
+    }
+    for sample in buffer.iter_mut().flat_map(|sample| sample.iter_mut()) {
+        *sample *= gain;
+    }
+}
+
+// Start mixing in the queued track once the current one gets close enough to its end
+fn start_crossfade(
+    app_state: &Mutex<super::State>,
+    config: &Mutex<Config>,
+    rate: u32,
+    source: &Track,
+    next: &(Track, bool),
+) -> Option<Crossfade> {
+    let (_, same_album) = *next;
+    let config = config.lock().unwrap();
+    if config.crossfade == 0 || (same_album && !config.crossfade_same_album) {
+        return None;
+    }
+
+    let duration = *app_state.lock().unwrap().durations.get(&source.path)?;
+    // Seconds left to play, shorter than the media time when sped up
+    let remaining =
+        duration.saturating_sub(source.current_time()) as f64 / 1000.0 / config.speed as f64;
+    if remaining > config.crossfade as f64 {
+        return None;
+    }
+
+    let length = remaining * rate as f64;
+    Some(Crossfade::new(length as usize, config.crossfade_curve))
+}
+
+fn iter_to_buffer<I: Iterator<Item = f32>>(
     iter: &mut I,
-    buffer: &mut [[i16; 2]; BUFFER_SIZE],
+    buffer: &mut [[f32; 2]; BUFFER_SIZE],
 ) -> usize {
     let mut iter = iter.take(BUFFER_SIZE);
     let mut index = 0;
@@ -1,30 +1,34 @@
-use id3::Tag;
-
-use gdk_pixbuf::{InterpType, Pixbuf, PixbufLoader};
+use gdk_pixbuf::Pixbuf;
 
 use gtk::{
     CellLayoutExt, CellRendererPixbuf, CellRendererText, ListStore, ListStoreExt,
-    ListStoreExtManual, StaticType, ToValue, TreeIter, TreeModelExt, TreeSelectionExt, TreeView,
-    TreeViewColumn, TreeViewColumnExt, TreeViewExt, Type, WidgetExt,
+    ListStoreExtManual, SelectionMode, StaticType, ToValue, TreeIter, TreeModelExt,
+    TreeSelectionExt, TreeSortableExtManual, TreeView, TreeViewColumn, TreeViewColumnExt,
+    TreeViewExt, Type, WidgetExt,
 };
 
-use self::Visibility::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use bookmarks::Bookmarks;
+use cache::DurationCache;
+use config::Config;
+use covers::CoverLoader;
+use guess::{apply_fields, guess_fields};
+use library::is_mp3_file;
+use metadata::Metadata;
 use player::Player;
+use ring::SampleRing;
+use stats::{format_date, stars, Stats};
 use std::cell::RefCell;
 use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use to_millis;
+use watcher::{Change, Watcher};
 use State;
 
-#[derive(PartialEq)]
-enum Visibility {
-    Invisible,
-    Visible,
-}
-
 const THUMBNAIL_COLUMN: u32 = 0;
 const TITLE_COLUMN: u32 = 1;
 const ARTIST_COLUMN: u32 = 2;
@@ -33,23 +37,57 @@ const GENRE_COLUMN: u32 = 4;
 const YEAR_COLUMN: u32 = 5;
 const TRACK_COLUMN: u32 = 6;
 const PATH_COLUMN: u32 = 7;
-const PIXBUF_COLUMN: u32 = 8;
-
-const IMAGE_SIZE: i32 = 256;
-const THUMBNAIL_SIZE: i32 = 64;
-
-const INTERP_HYPER: InterpType = 3;
+const ALBUM_ARTIST_COLUMN: u32 = 8;
+const DISC_COLUMN: u32 = 9;
+const COMPOSER_COLUMN: u32 = 10;
+const COMMENT_COLUMN: u32 = 11;
+const BITRATE_COLUMN: u32 = 12;
+const SAMPLE_RATE_COLUMN: u32 = 13;
+const FILE_SIZE_COLUMN: u32 = 14;
+// Numbers the columns above are sorted by, missing values being 0
+const YEAR_KEY_COLUMN: u32 = 15;
+const TRACK_KEY_COLUMN: u32 = 16;
+const DISC_KEY_COLUMN: u32 = 17;
+const BITRATE_KEY_COLUMN: u32 = 18;
+const SAMPLE_RATE_KEY_COLUMN: u32 = 19;
+const FILE_SIZE_KEY_COLUMN: u32 = 20;
+// The file was removed, or renamed out of the watched directories
+const MISSING_COLUMN: u32 = 21;
+const PLAYS_COLUMN: u32 = 22;
+const SKIPS_COLUMN: u32 = 23;
+const LAST_PLAYED_COLUMN: u32 = 24;
+const RATING_COLUMN: u32 = 25;
+const PLAYS_KEY_COLUMN: u32 = 26;
+const SKIPS_KEY_COLUMN: u32 = 27;
+const LAST_PLAYED_KEY_COLUMN: u32 = 28;
+const RATING_KEY_COLUMN: u32 = 29;
 
 pub struct Playlist {
+    config: Arc<Mutex<Config>>,
+    covers: CoverLoader,
     current_song: RefCell<Option<String>>,
+    // Queued to be played after the current one
+    next_song: RefCell<Option<String>>,
+    duration_cache: Arc<Mutex<DurationCache>>,
     model: ListStore,
     player: Player,
     state: Arc<Mutex<State>>,
+    stats: Rc<RefCell<Stats>>,
+    bookmarks: Rc<RefCell<Bookmarks>>,
     treeview: TreeView,
+    // First and last rows shown, the others have their thumbnails cleared
+    visible_rows: RefCell<Option<(i32, i32)>>,
+    watcher: Rc<Watcher>,
 }
 
 impl Playlist {
-    pub(crate) fn new(state: Arc<Mutex<State>>) -> Self {
+    pub(crate) fn new(
+        state: Arc<Mutex<State>>,
+        config: Arc<Mutex<Config>>,
+        watcher: Rc<Watcher>,
+        stats: Rc<RefCell<Stats>>,
+        bookmarks: Rc<RefCell<Bookmarks>>,
+    ) -> Self {
         let model = ListStore::new(&[
             Pixbuf::static_type(), // Thumbnail
             Type::String,          // Metadata
@@ -59,99 +97,451 @@ impl Playlist {
             Type::String,          // Metadata
             Type::String,          // Metadata
             Type::String,          // Metadata
-            Pixbuf::static_type(), // Thumbnail bigger, currently play
+            Type::String,          // Metadata
+            Type::String,          // Metadata
+            Type::String,          // Metadata
+            Type::String,          // Stream
+            Type::String,          // Stream
+            Type::String,          // File size
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::Bool,            // Missing
+            Type::String,          // Statistics
+            Type::String,          // Statistics
+            Type::String,          // Statistics
+            Type::String,          // Statistics
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
+            Type::U64,             // Sort keys
         ]);
 
         let treeview = TreeView::new_with_model(&model);
         treeview.set_hexpand(true);
         treeview.set_vexpand(true);
+        treeview.get_selection().set_mode(SelectionMode::Multiple);
 
         // Create columns shown in this view
         Self::create_columns(&treeview);
 
         Playlist {
+            config: config.clone(),
+            covers: CoverLoader::new(),
             current_song: RefCell::new(None),
+            next_song: RefCell::new(None),
+            duration_cache: Arc::new(Mutex::new(DurationCache::load())),
             model,
-            player: Player::new(state.clone()),
+            player: Player::new(state.clone(), config),
             state,
+            stats,
+            bookmarks,
             treeview,
+            visible_rows: RefCell::new(None),
+            watcher,
         }
     }
 
     // Add Metadata from MP3 file
     pub fn add(&self, path: &Path) {
         self.compute_duration(path);
-        let filename = path
-            .file_stem()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or_default();
         let row = self.model.append();
+        self.fill_row(&row, path);
+        if let Some(directory) = path.parent() {
+            self.watcher.watch(directory);
+        }
+        // Appended after the current track, which had none following it
+        if self.next_song.borrow().is_none() {
+            self.requeue();
+        }
+    }
 
-        if let Ok(tag) = Tag::read_from_path(path) {
-            let title = tag.title().unwrap_or(filename);
-            let artist = tag.artist().unwrap_or("No artist");
-            let album = tag.album().unwrap_or("No album");
-            let genre = tag.genre().unwrap_or("No genre");
-            let year = tag
-                .year()
-                .map(|year| year.to_string())
-                .unwrap_or("No year".to_string());
-            let track = tag
-                .track()
-                .map(|track| track.to_string())
-                .unwrap_or("No track".to_string());
-            let total_tracks = tag
-                .total_tracks()
-                .map(|total_tracks| total_tracks.to_string())
-                .unwrap_or("No Total Tracks".to_string());
-            let track_value = format!("{} / {}", track, total_tracks);
-
-            self.set_pixbuf(&row, &tag);
-            self.model.set_value(&row, TITLE_COLUMN, &title.to_value());
-            self.model
-                .set_value(&row, ARTIST_COLUMN, &artist.to_value());
-            self.model.set_value(&row, ALBUM_COLUMN, &album.to_value());
-            self.model.set_value(&row, GENRE_COLUMN, &genre.to_value());
-            self.model.set_value(&row, YEAR_COLUMN, &year.to_value());
-            self.model
-                .set_value(&row, TRACK_COLUMN, &track_value.to_value());
+    // Read the tags again into the rows of these files, after they were edited
+    pub fn refresh(&self, paths: &[String]) {
+        let iter = match self.model.get_iter_first() {
+            Some(iter) => iter,
+            None => return,
+        };
+        loop {
+            if let Some(path) = self.iter_path(&iter) {
+                if paths.contains(&path) {
+                    self.covers.forget(&path);
+                    self.fill_row(&iter, Path::new(&path));
+                }
+            }
+            if !self.model.iter_next(&iter) {
+                return;
+            }
+        }
+    }
+
+    // Follow a change on disk to the rows of the files concerned. New files in the directories
+    // of the rows are added.
+    pub fn apply_change(&self, change: &Change) {
+        let mut known = false;
+        let mut directories = HashSet::new();
+        if let Some(iter) = self.model.get_iter_first() {
+            loop {
+                if let Some(path) = self.iter_path(&iter) {
+                    known |= self.apply_change_to_row(&iter, Path::new(&path), change);
+                    if let Some(directory) = Path::new(&path).parent() {
+                        directories.insert(directory.to_path_buf());
+                    }
+                }
+                if !self.model.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+
+        let new_file = match *change {
+            Change::Modified(ref path) | Change::Renamed(_, ref path) => path,
+            _ => return,
+        };
+        let in_directories = new_file
+            .parent()
+            .is_some_and(|directory| directories.contains(directory));
+        if !known && in_directories && is_mp3_file(new_file) {
+            self.add(new_file);
+        }
+    }
+
+    // Whether the row is concerned
+    fn apply_change_to_row(&self, iter: &TreeIter, path: &Path, change: &Change) -> bool {
+        match *change {
+            Change::Modified(ref modified) if path == modified => {
+                self.covers.forget(&path.to_string_lossy());
+                self.compute_duration(path);
+                self.fill_row(iter, path);
+            }
+            // A directory takes the files in it along
+            Change::Removed(ref removed) if path.starts_with(removed) => {
+                self.model.set_value(iter, MISSING_COLUMN, &true.to_value());
+            }
+            Change::Renamed(ref from, ref to) if path.starts_with(from) => {
+                let renamed = match path.strip_prefix(from) {
+                    Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                    _ => to.clone(),
+                };
+                let mut current_song = self.current_song.borrow_mut();
+                if current_song.as_deref() == path.to_str() {
+                    *current_song = renamed.to_str().map(String::from);
+                }
+                // The statistics, bookmarks and settings of the file follow it
+                let (from, to) = (path.to_string_lossy(), renamed.to_string_lossy());
+                self.stats.borrow_mut().rename(&from, &to);
+                self.bookmarks.borrow_mut().rename(&from, &to);
+                let mut config = self.config.lock().unwrap();
+                if config.rename_track(&from, &to) {
+                    config.save();
+                }
+                drop(config);
+                self.covers.forget(&path.to_string_lossy());
+                self.compute_duration(&renamed);
+                self.fill_row(iter, &renamed);
+            }
+            // Replaced by another file, as tag editors saving to a temporary file do
+            Change::Renamed(_, ref to) if path == to => {
+                self.covers.forget(&path.to_string_lossy());
+                self.compute_duration(path);
+                self.fill_row(iter, path);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn fill_row(&self, row: &TreeIter, path: &Path) {
+        let mut metadata = Metadata::read_from_path(path);
+        if !metadata.tagged {
+            let patterns = self.config.lock().unwrap().tag_patterns.clone();
+            if let Some(fields) = guess_fields(&patterns, path) {
+                apply_fields(&fields, &mut metadata);
+            }
+        }
+        let title = if metadata.title.is_empty() {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
         } else {
-            self.model
-                .set_value(&row, TITLE_COLUMN, &filename.to_value());
+            metadata.title
+        };
+        let year = metadata.year.map(|year| year.to_string());
+        let bitrate = metadata.bitrate.map(|bitrate| format!("{} kbps", bitrate));
+        let sample_rate = metadata
+            .sample_rate
+            .map(|rate| format!("{:.1} kHz", rate as f64 / 1000.0));
+
+        let strings = [
+            (TITLE_COLUMN, title),
+            (ARTIST_COLUMN, metadata.artist),
+            (ALBUM_COLUMN, metadata.album),
+            (ALBUM_ARTIST_COLUMN, metadata.album_artist),
+            (GENRE_COLUMN, metadata.genre),
+            (COMPOSER_COLUMN, metadata.composer),
+            (COMMENT_COLUMN, metadata.comment),
+            (YEAR_COLUMN, year.unwrap_or_default()),
+            (
+                TRACK_COLUMN,
+                number_pair(metadata.track, metadata.total_tracks),
+            ),
+            (
+                DISC_COLUMN,
+                number_pair(metadata.disc, metadata.total_discs),
+            ),
+            (BITRATE_COLUMN, bitrate.unwrap_or_default()),
+            (SAMPLE_RATE_COLUMN, sample_rate.unwrap_or_default()),
+            (FILE_SIZE_COLUMN, file_size(metadata.file_size)),
+        ];
+        for &(column, ref value) in &strings {
+            self.model.set_value(row, column, &value.to_value());
+        }
+
+        let keys = [
+            (YEAR_KEY_COLUMN, metadata.year.unwrap_or(0).max(0) as u64),
+            // Tracks of the first disc first
+            (
+                TRACK_KEY_COLUMN,
+                metadata.disc.unwrap_or(0) as u64 * 1000 + metadata.track.unwrap_or(0) as u64,
+            ),
+            (DISC_KEY_COLUMN, metadata.disc.unwrap_or(0) as u64),
+            (BITRATE_KEY_COLUMN, metadata.bitrate.unwrap_or(0) as u64),
+            (
+                SAMPLE_RATE_KEY_COLUMN,
+                metadata.sample_rate.unwrap_or(0) as u64,
+            ),
+            (FILE_SIZE_KEY_COLUMN, metadata.file_size),
+        ];
+        for &(column, value) in &keys {
+            self.model.set_value(row, column, &value.to_value());
         }
 
+        // Loaded once the row is shown
+        self.model
+            .set_value(row, THUMBNAIL_COLUMN, &None::<Pixbuf>.to_value());
+
+        self.model
+            .set_value(row, MISSING_COLUMN, &(!path.exists()).to_value());
         let path = path.to_str().unwrap_or_default();
-        self.model.set_value(&row, PATH_COLUMN, &path.to_value());
+        self.model.set_value(row, PATH_COLUMN, &path.to_value());
+        self.fill_stats(row, path);
+    }
+
+    fn fill_stats(&self, row: &TreeIter, path: &str) {
+        let stats = self.stats.borrow().get(path);
+        let count = |count: u32| {
+            if count == 0 {
+                String::new()
+            } else {
+                count.to_string()
+            }
+        };
+        let texts = [
+            (PLAYS_COLUMN, count(stats.plays)),
+            (SKIPS_COLUMN, count(stats.skips)),
+            (
+                LAST_PLAYED_COLUMN,
+                stats.last_played.map(format_date).unwrap_or_default(),
+            ),
+            (RATING_COLUMN, stars(stats.rating)),
+        ];
+        for (column, text) in &texts {
+            self.model.set_value(row, *column, &text.to_value());
+        }
+        let keys = [
+            (PLAYS_KEY_COLUMN, stats.plays as u64),
+            (SKIPS_KEY_COLUMN, stats.skips as u64),
+            (LAST_PLAYED_KEY_COLUMN, stats.last_played.unwrap_or(0)),
+            (RATING_KEY_COLUMN, stats.rating as u64),
+        ];
+        for &(column, value) in &keys {
+            self.model.set_value(row, column, &value.to_value());
+        }
+    }
+
+    // After the statistics of the file changed
+    pub fn refresh_stats(&self, path: &str) {
+        let iter = match self.model.get_iter_first() {
+            Some(iter) => iter,
+            None => return,
+        };
+        loop {
+            if self.iter_path(&iter).as_deref() == Some(path) {
+                self.fill_stats(&iter, path);
+            }
+            if !self.model.iter_next(&iter) {
+                return;
+            }
+        }
     }
 
     pub fn view(&self) -> &TreeView {
         &self.treeview
     }
 
+    pub fn model(&self) -> &ListStore {
+        &self.model
+    }
+
     pub fn remove_selection(&self) {
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            self.model.remove(&iter);
+        // Last rows first, so that the paths of the remaining ones stay valid
+        for iter in self.selected_iters().iter().rev() {
+            self.model.remove(iter);
+        }
+        self.requeue();
+    }
+
+    // Rows for `paths`, in their order after the other rows, the rows already there being
+    // moved rather than read again. The rows of `previous` not among them are removed, except
+    // the one of the track played, which is returned.
+    pub fn sync_paths(&self, previous: &HashSet<String>, paths: &[String]) -> Option<String> {
+        let current = self.path();
+        let wanted: HashSet<&String> = paths.iter().collect();
+        let mut rows = HashMap::new();
+        let mut kept = None;
+        if let Some(iter) = self.model.get_iter_first() {
+            loop {
+                let path = self.iter_path(&iter).filter(|path| previous.contains(path));
+                let removed = match path {
+                    Some(path) => {
+                        if wanted.contains(&path) && !rows.contains_key(&path) {
+                            rows.insert(path, iter.clone());
+                            false
+                        } else if current.as_ref() == Some(&path) && kept.is_none() {
+                            kept = Some(path);
+                            false
+                        } else {
+                            true
+                        }
+                    }
+                    None => false,
+                };
+                let more = if removed {
+                    self.model.remove(&iter)
+                } else {
+                    self.model.iter_next(&iter)
+                };
+                if !more {
+                    break;
+                }
+            }
+        }
+
+        // The order of a sorted playlist is its own
+        let sorted = self.model.get_sort_column_id().is_some();
+        for path in paths {
+            match rows.get(path) {
+                Some(iter) if !sorted => self.model.move_before(iter, None),
+                Some(_) => (),
+                None => self.add(Path::new(path)),
+            }
         }
+        self.requeue();
+        kept
     }
 
+    pub fn selected_paths(&self) -> Vec<String> {
+        self.selected_iters()
+            .iter()
+            .filter_map(|iter| self.iter_path(iter))
+            .collect()
+    }
+
+    // Path and album of every selected row
+    // Path, album artist (the artist when there is none) and album of the selected rows
+    pub fn selected_tracks(&self) -> Vec<(String, String, String)> {
+        self.selected_iters()
+            .iter()
+            .filter_map(|iter| {
+                let path = self.iter_path(iter)?;
+                let text = |column: u32| {
+                    self.model
+                        .get_value(iter, column as i32)
+                        .get::<String>()
+                        .unwrap_or_default()
+                };
+                let mut artist = text(ALBUM_ARTIST_COLUMN);
+                if artist.is_empty() {
+                    artist = text(ARTIST_COLUMN);
+                }
+                Some((path, artist, text(ALBUM_COLUMN)))
+            })
+            .collect()
+    }
+
+    // Full size cover of the selected row
     pub fn pixbuf(&self) -> Option<Pixbuf> {
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            let value = self.model.get_value(&iter, PIXBUF_COLUMN as i32);
-            return value.get::<Pixbuf>();
+        let path = self.selected_path()?;
+        self.cover(&path)
+    }
+
+    pub fn cover(&self, path: &str) -> Option<Pixbuf> {
+        self.covers.image(path)
+    }
+
+    // Thumbnails of the rows shown are loaded in the background, those of the rows
+    // scrolled out of view are dropped
+    pub fn update_thumbnails(&self) {
+        self.covers.receive();
+        let rows = self.treeview.get_visible_range().and_then(|(start, end)| {
+            Some((*start.get_indices().first()?, *end.get_indices().first()?))
+        });
+
+        if rows != *self.visible_rows.borrow() {
+            if let Some(iter) = self.model.get_iter_first() {
+                let mut index = 0;
+                loop {
+                    let shown = rows.is_some_and(|(first, last)| first <= index && index <= last);
+                    if !shown && self.thumbnail(&iter).is_some() {
+                        self.model
+                            .set_value(&iter, THUMBNAIL_COLUMN, &None::<Pixbuf>.to_value());
+                    }
+                    index += 1;
+                    if !self.model.iter_next(&iter) {
+                        break;
+                    }
+                }
+            }
+            *self.visible_rows.borrow_mut() = rows;
+        }
+
+        let (first, last) = match rows {
+            Some(rows) => rows,
+            None => return,
+        };
+        for index in first..=last {
+            let iter = match self.model.iter_nth_child(None, index) {
+                Some(iter) => iter,
+                None => break,
+            };
+            if self.thumbnail(&iter).is_some() {
+                continue;
+            }
+            let thumbnail = self
+                .iter_path(&iter)
+                .and_then(|path| self.covers.thumbnail(&path));
+            if thumbnail.is_some() {
+                self.model
+                    .set_value(&iter, THUMBNAIL_COLUMN, &thumbnail.to_value());
+            }
         }
-        None
     }
 
     pub fn play(&self) -> bool {
         if let Some(path) = self.selected_path() {
+            if !Path::new(&path).exists() {
+                return false;
+            }
             if self.player.is_paused() && Some(&path) == self.path().as_ref() {
                 self.player.resume();
             } else {
                 self.player.load(&path);
                 *self.current_song.borrow_mut() = Some(path.into());
+                self.queue_next();
             }
             true
         } else {
@@ -163,18 +553,65 @@ impl Playlist {
         self.player.pause();
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.player.is_paused()
+    }
+
+    // The player went on to the queued track: select it and queue the one after
+    pub fn set_current(&self, path: &str) {
+        let next_iter = self
+            .selected_iter()
+            .filter(|iter| {
+                self.model.iter_next(iter) && self.iter_path(iter).as_deref() == Some(path)
+            })
+            .or_else(|| self.find(path));
+
+        if let Some(ref iter) = next_iter {
+            self.select(iter);
+        }
+        *self.current_song.borrow_mut() = Some(path.into());
+        self.queue_next();
+    }
+
     pub fn path(&self) -> Option<String> {
         self.current_song.borrow().clone()
     }
 
+    pub fn next_path(&self) -> Option<String> {
+        self.next_song.borrow().clone()
+    }
+
+    // Title, artist and album of the row of this file
+    pub fn titles(&self, path: &str) -> Option<(String, String, String)> {
+        let iter = self.find(path)?;
+        let text = |column: u32| {
+            self.model
+                .get_value(&iter, column as i32)
+                .get::<String>()
+                .unwrap_or_default()
+        };
+        Some((text(TITLE_COLUMN), text(ARTIST_COLUMN), text(ALBUM_COLUMN)))
+    }
+
+    pub fn tap(&self) -> Arc<SampleRing> {
+        self.player.tap()
+    }
+
+    // Milliseconds in the current track
+    pub fn seek(&self, millis: u64) {
+        if self.current_song.borrow().is_some() {
+            self.player.seek(millis);
+        }
+    }
+
     pub fn stop(&self) {
         *self.current_song.borrow_mut() = None;
+        *self.next_song.borrow_mut() = None;
         self.player.stop();
     }
 
     pub fn next(&self) -> bool {
-        let selection = self.treeview.get_selection();
-        let next_iter = if let Some((_, iter)) = selection.get_selected() {
+        let next_iter = if let Some(iter) = self.selected_iter() {
             if !self.model.iter_next(&iter) {
                 return false;
             }
@@ -184,15 +621,14 @@ impl Playlist {
         };
 
         if let Some(ref iter) = next_iter {
-            selection.select_iter(iter);
+            self.select(iter);
             self.play();
         }
         next_iter.is_some()
     }
 
     pub fn previous(&self) -> bool {
-        let selection = self.treeview.get_selection();
-        let previous_iter = if let Some((_, iter)) = selection.get_selected() {
+        let previous_iter = if let Some(iter) = self.selected_iter() {
             if !self.model.iter_previous(&iter) {
                 return false;
             }
@@ -203,81 +639,207 @@ impl Playlist {
         };
 
         if let Some(ref iter) = previous_iter {
-            selection.select_iter(iter);
+            self.select(iter);
             self.play();
         }
         previous_iter.is_some()
     }
 
     fn create_columns(treeview: &TreeView) {
-        Self::add_pixbuf_column(treeview, THUMBNAIL_COLUMN as i32, Visible);
-        Self::add_text_column(treeview, "Title", TITLE_COLUMN as i32);
-        Self::add_text_column(treeview, "Artist", ARTIST_COLUMN as i32);
-        Self::add_text_column(treeview, "Album", ALBUM_COLUMN as i32);
-        Self::add_text_column(treeview, "Genre", GENRE_COLUMN as i32);
-        Self::add_text_column(treeview, "Year", YEAR_COLUMN as i32);
-        Self::add_text_column(treeview, "Track", TRACK_COLUMN as i32);
-        Self::add_pixbuf_column(treeview, PIXBUF_COLUMN as i32, Invisible);
+        Self::add_pixbuf_column(treeview, THUMBNAIL_COLUMN as i32);
+        Self::add_text_column(treeview, "Title", TITLE_COLUMN, TITLE_COLUMN);
+        Self::add_text_column(treeview, "Artist", ARTIST_COLUMN, ARTIST_COLUMN);
+        Self::add_text_column(treeview, "Album", ALBUM_COLUMN, ALBUM_COLUMN);
+        Self::add_text_column(
+            treeview,
+            "Album artist",
+            ALBUM_ARTIST_COLUMN,
+            ALBUM_ARTIST_COLUMN,
+        );
+        Self::add_text_column(treeview, "Genre", GENRE_COLUMN, GENRE_COLUMN);
+        Self::add_text_column(treeview, "Composer", COMPOSER_COLUMN, COMPOSER_COLUMN);
+        Self::add_text_column(treeview, "Year", YEAR_COLUMN, YEAR_KEY_COLUMN);
+        Self::add_text_column(treeview, "Track", TRACK_COLUMN, TRACK_KEY_COLUMN);
+        Self::add_text_column(treeview, "Disc", DISC_COLUMN, DISC_KEY_COLUMN);
+        Self::add_text_column(treeview, "Comment", COMMENT_COLUMN, COMMENT_COLUMN);
+        Self::add_text_column(treeview, "Bitrate", BITRATE_COLUMN, BITRATE_KEY_COLUMN);
+        Self::add_text_column(
+            treeview,
+            "Sample rate",
+            SAMPLE_RATE_COLUMN,
+            SAMPLE_RATE_KEY_COLUMN,
+        );
+        Self::add_text_column(treeview, "Size", FILE_SIZE_COLUMN, FILE_SIZE_KEY_COLUMN);
+        Self::add_text_column(treeview, "Plays", PLAYS_COLUMN, PLAYS_KEY_COLUMN);
+        Self::add_text_column(treeview, "Skips", SKIPS_COLUMN, SKIPS_KEY_COLUMN);
+        Self::add_text_column(
+            treeview,
+            "Last played",
+            LAST_PLAYED_COLUMN,
+            LAST_PLAYED_KEY_COLUMN,
+        );
+        Self::add_text_column(treeview, "Rating", RATING_COLUMN, RATING_KEY_COLUMN);
     }
 
-    fn add_text_column(treeview: &TreeView, title: &str, column: i32) {
+    // Clicking the header sorts the rows by `sort_column`
+    fn add_text_column(treeview: &TreeView, title: &str, column: u32, sort_column: u32) {
         let view_column = TreeViewColumn::new();
         view_column.set_title(title);
         let cell = CellRendererText::new();
         view_column.set_expand(true);
         view_column.pack_start(&cell, true);
         // text attribute from the data that comes from the model at the specified column
-        view_column.add_attribute(&cell, "text", column);
+        view_column.add_attribute(&cell, "text", column as i32);
+        view_column.add_attribute(&cell, "strikethrough", MISSING_COLUMN as i32);
+        view_column.set_sort_column_id(sort_column as i32);
         treeview.append_column(&view_column);
     }
 
-    fn add_pixbuf_column(treeview: &TreeView, column: i32, visibility: Visibility) {
+    fn add_pixbuf_column(treeview: &TreeView, column: i32) {
         let view_column = TreeViewColumn::new();
-        if visibility == Visible {
-            let cell = CellRendererPixbuf::new(); // render image, only created if the column is visible
-            view_column.pack_start(&cell, true);
-            view_column.add_attribute(&cell, "pixbuf", column);
-        }
+        let cell = CellRendererPixbuf::new();
+        view_column.pack_start(&cell, true);
+        view_column.add_attribute(&cell, "pixbuf", column);
         treeview.append_column(&view_column);
     }
 
-    fn set_pixbuf(&self, row: &TreeIter, tag: &Tag) {
-        // tag represents the metadata of an MP3 file
-        if let Some(picture) = tag.pictures().next() {
-            let pixbuf_loader = PixbufLoader::new();
-            pixbuf_loader.set_size(IMAGE_SIZE, IMAGE_SIZE);
-            pixbuf_loader.loader_write(&picture.data).unwrap();
+    fn thumbnail(&self, iter: &TreeIter) -> Option<Pixbuf> {
+        let value = self.model.get_value(iter, THUMBNAIL_COLUMN as i32);
+        value.get::<Pixbuf>()
+    }
 
-            if let Some(pixbuf) = pixbuf_loader.get_pixbuf() {
-                let thumbnail = pixbuf
-                    .scale_simple(THUMBNAIL_SIZE, THUMBNAIL_SIZE, INTERP_HYPER)
-                    .unwrap();
-                self.model
-                    .set_value(row, THUMBNAIL_COLUMN, &thumbnail.to_value());
-                self.model.set_value(row, PIXBUF_COLUMN, &pixbuf.to_value());
+    fn selected_path(&self) -> Option<String> {
+        let iter = self.selected_iter()?;
+        self.iter_path(&iter)
+    }
+
+    fn selected_iters(&self) -> Vec<TreeIter> {
+        let (paths, _) = self.treeview.get_selection().get_selected_rows();
+        paths
+            .iter()
+            .filter_map(|path| self.model.get_iter(path))
+            .collect()
+    }
+
+    // Several rows can be selected, the first one is the one played
+    fn selected_iter(&self) -> Option<TreeIter> {
+        self.selected_iters().into_iter().next()
+    }
+
+    fn select(&self, iter: &TreeIter) {
+        let selection = self.treeview.get_selection();
+        selection.unselect_all();
+        selection.select_iter(iter);
+    }
+
+    fn iter_path(&self, iter: &TreeIter) -> Option<String> {
+        let value = self.model.get_value(iter, PATH_COLUMN as i32);
+        value.get::<String>()
+    }
+
+    fn find(&self, path: &str) -> Option<TreeIter> {
+        let iter = self.model.get_iter_first()?;
+        loop {
+            if self.iter_path(&iter).as_deref() == Some(path) {
+                return Some(iter);
+            }
+            if !self.model.iter_next(&iter) {
+                return None;
             }
-            pixbuf_loader.close().unwrap();
         }
     }
 
-    fn selected_path(&self) -> Option<String> {
-        let selection = self.treeview.get_selection();
-        if let Some((_, iter)) = selection.get_selected() {
-            let value = self.model.get_value(&iter, PATH_COLUMN as i32);
-            return value.get::<String>();
+    // Let the player pre-open the row following the current one, for gapless playback
+    // or crossfading
+    fn queue_next(&self) {
+        let (next_path, same_album) = self.following();
+        *self.next_song.borrow_mut() = next_path.clone();
+        self.player.queue(next_path.map(PathBuf::from), same_album);
+    }
+
+    // After rows were added, removed or moved: queue the track now following the current
+    // one, unless it is already
+    pub fn requeue(&self) {
+        if self.current_song.borrow().is_none() {
+            return;
+        }
+        let (next_path, same_album) = self.following();
+        if next_path != *self.next_song.borrow() {
+            *self.next_song.borrow_mut() = next_path.clone();
+            self.player.queue(next_path.map(PathBuf::from), same_album);
         }
-        None
+    }
+
+    // The row of the track played: the selected one, unless the selection moved away
+    fn current_iter(&self) -> Option<TreeIter> {
+        let selected = self.selected_iter();
+        let current = match *self.current_song.borrow() {
+            Some(ref current) => current.clone(),
+            None => return selected,
+        };
+        selected
+            .filter(|iter| self.iter_path(iter).as_deref() == Some(current.as_str()))
+            .or_else(|| self.find(&current))
+    }
+
+    // The path of the row after the current one, and whether it is of the same album
+    fn following(&self) -> (Option<String>, bool) {
+        let mut same_album = false;
+        let next_path = self.current_iter().and_then(|iter| {
+            let album = self
+                .model
+                .get_value(&iter, ALBUM_COLUMN as i32)
+                .get::<String>();
+            if !self.model.iter_next(&iter) {
+                return None;
+            }
+            let next_album = self
+                .model
+                .get_value(&iter, ALBUM_COLUMN as i32)
+                .get::<String>();
+            same_album =
+                album.as_ref().is_some_and(|album| !album.is_empty()) && album == next_album;
+            self.iter_path(&iter)
+        });
+        (next_path, same_album)
     }
 
     fn compute_duration(&self, path: &Path) {
         let state = self.state.clone();
+        let duration_cache = self.duration_cache.clone();
         let path = path.to_string_lossy().to_string();
 
+        if let Some(duration) = duration_cache.lock().unwrap().get(&path) {
+            state.lock().unwrap().durations.insert(path, duration);
+            return;
+        }
+
         thread::spawn(move || {
             if let Some(duration) = Player::compute_duration(&path) {
+                let duration = to_millis(duration);
+                duration_cache.lock().unwrap().insert(&path, duration);
                 let mut state = state.lock().unwrap();
-                state.durations.insert(path, to_millis(duration));
+                state.durations.insert(path, duration);
             }
         });
     }
 }
+
+// "3 / 12", or only the number when the total is unknown
+fn number_pair(number: Option<u32>, total: Option<u32>) -> String {
+    match (number, total) {
+        (Some(number), Some(total)) => format!("{} / {}", number, total),
+        (Some(number), None) => number.to_string(),
+        (None, _) => String::new(),
+    }
+}
+
+fn file_size(bytes: u64) -> String {
+    if bytes == 0 {
+        String::new()
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
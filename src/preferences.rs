@@ -0,0 +1,144 @@
+use gtk::{
+    Align, ApplicationWindow, CheckButton, ComboBoxExt, ComboBoxText, ComboBoxTextExt,
+    ContainerExt, Dialog, DialogExt, DialogFlags, Grid, GridExt, IsA, Label, SpinButton,
+    SpinButtonExt, ToggleButtonExt, Widget, WidgetExt,
+};
+
+use std::sync::{Arc, Mutex};
+
+use config::{Config, AUTOMATIC_FORMAT, MAX_CROSSFADE, MAX_PREAMP};
+use crossfade::FadeCurve;
+use dialog::{RESPONSE_ACCEPT, RESPONSE_CANCEL};
+use output::{SampleFormat, MAX_RATE};
+use replaygain::GainMode;
+use resampler::ResamplerQuality;
+
+const OUTPUT_RATES: [u32; 5] = [32000, 44100, 48000, 88200, MAX_RATE];
+
+// Rows of the preferences grid: a label and the widget editing the setting
+fn add_row<W: IsA<Widget>>(grid: &Grid, row: i32, title: &str, widget: &W) {
+    let label = Label::new(title);
+    label.set_halign(Align::Start);
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(widget, 1, row, 1, 1);
+}
+
+pub fn show_preferences_dialog(parent: &ApplicationWindow, config: &Arc<Mutex<Config>>) {
+    let dialog = Dialog::new_with_buttons(
+        Some("Preferences"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", RESPONSE_CANCEL), ("Accept", RESPONSE_ACCEPT)],
+    );
+
+    let grid = Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+    grid.set_border_width(10);
+    dialog.get_content_area().add(&grid);
+
+    let current = config.lock().unwrap().clone();
+
+    let crossfade = SpinButton::new_with_range(0.0, MAX_CROSSFADE as f64, 1.0);
+    crossfade.set_value(current.crossfade as f64);
+    add_row(&grid, 0, "Crossfade (seconds)", &crossfade);
+
+    let crossfade_curve = ComboBoxText::new();
+    for curve in &[FadeCurve::Linear, FadeCurve::EqualPower] {
+        crossfade_curve.append(curve.name(), curve.name());
+    }
+    crossfade_curve.set_active_id(current.crossfade_curve.name());
+    add_row(&grid, 1, "Crossfade curve", &crossfade_curve);
+
+    let crossfade_same_album = CheckButton::new_with_label("Crossfade tracks of the same album");
+    crossfade_same_album.set_active(current.crossfade_same_album);
+    grid.attach(&crossfade_same_album, 0, 2, 2, 1);
+
+    let replaygain_mode = ComboBoxText::new();
+    for mode in &[GainMode::Off, GainMode::Track, GainMode::Album] {
+        replaygain_mode.append(mode.name(), mode.name());
+    }
+    replaygain_mode.set_active_id(current.replaygain_mode.name());
+    add_row(&grid, 3, "ReplayGain", &replaygain_mode);
+
+    let replaygain_preamp = SpinButton::new_with_range(-MAX_PREAMP as f64, MAX_PREAMP as f64, 0.5);
+    replaygain_preamp.set_value(current.replaygain_preamp as f64);
+    add_row(&grid, 4, "ReplayGain preamp (dB)", &replaygain_preamp);
+
+    let replaygain_prevent_clipping =
+        CheckButton::new_with_label("Prevent clipping using the peak values");
+    replaygain_prevent_clipping.set_active(current.replaygain_prevent_clipping);
+    grid.attach(&replaygain_prevent_clipping, 0, 5, 2, 1);
+
+    let output_format = ComboBoxText::new();
+    output_format.append(AUTOMATIC_FORMAT, "Format of the sound card");
+    output_format.append(SampleFormat::S16.name(), "16 bits, dithered");
+    output_format.append(SampleFormat::S24.name(), "24 bits, dithered");
+    output_format.append(SampleFormat::F32.name(), "32 bits float");
+    output_format.set_active_id(
+        current
+            .output_format
+            .map(|format| format.name())
+            .unwrap_or(AUTOMATIC_FORMAT),
+    );
+    add_row(&grid, 6, "Output format", &output_format);
+
+    let output_rate = ComboBoxText::new();
+    output_rate.append("0", "Rate of each file");
+    for rate in OUTPUT_RATES.iter() {
+        output_rate.append(rate.to_string().as_str(), &format!("{} Hz", rate));
+    }
+    output_rate.set_active_id(current.output_rate.to_string().as_str());
+    add_row(&grid, 7, "Output rate", &output_rate);
+
+    let resampler_quality = ComboBoxText::new();
+    for quality in &[
+        ResamplerQuality::Low,
+        ResamplerQuality::Medium,
+        ResamplerQuality::High,
+    ] {
+        resampler_quality.append(quality.name(), quality.name());
+    }
+    resampler_quality.set_active_id(current.resampler_quality.name());
+    add_row(&grid, 8, "Resampler quality", &resampler_quality);
+
+    let stats_in_tags = CheckButton::new_with_label("Write play counts and ratings to the tags");
+    stats_in_tags.set_active(current.stats_in_tags);
+    grid.attach(&stats_in_tags, 0, 9, 2, 1);
+
+    dialog.show_all();
+    if dialog.run() == RESPONSE_ACCEPT {
+        let mut config = config.lock().unwrap();
+        config.crossfade = crossfade.get_value_as_int() as u32;
+        if let Some(curve) = crossfade_curve
+            .get_active_id()
+            .and_then(|id| FadeCurve::from_name(&id))
+        {
+            config.crossfade_curve = curve;
+        }
+        config.crossfade_same_album = crossfade_same_album.get_active();
+        if let Some(mode) = replaygain_mode
+            .get_active_id()
+            .and_then(|id| GainMode::from_name(&id))
+        {
+            config.replaygain_mode = mode;
+        }
+        config.replaygain_preamp = replaygain_preamp.get_value() as f32;
+        config.replaygain_prevent_clipping = replaygain_prevent_clipping.get_active();
+        if let Some(id) = output_format.get_active_id() {
+            config.output_format = SampleFormat::from_name(&id);
+        }
+        if let Some(rate) = output_rate.get_active_id().and_then(|id| id.parse().ok()) {
+            config.output_rate = rate;
+        }
+        if let Some(quality) = resampler_quality
+            .get_active_id()
+            .and_then(|id| ResamplerQuality::from_name(&id))
+        {
+            config.resampler_quality = quality;
+        }
+        config.stats_in_tags = stats_in_tags.get_active();
+        config.save();
+    }
+    dialog.destroy();
+}
@@ -0,0 +1,122 @@
+use id3::Tag;
+use std::path::Path;
+
+use ape::ApeTag;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GainMode {
+    Off,
+    Track,
+    Album,
+}
+
+impl GainMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            GainMode::Off => "off",
+            GainMode::Track => "track",
+            GainMode::Album => "album",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GainMode> {
+        match name {
+            "off" => Some(GainMode::Off),
+            "track" => Some(GainMode::Track),
+            "album" => Some(GainMode::Album),
+            _ => None,
+        }
+    }
+}
+
+// "-6.54 dB" or "0.988831"
+fn parse_value(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .trim_end_matches("dB")
+        .trim_end_matches("db")
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn db_to_scale(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// Gains in dB and peaks as sample amplitudes (1.0 is full scale)
+#[derive(Clone, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    // ID3v2 TXXX frames first, then an APEv2 tag as written by mp3gain or foobar2000
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> ReplayGain {
+        let mut replay_gain = ReplayGain::default();
+
+        if let Ok(tag) = Tag::read_from_path(&path) {
+            for text in tag.extended_texts() {
+                replay_gain.set(&text.description, &text.value);
+            }
+        }
+
+        if replay_gain.track_gain.is_none() && replay_gain.album_gain.is_none() {
+            if let Some(tag) = ApeTag::read_from_path(&path) {
+                for key in &[
+                    "REPLAYGAIN_TRACK_GAIN",
+                    "REPLAYGAIN_TRACK_PEAK",
+                    "REPLAYGAIN_ALBUM_GAIN",
+                    "REPLAYGAIN_ALBUM_PEAK",
+                ] {
+                    if let Some(value) = tag.get(key) {
+                        replay_gain.set(key, value);
+                    }
+                }
+            }
+        }
+
+        replay_gain
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let field = match key.to_ascii_uppercase().as_str() {
+            "REPLAYGAIN_TRACK_GAIN" => &mut self.track_gain,
+            "REPLAYGAIN_TRACK_PEAK" => &mut self.track_peak,
+            "REPLAYGAIN_ALBUM_GAIN" => &mut self.album_gain,
+            "REPLAYGAIN_ALBUM_PEAK" => &mut self.album_peak,
+            _ => return,
+        };
+        *field = parse_value(value);
+    }
+
+    // Linear factor applied to the samples. The album values are used in album mode and
+    // the track ones otherwise, each falling back to the other when missing.
+    pub fn scale(&self, mode: GainMode, preamp: f32, prevent_clipping: bool) -> f32 {
+        let (gain, peak) = match mode {
+            GainMode::Off => return 1.0,
+            GainMode::Track => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+            GainMode::Album => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+
+        let gain = match gain {
+            Some(gain) => gain,
+            None => return 1.0,
+        };
+
+        let scale = db_to_scale(gain + preamp);
+        match peak {
+            Some(peak) if prevent_clipping && peak > 0.0 => scale.min(1.0 / peak),
+            _ => scale,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResamplerQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl ResamplerQuality {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ResamplerQuality::Low => "low",
+            ResamplerQuality::Medium => "medium",
+            ResamplerQuality::High => "high",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ResamplerQuality> {
+        match name {
+            "low" => Some(ResamplerQuality::Low),
+            "medium" => Some(ResamplerQuality::Medium),
+            "high" => Some(ResamplerQuality::High),
+            _ => None,
+        }
+    }
+
+    // Zero crossings on each side of the filter, table resolution between two of them,
+    // and bandwidth kept below the Nyquist frequency
+    fn parameters(&self) -> (usize, usize, f64) {
+        match *self {
+            ResamplerQuality::Low => (8, 64, 0.85),
+            ResamplerQuality::Medium => (16, 256, 0.92),
+            ResamplerQuality::High => (32, 512, 0.96),
+        }
+    }
+}
+
+// Blackman window, x going from -1 to 1
+fn window(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Band limited interpolation with a windowed sinc filter, for any ratio of rates
+pub struct Resampler {
+    from: u32,
+    to: u32,
+    quality: ResamplerQuality,
+    taps: usize,
+    phases: usize,
+    // Half of the filter, from the center to the last tap
+    table: Vec<f32>,
+    // Input samples per output sample
+    step: f64,
+    buffer: Vec<[f32; 2]>,
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, quality: ResamplerQuality) -> Self {
+        let (zero_crossings, phases, bandwidth) = quality.parameters();
+        // Lower the cutoff when downsampling to remove what the output cannot represent
+        let cutoff = bandwidth * (to as f64 / from as f64).min(1.0);
+        let taps = (zero_crossings as f64 / cutoff).ceil() as usize;
+
+        let table = (0..taps * phases + 2)
+            .map(|index| {
+                let distance = index as f64 / phases as f64;
+                if distance >= taps as f64 {
+                    0.0
+                } else {
+                    (cutoff * sinc(cutoff * distance) * window(distance / taps as f64)) as f32
+                }
+            })
+            .collect();
+
+        Resampler {
+            from,
+            to,
+            quality,
+            taps,
+            phases,
+            table,
+            step: from as f64 / to as f64,
+            // Start centered on the first sample, with silence before it
+            buffer: vec![[0.0; 2]; taps],
+            position: taps as f64,
+        }
+    }
+
+    pub fn matches(&self, from: u32, to: u32, quality: ResamplerQuality) -> bool {
+        self.from == from && self.to == to && self.quality == quality
+    }
+
+    fn coefficient(&self, distance: f64) -> f32 {
+        let index = distance.abs() * self.phases as f64;
+        let lower = index as usize;
+        let fraction = (index - lower as f64) as f32;
+        self.table[lower] + (self.table[lower + 1] - self.table[lower]) * fraction
+    }
+
+    // Append to `output` every sample that can be computed from the input received so far
+    pub fn process(&mut self, input: &[[f32; 2]], output: &mut Vec<[f32; 2]>) {
+        self.buffer.extend_from_slice(input);
+
+        while self.position as usize + self.taps < self.buffer.len() {
+            let center = self.position as usize;
+            let mut sample = [0.0; 2];
+            for index in center + 1 - self.taps..=center + self.taps {
+                let coefficient = self.coefficient(self.position - index as f64);
+                sample[0] += self.buffer[index][0] * coefficient;
+                sample[1] += self.buffer[index][1] * coefficient;
+            }
+            output.push(sample);
+            self.position += self.step;
+        }
+
+        // Only keep what the next samples need
+        let consumed = (self.position as usize + 1).saturating_sub(self.taps);
+        self.buffer.drain(..consumed);
+        self.position -= consumed as f64;
+    }
+
+    // Push out the samples waiting for input after the end of the stream
+    pub fn flush(&mut self, output: &mut Vec<[f32; 2]>) {
+        let silence = vec![[0.0; 2]; self.taps];
+        self.process(&silence, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Resampler, ResamplerQuality};
+    use std::f32::consts::PI;
+
+    fn sine(frequency: f32, rate: u32, frames: usize) -> Vec<[f32; 2]> {
+        (0..frames)
+            .map(|index| {
+                let sample = (2.0 * PI * frequency * index as f32 / rate as f32).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn resample(
+        from: u32,
+        to: u32,
+        quality: ResamplerQuality,
+        input: &[[f32; 2]],
+    ) -> Vec<[f32; 2]> {
+        let mut resampler = Resampler::new(from, to, quality);
+        let mut output = Vec::new();
+        resampler.process(input, &mut output);
+        resampler.flush(&mut output);
+        output
+    }
+
+    #[test]
+    fn output_length() {
+        let input = vec![[0.0; 2]; 44100];
+        let output = resample(44100, 48000, ResamplerQuality::Medium, &input);
+        assert!(
+            (output.len() as i32 - 48000).abs() < 100,
+            "{}",
+            output.len()
+        );
+        let output = resample(48000, 44100, ResamplerQuality::Medium, &input);
+        assert!(
+            (output.len() as i32 - 40516).abs() < 100,
+            "{}",
+            output.len()
+        );
+    }
+
+    #[test]
+    fn constant_level_kept() {
+        for &(from, to) in &[(44100, 48000), (48000, 44100), (44100, 96000)] {
+            let input = vec![[0.5, -0.25]; 10000];
+            let output = resample(from, to, ResamplerQuality::High, &input);
+            for frame in &output[200..output.len() - 200] {
+                assert!(
+                    (frame[0] - 0.5).abs() < 0.005,
+                    "{} to {}: {}",
+                    from,
+                    to,
+                    frame[0]
+                );
+                assert!((frame[1] + 0.25).abs() < 0.005);
+            }
+        }
+    }
+
+    #[test]
+    fn sine_in_passband() {
+        // The first output sample is at the time of the first input sample
+        let input = sine(1000.0, 44100, 44100);
+        let output = resample(44100, 48000, ResamplerQuality::Medium, &input);
+        let expected = sine(1000.0, 48000, 48000);
+        for (frame, expected) in output[500..47000].iter().zip(&expected[500..47000]) {
+            assert!((frame[0] - expected[0]).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn frequencies_above_output_nyquist_removed() {
+        let input = sine(30000.0, 96000, 96000);
+        let output = resample(96000, 44100, ResamplerQuality::Medium, &input);
+        let middle = &output[500..output.len() - 500];
+        let power =
+            middle.iter().map(|frame| frame[0] * frame[0]).sum::<f32>() / middle.len() as f32;
+        assert!(power.sqrt() < 0.01, "{}", power.sqrt());
+    }
+
+    #[test]
+    fn blocks_of_any_size() {
+        let input = sine(440.0, 44100, 10000);
+        let whole = resample(44100, 48000, ResamplerQuality::Low, &input);
+
+        let mut resampler = Resampler::new(44100, 48000, ResamplerQuality::Low);
+        let mut output = Vec::new();
+        for block in input.chunks(333) {
+            resampler.process(block, &mut output);
+        }
+        resampler.flush(&mut output);
+
+        assert_eq!(output.len(), whole.len());
+        for (frame, expected) in output.iter().zip(&whole) {
+            assert!((frame[0] - expected[0]).abs() < 1e-4);
+        }
+    }
+}
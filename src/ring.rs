@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+// Latest samples written by the player, read by the visualizer without either side waiting
+// for the other. A frame overwritten while it is read only shows up as a glitch on screen.
+pub struct SampleRing {
+    // f32 bits, capacity being a power of two
+    samples: Vec<[AtomicU32; 2]>,
+    written: AtomicUsize,
+    rate: AtomicU32,
+    // Frames of the output not played yet, when the last samples were written
+    queued: AtomicUsize,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.next_power_of_two();
+        SampleRing {
+            samples: (0..capacity)
+                .map(|_| [AtomicU32::new(0), AtomicU32::new(0)])
+                .collect(),
+            written: AtomicUsize::new(0),
+            rate: AtomicU32::new(0),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    // Only called from the player thread, once the buffer was handed to the output
+    pub fn write(&self, buffer: &[[f32; 2]], rate: u32, queued: usize) {
+        let mask = self.samples.len() - 1;
+        let written = self.written.load(Ordering::Relaxed);
+        for (index, sample) in buffer.iter().enumerate() {
+            let slot = &self.samples[(written + index) & mask];
+            slot[0].store(sample[0].to_bits(), Ordering::Relaxed);
+            slot[1].store(sample[1].to_bits(), Ordering::Relaxed);
+        }
+        self.rate.store(rate, Ordering::Relaxed);
+        self.queued.store(queued, Ordering::Relaxed);
+        self.written
+            .store(written.wrapping_add(buffer.len()), Ordering::Release);
+    }
+
+    // Total of the samples written so far, to tell whether anything changed
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    // Fill `output` with the samples ending `delay` samples before the latest, the oldest first
+    pub fn read(&self, output: &mut [[f32; 2]], delay: usize) {
+        let mask = self.samples.len() - 1;
+        let written = self.written.load(Ordering::Acquire);
+        let count = output.len().min(self.samples.len());
+        let delay = delay.min(self.samples.len() - count);
+        let start = written.wrapping_sub(count + delay);
+        for (index, sample) in output.iter_mut().take(count).enumerate() {
+            let slot = &self.samples[(start.wrapping_add(index)) & mask];
+            *sample = [
+                f32::from_bits(slot[0].load(Ordering::Relaxed)),
+                f32::from_bits(slot[1].load(Ordering::Relaxed)),
+            ];
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use id3::{self, Tag};
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use loudness::{gated_loudness, LoudnessMeter};
+use mp3::Mp3Decoder;
+use tag_editor::{read_tag, tag_version};
+
+// ReplayGain 2.0 reference level
+const REFERENCE_LOUDNESS: f64 = -18.0;
+const MAX_WORKERS: usize = 4;
+
+pub struct TrackLoudness {
+    pub path: String,
+    // Album artist, or artist, telling apart the albums with the same name
+    pub artist: String,
+    pub album: String,
+    // LUFS, None for silent tracks
+    pub loudness: Option<f64>,
+    pub peak: f64,
+    blocks: Vec<f64>,
+}
+
+impl TrackLoudness {
+    pub fn gain(&self) -> Option<f64> {
+        self.loudness.map(|loudness| REFERENCE_LOUDNESS - loudness)
+    }
+}
+
+pub struct AlbumLoudness {
+    pub artist: String,
+    pub album: String,
+    pub loudness: Option<f64>,
+    pub peak: f64,
+}
+
+impl AlbumLoudness {
+    pub fn gain(&self) -> Option<f64> {
+        self.loudness.map(|loudness| REFERENCE_LOUDNESS - loudness)
+    }
+}
+
+// Shared between the workers and the UI polling it
+pub struct ScanProgress {
+    pub total: usize,
+    pub done: usize,
+    pub tracks: Vec<TrackLoudness>,
+}
+
+impl ScanProgress {
+    pub fn is_finished(&self) -> bool {
+        self.done == self.total
+    }
+
+    // Tracks are grouped by artist and album name, those without an album name are not
+    pub fn albums(&self) -> Vec<AlbumLoudness> {
+        let mut names: Vec<(&str, &str)> = self
+            .tracks
+            .iter()
+            .map(|track| (track.artist.as_str(), track.album.as_str()))
+            .filter(|(_, album)| !album.is_empty())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        names
+            .into_iter()
+            .map(|(artist, album)| {
+                let tracks = || {
+                    self.tracks
+                        .iter()
+                        .filter(move |track| track.artist == artist && track.album == album)
+                };
+                AlbumLoudness {
+                    artist: artist.to_string(),
+                    album: album.to_string(),
+                    loudness: gated_loudness(tracks().flat_map(|track| track.blocks.iter())),
+                    peak: tracks().map(|track| track.peak).fold(0.0, f64::max),
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct WriteProgress {
+    pub total: usize,
+    pub done: usize,
+    pub failures: usize,
+}
+
+impl WriteProgress {
+    pub fn is_finished(&self) -> bool {
+        self.done == self.total
+    }
+}
+
+fn measure(path: &str) -> Option<LoudnessMeter> {
+    let file = File::open(path).ok()?;
+    let mut decoder = Mp3Decoder::new(BufReader::new(file)).ok()?;
+    let channels = decoder.channels();
+    let mut meter = LoudnessMeter::new(decoder.samples_rate(), channels);
+
+    let mut frame = Vec::with_capacity(channels);
+    for sample in decoder.by_ref() {
+        frame.push(sample as f64);
+        if frame.len() == channels {
+            meter.add_frame(&frame);
+            frame.clear();
+        }
+    }
+
+    Some(meter)
+}
+
+// Decode the tracks (path, artist and album) in background threads
+pub fn scan(tracks: Vec<(String, String, String)>) -> Arc<Mutex<ScanProgress>> {
+    let progress = Arc::new(Mutex::new(ScanProgress {
+        total: tracks.len(),
+        done: 0,
+        tracks: Vec::new(),
+    }));
+    let workers = MAX_WORKERS.min(tracks.len());
+    let queue = Arc::new(Mutex::new(tracks.into_iter().collect::<VecDeque<_>>()));
+
+    for _ in 0..workers {
+        let progress = progress.clone();
+        let queue = queue.clone();
+        thread::spawn(move || loop {
+            let next = queue.lock().unwrap().pop_front();
+            let (path, artist, album) = match next {
+                Some(track) => track,
+                None => break,
+            };
+
+            let meter = measure(&path);
+            let mut progress = progress.lock().unwrap();
+            progress.done += 1;
+            if let Some(meter) = meter {
+                progress.tracks.push(TrackLoudness {
+                    path,
+                    artist,
+                    album,
+                    loudness: meter.loudness(),
+                    peak: meter.true_peak(),
+                    blocks: meter.blocks().to_vec(),
+                });
+            }
+        });
+    }
+
+    progress
+}
+
+fn set_extended_text(tag: &mut Tag, description: &str, value: String) {
+    tag.remove_extended_text(Some(description), None);
+    tag.add_extended_text(description, value);
+}
+
+// Store the results as ReplayGain TXXX frames
+pub fn write_tags(track: &TrackLoudness, album: Option<&AlbumLoudness>) -> id3::Result<()> {
+    let mut tag = read_tag(&track.path)?;
+
+    if let Some(gain) = track.gain() {
+        set_extended_text(&mut tag, "REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", gain));
+        set_extended_text(
+            &mut tag,
+            "REPLAYGAIN_TRACK_PEAK",
+            format!("{:.6}", track.peak),
+        );
+    }
+    if let Some(album) = album {
+        if let Some(gain) = album.gain() {
+            set_extended_text(&mut tag, "REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", gain));
+            set_extended_text(
+                &mut tag,
+                "REPLAYGAIN_ALBUM_PEAK",
+                format!("{:.6}", album.peak),
+            );
+        }
+    }
+
+    tag.write_to_path(&track.path, tag_version(&track.path))
+}
+
+// Write the tags of the tracks in a background thread, with the results of their album
+pub fn write_all_tags(
+    tracks: Vec<TrackLoudness>,
+    albums: Vec<AlbumLoudness>,
+) -> Arc<Mutex<WriteProgress>> {
+    let progress = Arc::new(Mutex::new(WriteProgress {
+        total: tracks.len(),
+        done: 0,
+        failures: 0,
+    }));
+    let albums: HashMap<(String, String), AlbumLoudness> = albums
+        .into_iter()
+        .map(|album| ((album.artist.clone(), album.album.clone()), album))
+        .collect();
+
+    let thread_progress = progress.clone();
+    thread::spawn(move || {
+        for track in &tracks {
+            let key = (track.artist.clone(), track.album.clone());
+            let failed = write_tags(track, albums.get(&key)).is_err();
+            let mut progress = thread_progress.lock().unwrap();
+            progress.done += 1;
+            if failed {
+                progress.failures += 1;
+            }
+        }
+    });
+
+    progress
+}
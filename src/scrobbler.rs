@@ -0,0 +1,131 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use config::config_dir;
+use metadata::Metadata;
+use stats::played_threshold;
+use unix_time;
+use State;
+
+const SCROBBLER_LOG_FILE: &str = ".scrobbler.log";
+const HEADER: &str = concat!(
+    "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/rusic ",
+    env!("CARGO_PKG_VERSION"),
+    "\n"
+);
+
+// Milliseconds. Shorter tracks are not scrobbled, the others once they were listened to as
+// long as a counted play.
+const MIN_DURATION: u64 = 30_000;
+
+struct Listen {
+    path: String,
+    // Seconds since the epoch
+    started: u64,
+    // Milliseconds of the track played, the parts skipped by seeking left out
+    listened: u64,
+}
+
+// Completed plays appended to a .scrobbler.log file, for tools submitting them later.
+// Driven by the player thread as tracks start, play and end. The tags are read and the
+// file written by a thread of its own, not to hold up the playback.
+pub struct Scrobbler {
+    app_state: Arc<Mutex<State>>,
+    listens: Sender<Listen>,
+    listen: Option<Listen>,
+}
+
+impl Scrobbler {
+    pub(crate) fn new(app_state: Arc<Mutex<State>>) -> Self {
+        let (listens, receiver) = channel::<Listen>();
+        let thread_state = app_state.clone();
+        thread::spawn(move || {
+            for listen in receiver {
+                let duration = thread_state
+                    .lock()
+                    .unwrap()
+                    .durations
+                    .get(&listen.path)
+                    .cloned();
+                if let Some(duration) = duration {
+                    if duration > MIN_DURATION && listen.listened >= played_threshold(duration) {
+                        append(&listen, duration);
+                    }
+                }
+            }
+        });
+        Scrobbler {
+            app_state,
+            listens,
+            listen: None,
+        }
+    }
+
+    // The previous track ends
+    pub fn start(&mut self, path: &str) {
+        self.finish();
+        self.listen = Some(Listen {
+            path: path.to_string(),
+            started: unix_time(),
+            listened: 0,
+        });
+        self.app_state.lock().unwrap().listened = Some((path.to_string(), 0));
+    }
+
+    // Also shared with the play counts through the application state
+    pub fn listened(&mut self, millis: u64) {
+        if let Some(ref mut listen) = self.listen {
+            listen.listened += millis;
+            if let Some((_, ref mut listened)) = self.app_state.lock().unwrap().listened {
+                *listened = listen.listened;
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if let Some(listen) = self.listen.take() {
+            self.app_state.lock().unwrap().listened = None;
+            let _ = self.listens.send(listen);
+        }
+    }
+}
+
+// "artist\talbum\ttitle\ttrack\tseconds\tL\ttimestamp\tmusicbrainz id". The tracks without
+// an artist or a title cannot be submitted, and are left out.
+fn append(listen: &Listen, duration: u64) {
+    let metadata = Metadata::read_from_path(&listen.path);
+    if metadata.artist.is_empty() || metadata.title.is_empty() {
+        return;
+    }
+    let file = match config_dir() {
+        Some(dir) => dir.join(SCROBBLER_LOG_FILE),
+        None => return,
+    };
+    let output = OpenOptions::new().create(true).append(true).open(file);
+    if let Ok(mut output) = output {
+        let empty = output
+            .metadata()
+            .map(|file| file.len() == 0)
+            .unwrap_or(false);
+        if empty {
+            let _ = output.write_all(HEADER.as_bytes());
+        }
+        let text = |value: &str| value.replace(['\t', '\n'], " ");
+        let _ = writeln!(
+            output,
+            "{}\t{}\t{}\t{}\t{}\tL\t{}\t",
+            text(&metadata.artist),
+            text(&metadata.album),
+            text(&metadata.title),
+            metadata
+                .track
+                .map(|track| track.to_string())
+                .unwrap_or_default(),
+            duration / 1_000,
+            listen.started
+        );
+    }
+}
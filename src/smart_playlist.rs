@@ -0,0 +1,576 @@
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use cache::stable_hash;
+use config::{config_dir, parse_bool};
+use library::Track;
+use stats::{Stats, TrackStats};
+use unix_time;
+
+const SMART_PLAYLISTS_FILE: &str = "smart_playlists";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Track,
+    Duration,
+    Plays,
+    Skips,
+    Rating,
+    DaysSincePlayed,
+}
+
+pub const FIELDS: [Field; 11] = [
+    Field::Title,
+    Field::Artist,
+    Field::Album,
+    Field::Genre,
+    Field::Year,
+    Field::Track,
+    Field::Duration,
+    Field::Plays,
+    Field::Skips,
+    Field::Rating,
+    Field::DaysSincePlayed,
+];
+
+pub enum Value {
+    Text(String),
+    // Seconds for the duration
+    Number(Option<i64>),
+}
+
+impl Field {
+    pub fn title(self) -> &'static str {
+        match self {
+            Field::Title => "Title",
+            Field::Artist => "Artist",
+            Field::Album => "Album",
+            Field::Genre => "Genre",
+            Field::Year => "Year",
+            Field::Track => "Track",
+            Field::Duration => "Duration (s)",
+            Field::Plays => "Play count",
+            Field::Skips => "Skip count",
+            Field::Rating => "Rating",
+            Field::DaysSincePlayed => "Days since played",
+        }
+    }
+
+    // Name in the smart playlists file
+    fn key(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Artist => "artist",
+            Field::Album => "album",
+            Field::Genre => "genre",
+            Field::Year => "year",
+            Field::Track => "track",
+            Field::Duration => "duration",
+            Field::Plays => "plays",
+            Field::Skips => "skips",
+            Field::Rating => "rating",
+            Field::DaysSincePlayed => "days_since_played",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Field> {
+        FIELDS.iter().cloned().find(|field| field.key() == key)
+    }
+
+    pub fn is_number(self) -> bool {
+        !matches!(
+            self,
+            Field::Title | Field::Artist | Field::Album | Field::Genre
+        )
+    }
+
+    fn value(self, track: &Track, stats: &TrackStats, now: u64) -> Value {
+        match self {
+            Field::Title => Value::Text(track.title()),
+            Field::Artist => Value::Text(track.artist.clone()),
+            Field::Album => Value::Text(track.album.clone()),
+            Field::Genre => Value::Text(track.genre.clone()),
+            Field::Year => Value::Number(track.year.map(i64::from)),
+            Field::Track => Value::Number(track.track.map(i64::from)),
+            Field::Duration => {
+                Value::Number(track.duration.map(|duration| (duration / 1_000) as i64))
+            }
+            Field::Plays => Value::Number(Some(stats.plays as i64)),
+            Field::Skips => Value::Number(Some(stats.skips as i64)),
+            Field::Rating => Value::Number(Some(stats.rating as i64)),
+            // Never played tracks have no value
+            Field::DaysSincePlayed => Value::Number(
+                stats
+                    .last_played
+                    .map(|played| (now.saturating_sub(played) / SECONDS_PER_DAY) as i64),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operator {
+    Is,
+    IsNot,
+    Contains,
+    LessThan,
+    GreaterThan,
+}
+
+pub const OPERATORS: [Operator; 5] = [
+    Operator::Is,
+    Operator::IsNot,
+    Operator::Contains,
+    Operator::LessThan,
+    Operator::GreaterThan,
+];
+
+impl Operator {
+    pub fn title(self) -> &'static str {
+        match self {
+            Operator::Is => "is",
+            Operator::IsNot => "is not",
+            Operator::Contains => "contains",
+            Operator::LessThan => "is less than",
+            Operator::GreaterThan => "is greater than",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Operator::Is => "is",
+            Operator::IsNot => "is-not",
+            Operator::Contains => "contains",
+            Operator::LessThan => "less",
+            Operator::GreaterThan => "greater",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Operator> {
+        OPERATORS
+            .iter()
+            .cloned()
+            .find(|operator| operator.key() == key)
+    }
+}
+
+#[derive(Clone)]
+pub struct Rule {
+    pub field: Field,
+    pub operator: Operator,
+    pub value: String,
+}
+
+impl Rule {
+    // Text is compared ignoring case, and numbers as numbers: a track without the number,
+    // or a value that is not one, matches nothing but "is not"
+    fn matches(&self, track: &Track, stats: &TrackStats, now: u64) -> bool {
+        match self.field.value(track, stats, now) {
+            Value::Text(text) => {
+                let (text, value) = (text.to_lowercase(), self.value.trim().to_lowercase());
+                match self.operator {
+                    Operator::Is => text == value,
+                    Operator::IsNot => text != value,
+                    Operator::Contains => text.contains(&value),
+                    Operator::LessThan => text < value,
+                    Operator::GreaterThan => text > value,
+                }
+            }
+            Value::Number(number) => {
+                let ordering = match (number, self.value.trim().parse::<i64>().ok()) {
+                    (Some(number), Some(value)) => number.cmp(&value),
+                    _ => return self.operator == Operator::IsNot,
+                };
+                match self.operator {
+                    Operator::Is | Operator::Contains => ordering == Ordering::Equal,
+                    Operator::IsNot => ordering != Ordering::Equal,
+                    Operator::LessThan => ordering == Ordering::Less,
+                    Operator::GreaterThan => ordering == Ordering::Greater,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Order {
+    // Shuffled once, the order kept when the playlist is refreshed
+    Random,
+    Ascending(Field),
+    Descending(Field),
+}
+
+#[derive(Clone)]
+pub struct SmartPlaylist {
+    pub name: String,
+    // Every rule has to match, otherwise any
+    pub match_all: bool,
+    pub rules: Vec<Rule>,
+    pub limit: Option<usize>,
+    pub order: Order,
+    seed: u64,
+}
+
+impl SmartPlaylist {
+    pub fn new(name: &str) -> Self {
+        SmartPlaylist {
+            name: name.to_string(),
+            match_all: true,
+            rules: Vec::new(),
+            limit: None,
+            order: Order::Ascending(Field::Artist),
+            seed: unix_time(),
+        }
+    }
+
+    fn matches(&self, track: &Track, stats: &TrackStats, now: u64) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        if self.match_all {
+            self.rules
+                .iter()
+                .all(|rule| rule.matches(track, stats, now))
+        } else {
+            self.rules
+                .iter()
+                .any(|rule| rule.matches(track, stats, now))
+        }
+    }
+
+    // Paths of the matching tracks, ordered and limited
+    pub fn evaluate<'a, I: Iterator<Item = &'a Track>>(
+        &self,
+        tracks: I,
+        stats: &Stats,
+    ) -> Vec<String> {
+        let now = unix_time();
+        let mut tracks: Vec<(&Track, TrackStats)> = tracks
+            .map(|track| (track, stats.get(&track.path)))
+            .filter(|(track, stats)| self.matches(track, stats, now))
+            .collect();
+        // The path last, so that the order does not depend on the library's
+        tracks.sort_by(|first, second| match self.order {
+            Order::Random => self
+                .shuffle_key(first.0)
+                .cmp(&self.shuffle_key(second.0))
+                .then_with(|| first.0.path.cmp(&second.0.path)),
+            Order::Ascending(field) => compare(field, false, first, second, now),
+            Order::Descending(field) => compare(field, true, first, second, now),
+        });
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+        tracks.iter().map(|(track, _)| track.path.clone()).collect()
+    }
+
+    // The same across builds, so that the saved seed keeps the order. The bits are mixed
+    // again, the paths of an album only differing in their last bytes.
+    fn shuffle_key(&self, track: &Track) -> u64 {
+        let mut data = track.path.as_bytes().to_vec();
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        let mut key = stable_hash(&data);
+        key = (key ^ (key >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        key = (key ^ (key >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        key ^ (key >> 31)
+    }
+
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("name", self.name.clone()),
+            ("match_all", self.match_all.to_string()),
+        ];
+        for rule in &self.rules {
+            let rule = format!(
+                "{}\t{}\t{}",
+                rule.field.key(),
+                rule.operator.key(),
+                rule.value.replace(['\t', '\n'], " ")
+            );
+            entries.push(("rule", rule));
+        }
+        if let Some(limit) = self.limit {
+            entries.push(("limit", limit.to_string()));
+        }
+        let order = match self.order {
+            Order::Random => "random".to_string(),
+            Order::Ascending(field) => field.key().to_string(),
+            Order::Descending(field) => format!("-{}", field.key()),
+        };
+        entries.push(("order", order));
+        entries.push(("seed", self.seed.to_string()));
+        entries
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "match_all" => {
+                if let Some(match_all) = parse_bool(value) {
+                    self.match_all = match_all;
+                }
+            }
+            "rule" => {
+                let fields: Vec<&str> = value.splitn(3, '\t').collect();
+                if let [field, operator, value] = fields[..] {
+                    if let (Some(field), Some(operator)) =
+                        (Field::from_key(field), Operator::from_key(operator))
+                    {
+                        self.rules.push(Rule {
+                            field,
+                            operator,
+                            value: value.to_string(),
+                        });
+                    }
+                }
+            }
+            "limit" => self.limit = value.parse().ok(),
+            "order" => {
+                let order = if value == "random" {
+                    Some(Order::Random)
+                } else if let Some(key) = value.strip_prefix('-') {
+                    Field::from_key(key).map(Order::Descending)
+                } else {
+                    Field::from_key(value).map(Order::Ascending)
+                };
+                if let Some(order) = order {
+                    self.order = order;
+                }
+            }
+            "seed" => {
+                if let Ok(seed) = value.parse() {
+                    self.seed = seed;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Missing values last whatever the direction, then by path
+fn compare(
+    field: Field,
+    descending: bool,
+    (first, first_stats): &(&Track, TrackStats),
+    (second, second_stats): &(&Track, TrackStats),
+    now: u64,
+) -> Ordering {
+    let direction = |ordering: Ordering| {
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    };
+    let ordering = match (
+        field.value(first, first_stats, now),
+        field.value(second, second_stats, now),
+    ) {
+        (Value::Text(first), Value::Text(second)) => match (first.is_empty(), second.is_empty()) {
+            (false, false) => direction(first.to_lowercase().cmp(&second.to_lowercase())),
+            (first, second) => first.cmp(&second),
+        },
+        (Value::Number(first), Value::Number(second)) => match (first, second) {
+            (Some(first), Some(second)) => direction(first.cmp(&second)),
+            (first, second) => first.is_none().cmp(&second.is_none()),
+        },
+        _ => Ordering::Equal,
+    };
+    ordering.then_with(|| first.path.cmp(&second.path))
+}
+
+// Saved as "key = value" lines, each playlist starting with its name
+pub struct SmartPlaylists {
+    pub playlists: Vec<SmartPlaylist>,
+    // Name of the one in the playlist, and the paths it added, for the next refresh
+    pub loaded: Option<(String, Vec<String>)>,
+    file: Option<PathBuf>,
+    // Incremented on every change, so that the views know when to refresh
+    revision: u32,
+}
+
+impl SmartPlaylists {
+    pub fn load() -> Self {
+        let file = config_dir().map(|dir| dir.join(SMART_PLAYLISTS_FILE));
+        let mut playlists: Vec<SmartPlaylist> = Vec::new();
+        if let Some(reader) = file.as_ref().and_then(|file| File::open(file).ok()) {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                let mut fields = line.splitn(2, '=');
+                let (key, value) = match (fields.next(), fields.next()) {
+                    (Some(key), Some(value)) => (key.trim(), value.trim()),
+                    _ => continue,
+                };
+                if key == "name" {
+                    let mut playlist = SmartPlaylist::new(value);
+                    playlist.seed = 0;
+                    playlists.push(playlist);
+                } else if let Some(playlist) = playlists.last_mut() {
+                    playlist.set(key, value);
+                }
+            }
+        }
+
+        SmartPlaylists {
+            playlists,
+            loaded: None,
+            file,
+            revision: 0,
+        }
+    }
+
+    fn save(&self) {
+        let file = self.file.as_ref().and_then(|file| File::create(file).ok());
+        if let Some(mut file) = file {
+            for playlist in &self.playlists {
+                for (key, value) in playlist.entries() {
+                    let _ = writeln!(file, "{} = {}", key, value);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SmartPlaylist> {
+        self.playlists.iter().find(|playlist| playlist.name == name)
+    }
+
+    // Replaces the playlist named `previous`, or is added after the others
+    pub fn set(&mut self, previous: Option<&str>, playlist: SmartPlaylist) {
+        let index = previous.and_then(|previous| {
+            self.playlists
+                .iter()
+                .position(|other| other.name == previous)
+        });
+        match index {
+            Some(index) => self.playlists[index] = playlist,
+            None => self.playlists.push(playlist),
+        }
+        self.revision += 1;
+        self.save();
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.playlists.retain(|playlist| playlist.name != name);
+        self.revision += 1;
+        self.save();
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, Field, Operator, Rule};
+    use library::Track;
+    use stats::TrackStats;
+
+    fn track(path: &str, artist: &str, year: Option<i32>) -> Track {
+        Track {
+            path: path.to_string(),
+            mtime: 0,
+            size: 0,
+            title: String::new(),
+            artist: artist.to_string(),
+            album: String::new(),
+            genre: String::new(),
+            year,
+            track: None,
+            duration: None,
+            cover: None,
+        }
+    }
+
+    fn matches(field: Field, operator: Operator, value: &str, track: &Track) -> bool {
+        let rule = Rule {
+            field,
+            operator,
+            value: value.to_string(),
+        };
+        rule.matches(track, &TrackStats::default(), 0)
+    }
+
+    fn sorted(field: Field, descending: bool, tracks: &[Track]) -> Vec<String> {
+        let mut tracks: Vec<(&Track, TrackStats)> = tracks
+            .iter()
+            .map(|track| (track, TrackStats::default()))
+            .collect();
+        tracks.sort_by(|first, second| compare(field, descending, first, second, 0));
+        tracks.iter().map(|(track, _)| track.path.clone()).collect()
+    }
+
+    #[test]
+    fn text_rules_ignore_case() {
+        let track = track("a.mp3", "The Band", None);
+        assert!(matches(Field::Artist, Operator::Is, " the band ", &track));
+        assert!(matches(Field::Artist, Operator::Contains, "BAND", &track));
+        assert!(!matches(Field::Artist, Operator::IsNot, "the band", &track));
+        assert!(matches(Field::Title, Operator::Is, "a", &track));
+    }
+
+    #[test]
+    fn number_rules() {
+        let track = track("a.mp3", "", Some(1999));
+        assert!(matches(Field::Year, Operator::LessThan, "2000", &track));
+        assert!(!matches(Field::Year, Operator::GreaterThan, "2000", &track));
+        assert!(matches(Field::Year, Operator::Is, "1999", &track));
+        assert!(matches(Field::Plays, Operator::Is, "0", &track));
+    }
+
+    #[test]
+    fn missing_numbers_only_match_is_not() {
+        let track = track("a.mp3", "", None);
+        for &operator in &[Operator::Is, Operator::LessThan, Operator::GreaterThan] {
+            assert!(!matches(Field::Year, operator, "2000", &track));
+        }
+        assert!(matches(Field::Year, Operator::IsNot, "2000", &track));
+        assert!(!matches(Field::Plays, Operator::LessThan, "many", &track));
+        assert!(matches(Field::Plays, Operator::IsNot, "many", &track));
+    }
+
+    #[test]
+    fn missing_values_sort_last() {
+        let tracks = [
+            track("a.mp3", "", Some(2001)),
+            track("b.mp3", "", None),
+            track("c.mp3", "", Some(1999)),
+            track("d.mp3", "", None),
+        ];
+        assert_eq!(
+            sorted(Field::Year, false, &tracks),
+            vec!["c.mp3", "a.mp3", "b.mp3", "d.mp3"]
+        );
+        assert_eq!(
+            sorted(Field::Year, true, &tracks),
+            vec!["a.mp3", "c.mp3", "b.mp3", "d.mp3"]
+        );
+        assert_eq!(
+            sorted(Field::DaysSincePlayed, true, &tracks),
+            vec!["a.mp3", "b.mp3", "c.mp3", "d.mp3"]
+        );
+    }
+
+    #[test]
+    fn empty_text_sorts_last() {
+        let tracks = [
+            track("a.mp3", "", None),
+            track("b.mp3", "beta", None),
+            track("c.mp3", "Alpha", None),
+        ];
+        assert_eq!(
+            sorted(Field::Artist, false, &tracks),
+            vec!["c.mp3", "b.mp3", "a.mp3"]
+        );
+        assert_eq!(
+            sorted(Field::Artist, true, &tracks),
+            vec!["b.mp3", "c.mp3", "a.mp3"]
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use gtk::{
+    Align, ApplicationWindow, BoxExt, Button, ButtonExt, CheckButton, ComboBoxExt, ComboBoxText,
+    ComboBoxTextExt, ContainerExt, Continue, Dialog, DialogExt, DialogFlags, Entry, EntryExt, Grid,
+    GridExt, GtkWindowExt, Label, LabelExt, ListStore, ListStoreExt, ListStoreExtManual,
+    Orientation, SpinButton, SpinButtonExt, ToggleButtonExt, TreeModelExt, TreeSelectionExt,
+    TreeView, TreeViewExt, Type, WidgetExt, Window, WindowType,
+};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use dialog::{add_column, scrolled, RESPONSE_ACCEPT, RESPONSE_CANCEL};
+use library::Library;
+use millis_to_minutes;
+use playlist::Playlist;
+use smart_playlist::{Order, Rule, SmartPlaylist, SmartPlaylists, FIELDS, OPERATORS};
+use stats::Stats;
+use tag_editor::show_error;
+use App;
+
+const MAX_LIMIT: f64 = 10_000.0;
+
+const TITLE_COLUMN: u32 = 0;
+const ARTIST_COLUMN: u32 = 1;
+const ALBUM_COLUMN: u32 = 2;
+const DURATION_COLUMN: u32 = 3;
+
+struct RuleRow {
+    container: gtk::Box,
+    field: ComboBoxText,
+    operator: ComboBoxText,
+    value: Entry,
+}
+
+impl RuleRow {
+    fn rule(&self) -> Option<Rule> {
+        Some(Rule {
+            field: *FIELDS.get(self.field.get_active() as usize)?,
+            operator: *OPERATORS.get(self.operator.get_active() as usize)?,
+            value: self.value.get_text().unwrap_or_default(),
+        })
+    }
+}
+
+fn add_rule_row(rules_box: &gtk::Box, rows: &Rc<RefCell<Vec<RuleRow>>>, rule: Option<&Rule>) {
+    let container = gtk::Box::new(Orientation::Horizontal, 6);
+    let field = ComboBoxText::new();
+    for field_choice in &FIELDS {
+        field.append_text(field_choice.title());
+    }
+    let operator = ComboBoxText::new();
+    for operator_choice in &OPERATORS {
+        operator.append_text(operator_choice.title());
+    }
+    let value = Entry::new();
+    value.set_hexpand(true);
+    let remove = Button::new_with_label("Remove");
+
+    let index = |position: Option<usize>| position.unwrap_or(0) as i32;
+    field.set_active(index(
+        rule.and_then(|rule| FIELDS.iter().position(|&other| other == rule.field)),
+    ));
+    operator.set_active(index(rule.and_then(|rule| {
+        OPERATORS.iter().position(|&other| other == rule.operator)
+    })));
+    if let Some(rule) = rule {
+        value.set_text(&rule.value);
+    }
+
+    container.add(&field);
+    container.add(&operator);
+    container.add(&value);
+    container.add(&remove);
+    rules_box.add(&container);
+    container.show_all();
+
+    let remove_rows = rows.clone();
+    let remove_box = rules_box.clone();
+    let remove_container = container.clone();
+    remove.connect_clicked(move |_| {
+        remove_rows
+            .borrow_mut()
+            .retain(|row| row.container != remove_container);
+        remove_box.remove(&remove_container);
+    });
+
+    rows.borrow_mut().push(RuleRow {
+        container,
+        field,
+        operator,
+        value,
+    });
+}
+
+fn grid_label(grid: &Grid, text: &str, row: i32) {
+    let label = Label::new(text);
+    label.set_halign(Align::Start);
+    grid.attach(&label, 0, row, 1, 1);
+}
+
+// Rule editor. `taken` are the names of the other smart playlists. None when cancelled.
+fn edit_smart_playlist(
+    parent: &Window,
+    playlist: &SmartPlaylist,
+    taken: &[String],
+) -> Option<SmartPlaylist> {
+    let dialog = Dialog::new_with_buttons(
+        Some("Smart playlist"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", RESPONSE_CANCEL), ("Save", RESPONSE_ACCEPT)],
+    );
+    dialog.set_default_size(600, -1);
+
+    let grid = Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+    grid.set_border_width(10);
+    dialog.get_content_area().add(&grid);
+
+    grid_label(&grid, "Name", 0);
+    let name = Entry::new();
+    name.set_text(&playlist.name);
+    name.set_hexpand(true);
+    grid.attach(&name, 1, 0, 1, 1);
+
+    grid_label(&grid, "Match", 1);
+    let match_all = ComboBoxText::new();
+    match_all.append_text("all the rules");
+    match_all.append_text("any rule");
+    match_all.set_active(if playlist.match_all { 0 } else { 1 });
+    grid.attach(&match_all, 1, 1, 1, 1);
+
+    let rules_box = gtk::Box::new(Orientation::Vertical, 6);
+    grid.attach(&rules_box, 0, 2, 2, 1);
+    let rows = Rc::new(RefCell::new(Vec::new()));
+    for rule in &playlist.rules {
+        add_rule_row(&rules_box, &rows, Some(rule));
+    }
+    let add_rule = Button::new_with_label("Add rule");
+    grid.attach(&add_rule, 0, 3, 1, 1);
+    let add_rows = rows.clone();
+    let add_box = rules_box.clone();
+    add_rule.connect_clicked(move |_| {
+        add_rule_row(&add_box, &add_rows, None);
+    });
+
+    let limited = CheckButton::new_with_label("Limit to");
+    limited.set_active(playlist.limit.is_some());
+    grid.attach(&limited, 0, 4, 1, 1);
+    let limit = SpinButton::new_with_range(1.0, MAX_LIMIT, 1.0);
+    limit.set_value(playlist.limit.unwrap_or(50) as f64);
+    limit.set_sensitive(playlist.limit.is_some());
+    grid.attach(&limit, 1, 4, 1, 1);
+    let limit_spin = limit.clone();
+    limited.connect_toggled(move |limited| {
+        limit_spin.set_sensitive(limited.get_active());
+    });
+
+    // Random first, then each field
+    grid_label(&grid, "Order by", 5);
+    let order_box = gtk::Box::new(Orientation::Horizontal, 6);
+    let order = ComboBoxText::new();
+    order.append_text("Random");
+    for field in &FIELDS {
+        order.append_text(field.title());
+    }
+    let descending = CheckButton::new_with_label("Descending");
+    let (order_field, is_descending) = match playlist.order {
+        Order::Random => (None, false),
+        Order::Ascending(field) => (Some(field), false),
+        Order::Descending(field) => (Some(field), true),
+    };
+    let order_index = order_field
+        .and_then(|field| FIELDS.iter().position(|&other| other == field))
+        .map_or(0, |index| index as i32 + 1);
+    order.set_active(order_index);
+    descending.set_active(is_descending);
+    order_box.add(&order);
+    order_box.add(&descending);
+    grid.attach(&order_box, 1, 5, 1, 1);
+
+    dialog.show_all();
+    let edited = loop {
+        if dialog.run() != RESPONSE_ACCEPT {
+            break None;
+        }
+
+        let mut edited = playlist.clone();
+        edited.name = name.get_text().unwrap_or_default().trim().to_string();
+        edited.match_all = match_all.get_active() == 0;
+        edited.rules = rows.borrow().iter().filter_map(RuleRow::rule).collect();
+        edited.limit = if limited.get_active() {
+            Some(limit.get_value_as_int() as usize)
+        } else {
+            None
+        };
+        let order_field = match order.get_active() {
+            index if index > 0 => FIELDS.get(index as usize - 1).cloned(),
+            _ => None,
+        };
+        edited.order = match order_field {
+            Some(field) if descending.get_active() => Order::Descending(field),
+            Some(field) => Order::Ascending(field),
+            None => Order::Random,
+        };
+
+        if edited.name.is_empty() {
+            show_error(&dialog, "The smart playlist needs a name", "");
+            continue;
+        }
+        if taken.contains(&edited.name) {
+            show_error(
+                &dialog,
+                "Another smart playlist has this name",
+                &edited.name,
+            );
+            continue;
+        }
+        let not_number = edited
+            .rules
+            .iter()
+            .find(|rule| rule.field.is_number() && rule.value.trim().parse::<i64>().is_err());
+        if let Some(rule) = not_number {
+            show_error(
+                &dialog,
+                &format!("{} is compared to a number", rule.field.title()),
+                &rule.value,
+            );
+            continue;
+        }
+        break Some(edited);
+    };
+    dialog.destroy();
+    edited
+}
+
+// Put the tracks of the smart playlist in the playlist, in its order after the other rows.
+// The rows it added before and no longer matching are removed, unless being played.
+fn load_smart_playlist(
+    name: &str,
+    smart_playlists: &RefCell<SmartPlaylists>,
+    library: &Mutex<Library>,
+    stats: &RefCell<Stats>,
+    playlist: &Playlist,
+) {
+    let mut smart_playlists = smart_playlists.borrow_mut();
+    let mut paths = match smart_playlists.get(name) {
+        Some(smart_playlist) => {
+            smart_playlist.evaluate(library.lock().unwrap().tracks(), &stats.borrow())
+        }
+        None => return,
+    };
+    let previous: HashSet<String> = smart_playlists
+        .loaded
+        .take()
+        .map(|(_, paths)| paths.into_iter().collect())
+        .unwrap_or_default();
+
+    // Removed with a later refresh, once another track is played
+    if let Some(kept) = playlist.sync_paths(&previous, &paths) {
+        paths.push(kept);
+    }
+    smart_playlists.loaded = Some((name.to_string(), paths));
+}
+
+// The saved smart playlists, and the tracks matching the selected one
+struct SmartPlaylistsView {
+    smart_playlists: Rc<RefCell<SmartPlaylists>>,
+    library: Arc<Mutex<Library>>,
+    stats: Rc<RefCell<Stats>>,
+    names_view: TreeView,
+    names_model: ListStore,
+    tracks_model: ListStore,
+    status: Label,
+    // Library, smart playlists and statistics revisions shown
+    revisions: Cell<Option<(u64, u32, u32)>>,
+}
+
+impl SmartPlaylistsView {
+    fn selected_name(&self) -> Option<String> {
+        let (model, iter) = self.names_view.get_selection().get_selected()?;
+        model.get_value(&iter, 0).get::<String>()
+    }
+
+    fn fill_names(&self, selected: Option<String>) {
+        self.names_model.clear();
+        for playlist in &self.smart_playlists.borrow().playlists {
+            let iter = self
+                .names_model
+                .insert_with_values(None, &[0], &[&playlist.name]);
+            if Some(&playlist.name) == selected.as_ref() {
+                self.names_view.get_selection().select_iter(&iter);
+            }
+        }
+    }
+
+    fn fill_tracks(&self) {
+        self.tracks_model.clear();
+        let name = match self.selected_name() {
+            Some(name) => name,
+            None => {
+                self.status.set_text("");
+                return;
+            }
+        };
+        let smart_playlists = self.smart_playlists.borrow();
+        let library = self.library.lock().unwrap();
+        let paths = match smart_playlists.get(&name) {
+            Some(smart_playlist) => smart_playlist.evaluate(library.tracks(), &self.stats.borrow()),
+            None => return,
+        };
+        let tracks: Vec<_> = paths.iter().filter_map(|path| library.get(path)).collect();
+        for track in &tracks {
+            let duration = track.duration.map(millis_to_minutes).unwrap_or_default();
+            self.tracks_model.insert_with_values(
+                None,
+                &[TITLE_COLUMN, ARTIST_COLUMN, ALBUM_COLUMN, DURATION_COLUMN],
+                &[&track.title(), &track.artist, &track.album, &duration],
+            );
+        }
+        self.status.set_text(&format!("{} tracks", tracks.len()));
+    }
+
+    // Called periodically, the tracks follow the library and the edits
+    fn reload(&self) {
+        let revisions = (
+            self.library.lock().unwrap().revision(),
+            self.smart_playlists.borrow().revision(),
+            self.stats.borrow().revision(),
+        );
+        if self.revisions.get() != Some(revisions) {
+            self.revisions.set(Some(revisions));
+            let selected = self.selected_name();
+            self.fill_names(selected);
+            self.fill_tracks();
+        }
+    }
+}
+
+pub fn show_smart_playlists_window(
+    parent: &ApplicationWindow,
+    smart_playlists: &Rc<RefCell<SmartPlaylists>>,
+    library: &Arc<Mutex<Library>>,
+    stats: &Rc<RefCell<Stats>>,
+    playlist: &Rc<Playlist>,
+) {
+    let window = Window::new(WindowType::Toplevel);
+    window.set_title("Smart playlists");
+    window.set_transient_for(Some(parent));
+    window.set_destroy_with_parent(true);
+    window.set_default_size(800, 500);
+
+    let hbox = gtk::Box::new(Orientation::Horizontal, 6);
+    hbox.set_border_width(10);
+    window.add(&hbox);
+
+    let names_box = gtk::Box::new(Orientation::Vertical, 6);
+    let names_model = ListStore::new(&[Type::String]);
+    let names_view = TreeView::new_with_model(&names_model);
+    add_column(&names_view, "Name", 0);
+    let names_window = scrolled(&names_view);
+    names_window.set_size_request(200, -1);
+    names_box.pack_start(&names_window, true, true, 0);
+    let new_button = Button::new_with_label("New…");
+    names_box.pack_start(&new_button, false, false, 0);
+    let edit_button = Button::new_with_label("Edit…");
+    names_box.pack_start(&edit_button, false, false, 0);
+    let delete_button = Button::new_with_label("Delete");
+    names_box.pack_start(&delete_button, false, false, 0);
+    hbox.pack_start(&names_box, false, false, 0);
+
+    let tracks_box = gtk::Box::new(Orientation::Vertical, 6);
+    let tracks_model = ListStore::new(&[Type::String; 4]);
+    let tracks_view = TreeView::new_with_model(&tracks_model);
+    add_column(&tracks_view, "Title", TITLE_COLUMN);
+    add_column(&tracks_view, "Artist", ARTIST_COLUMN);
+    add_column(&tracks_view, "Album", ALBUM_COLUMN);
+    add_column(&tracks_view, "Duration", DURATION_COLUMN);
+    tracks_box.pack_start(&scrolled(&tracks_view), true, true, 0);
+    let bottom = gtk::Box::new(Orientation::Horizontal, 6);
+    let status = Label::new(None);
+    bottom.pack_start(&status, false, false, 0);
+    let load_button = Button::new_with_label("Load into playlist");
+    bottom.pack_end(&load_button, false, false, 0);
+    tracks_box.pack_start(&bottom, false, false, 0);
+    hbox.pack_start(&tracks_box, true, true, 0);
+
+    let view = Rc::new(SmartPlaylistsView {
+        smart_playlists: smart_playlists.clone(),
+        library: library.clone(),
+        stats: stats.clone(),
+        names_view,
+        names_model,
+        tracks_model,
+        status,
+        revisions: Cell::new(None),
+    });
+
+    let selection_view = view.clone();
+    view.names_view.get_selection().connect_changed(move |_| {
+        selection_view.fill_tracks();
+    });
+
+    let new_window = window.clone();
+    let new_view = view.clone();
+    new_button.connect_clicked(move |_| {
+        let taken: Vec<String> = new_view
+            .smart_playlists
+            .borrow()
+            .playlists
+            .iter()
+            .map(|playlist| playlist.name.clone())
+            .collect();
+        let playlist = SmartPlaylist::new("");
+        if let Some(playlist) = edit_smart_playlist(&new_window, &playlist, &taken) {
+            let name = playlist.name.clone();
+            new_view.smart_playlists.borrow_mut().set(None, playlist);
+            new_view.fill_names(Some(name));
+        }
+    });
+
+    let edit_window = window.clone();
+    let edit_view = view.clone();
+    edit_button.connect_clicked(move |_| {
+        let name = match edit_view.selected_name() {
+            Some(name) => name,
+            None => return,
+        };
+        let (playlist, taken) = {
+            let smart_playlists = edit_view.smart_playlists.borrow();
+            let taken: Vec<String> = smart_playlists
+                .playlists
+                .iter()
+                .map(|playlist| playlist.name.clone())
+                .filter(|other| *other != name)
+                .collect();
+            match smart_playlists.get(&name) {
+                Some(playlist) => (playlist.clone(), taken),
+                None => return,
+            }
+        };
+        if let Some(playlist) = edit_smart_playlist(&edit_window, &playlist, &taken) {
+            let edited_name = playlist.name.clone();
+            let mut smart_playlists = edit_view.smart_playlists.borrow_mut();
+            smart_playlists.set(Some(&name), playlist);
+            // The playlist follows the new rules with the next refresh
+            if let Some((ref mut loaded, _)) = smart_playlists.loaded {
+                if *loaded == name {
+                    *loaded = edited_name.clone();
+                }
+            }
+            drop(smart_playlists);
+            edit_view.fill_names(Some(edited_name));
+        }
+    });
+
+    // The rows it added stay in the playlist
+    let delete_view = view.clone();
+    delete_button.connect_clicked(move |_| {
+        if let Some(name) = delete_view.selected_name() {
+            let mut smart_playlists = delete_view.smart_playlists.borrow_mut();
+            smart_playlists.remove(&name);
+            if smart_playlists
+                .loaded
+                .as_ref()
+                .is_some_and(|(loaded, _)| *loaded == name)
+            {
+                smart_playlists.loaded = None;
+            }
+            drop(smart_playlists);
+            delete_view.fill_names(None);
+        }
+    });
+
+    let load_view = view.clone();
+    let load_playlist = playlist.clone();
+    load_button.connect_clicked(move |_| {
+        if let Some(name) = load_view.selected_name() {
+            load_smart_playlist(
+                &name,
+                &load_view.smart_playlists,
+                &load_view.library,
+                &load_view.stats,
+                &load_playlist,
+            );
+        }
+    });
+
+    window.show_all();
+
+    view.reload();
+    let timeout_window = window.clone();
+    gtk::timeout_add(500, move || {
+        if !timeout_window.is_visible() {
+            return Continue(false);
+        }
+        view.reload();
+        Continue(true)
+    });
+}
+
+impl App {
+    // The smart playlist loaded in the playlist is evaluated again when the library or the
+    // statistics change
+    pub fn connect_smart_playlist_events(&self) {
+        let smart_playlists = self.smart_playlists.clone();
+        let library = self.library.clone();
+        let stats = self.stats.clone();
+        let playlist = self.playlist.clone();
+        let mut revisions = None;
+        gtk::timeout_add(500, move || {
+            let current = (
+                library.lock().unwrap().revision(),
+                smart_playlists.borrow().revision(),
+                stats.borrow().revision(),
+            );
+            if revisions != Some(current) {
+                revisions = Some(current);
+                let loaded = smart_playlists
+                    .borrow()
+                    .loaded
+                    .as_ref()
+                    .map(|(name, _)| name.clone());
+                if let Some(name) = loaded {
+                    load_smart_playlist(&name, &smart_playlists, &library, &stats, &playlist);
+                }
+            }
+            Continue(true)
+        });
+    }
+}
@@ -0,0 +1,369 @@
+use gdk::{self, ModifierType};
+use gtk::{Continue, Inhibit, WidgetExt};
+use id3::frame::Content;
+use id3::{self, Frame};
+use libc;
+
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use config::config_dir;
+use tag_editor::{read_tag, tag_version};
+use unix_time;
+use App;
+
+const STATS_FILE: &str = "stats";
+
+// A play counts once half the track, or this many milliseconds, were reached
+const PLAYED_MILLIS: u64 = 240_000;
+pub const MAX_RATING: u8 = 5;
+// Identifies the POPM frame written by this player
+const POPM_EMAIL: &str = "rusic";
+
+#[derive(Clone, Copy, Default)]
+pub struct TrackStats {
+    pub plays: u32,
+    pub skips: u32,
+    // Seconds since the epoch
+    pub last_played: Option<u64>,
+    // Stars, 0 for unrated
+    pub rating: u8,
+}
+
+// Files written by the writer thread, not to hold up the interface
+enum Save {
+    // Every line of the stats file
+    Entries(String),
+    Tag(String, TrackStats),
+}
+
+// Only the last version of the stats file waiting is written. The old one is kept until
+// the new one is complete.
+fn write_saves(file: Option<PathBuf>, saves: Vec<Save>) {
+    let mut entries = None;
+    for save in saves {
+        match save {
+            Save::Entries(text) => entries = Some(text),
+            Save::Tag(path, stats) => {
+                let _ = write_stats_tag(&path, &stats);
+            }
+        }
+    }
+    if let (Some(file), Some(entries)) = (file, entries) {
+        let temporary = file.with_extension("new");
+        let written = File::create(&temporary).and_then(|output| {
+            let mut output = BufWriter::new(output);
+            output.write_all(entries.as_bytes())?;
+            output.flush()
+        });
+        if written.is_ok() {
+            let _ = fs::rename(&temporary, file);
+        }
+    }
+}
+
+// Listening history and ratings by file, stored one per line as
+// "plays\tskips\tlast_played\trating\tpath", 0 standing for never played
+pub struct Stats {
+    entries: HashMap<String, TrackStats>,
+    saves: Sender<Save>,
+    // Incremented on every change, so that the views know when to refresh
+    revision: u32,
+    // Track being played, and whether its play was counted
+    listening: Option<(String, bool)>,
+}
+
+impl Stats {
+    pub fn load() -> Self {
+        let file = config_dir().map(|dir| dir.join(STATS_FILE));
+        let mut entries = HashMap::new();
+
+        if let Some(reader) = file.as_ref().and_then(|file| File::open(file).ok()) {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                let fields: Vec<&str> = line.splitn(5, '\t').collect();
+                if let [plays, skips, last_played, rating, path] = fields[..] {
+                    let last_played = last_played.parse().unwrap_or(0);
+                    entries.insert(
+                        path.to_string(),
+                        TrackStats {
+                            plays: plays.parse().unwrap_or(0),
+                            skips: skips.parse().unwrap_or(0),
+                            last_played: if last_played > 0 {
+                                Some(last_played)
+                            } else {
+                                None
+                            },
+                            rating: min(rating.parse().unwrap_or(0), MAX_RATING),
+                        },
+                    );
+                }
+            }
+        }
+
+        let (saves, receiver) = channel();
+        thread::spawn(move || {
+            while let Ok(save) = receiver.recv() {
+                let mut saves = vec![save];
+                saves.extend(receiver.try_iter());
+                write_saves(file.clone(), saves);
+            }
+        });
+
+        Stats {
+            entries,
+            saves,
+            revision: 0,
+            listening: None,
+        }
+    }
+
+    fn save(&self) {
+        let mut text = String::new();
+        for (path, stats) in &self.entries {
+            text += &format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                stats.plays,
+                stats.skips,
+                stats.last_played.unwrap_or(0),
+                stats.rating,
+                path
+            );
+        }
+        let _ = self.saves.send(Save::Entries(text));
+    }
+
+    // The POPM and PCNT frames of the file are updated in the background
+    pub fn write_tag(&self, path: &str) {
+        let _ = self.saves.send(Save::Tag(path.to_string(), self.get(path)));
+    }
+
+    pub fn get(&self, path: &str) -> TrackStats {
+        self.entries.get(path).cloned().unwrap_or_default()
+    }
+
+    fn change(&mut self, path: &str, change: impl FnOnce(&mut TrackStats)) {
+        change(self.entries.entry(path.to_string()).or_default());
+        self.revision += 1;
+        self.save();
+    }
+
+    // Called periodically with the milliseconds of the track heard, as the scrobbler counts
+    // them. Returns the track when its play was just counted.
+    pub fn update(
+        &mut self,
+        path: Option<String>,
+        listened: u64,
+        duration: Option<u64>,
+    ) -> Option<String> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                self.listening = None;
+                return None;
+            }
+        };
+        let counted = match self.listening {
+            Some((ref listening, counted)) if *listening == path => counted,
+            _ => false,
+        };
+        let played = duration.is_some_and(|duration| listened >= played_threshold(duration));
+        self.listening = Some((path.clone(), counted || played));
+        if counted || !played {
+            return None;
+        }
+
+        let now = unix_time();
+        self.change(&path, |stats| {
+            stats.plays += 1;
+            stats.last_played = Some(now);
+        });
+        Some(path)
+    }
+
+    // The track played is left for the next one before its play was counted. Returns it.
+    pub fn skip(&mut self) -> Option<String> {
+        let path = match self.listening.take() {
+            Some((path, false)) => path,
+            listening => {
+                self.listening = listening;
+                return None;
+            }
+        };
+        self.change(&path, |stats| stats.skips += 1);
+        Some(path)
+    }
+
+    pub fn set_rating(&mut self, path: &str, rating: u8) {
+        self.change(path, |stats| stats.rating = min(rating, MAX_RATING));
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some((ref mut listening, _)) = self.listening {
+            if listening == from {
+                *listening = to.to_string();
+            }
+        }
+        if let Some(stats) = self.entries.remove(from) {
+            self.entries.insert(to.to_string(), stats);
+            self.revision += 1;
+            self.save();
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+}
+
+pub fn played_threshold(duration: u64) -> u64 {
+    min(duration / 2, PLAYED_MILLIS)
+}
+
+// "2018-03-24 21:05", in local time
+pub fn format_date(seconds: u64) -> String {
+    let time = seconds as libc::time_t;
+    let mut local: libc::tm = unsafe { mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut local) }.is_null() {
+        return String::new();
+    }
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        local.tm_year + 1900,
+        local.tm_mon + 1,
+        local.tm_mday,
+        local.tm_hour,
+        local.tm_min
+    )
+}
+
+pub fn stars(rating: u8) -> String {
+    if rating == 0 {
+        return String::new();
+    }
+    let rating = min(rating, MAX_RATING) as usize;
+    "★".repeat(rating) + &"☆".repeat(MAX_RATING as usize - rating)
+}
+
+// POPM: email, rating from 1 to 255 (0 unknown) and play counter. PCNT: play counter.
+// Both counters are 32 bits, big endian.
+fn write_stats_tag(path: &str, stats: &TrackStats) -> id3::Result<()> {
+    let mut tag = read_tag(path)?;
+    let rating: u8 = match stats.rating {
+        0 => 0,
+        1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    };
+    let mut popularimeter = POPM_EMAIL.as_bytes().to_vec();
+    popularimeter.push(0);
+    popularimeter.push(rating);
+    popularimeter.extend_from_slice(&stats.plays.to_be_bytes());
+
+    // Frames of other players are kept
+    let others: Vec<Frame> = tag
+        .frames()
+        .filter(|frame| frame.id() == "POPM")
+        .filter(|frame| {
+            !frame
+                .content()
+                .unknown()
+                .is_some_and(|data| data.starts_with(&popularimeter[..POPM_EMAIL.len() + 1]))
+        })
+        .cloned()
+        .collect();
+    tag.remove("POPM");
+    tag.remove("PCNT");
+    for frame in others {
+        tag.add_frame(frame);
+    }
+    tag.add_frame(Frame::with_content("POPM", Content::Unknown(popularimeter)));
+    tag.add_frame(Frame::with_content(
+        "PCNT",
+        Content::Unknown(stats.plays.to_be_bytes().to_vec()),
+    ));
+    tag.write_to_path(path, tag_version(path))
+}
+
+impl App {
+    // Count the plays, and rate the selected rows with Ctrl+0 to Ctrl+5
+    pub fn connect_stats_events(&self) {
+        // Files whose tags are to be written, once they are no longer played
+        let pending_tags = Rc::new(RefCell::new(Vec::new()));
+
+        let stats = self.stats.clone();
+        let playlist = self.playlist.clone();
+        let state = self.state.clone();
+        let config = self.config.clone();
+        let timeout_pending_tags = pending_tags.clone();
+        gtk::timeout_add(100, move || {
+            let path = playlist.path();
+            let (listened, duration, stopped) = {
+                let state = state.lock().unwrap();
+                let duration = path
+                    .as_ref()
+                    .and_then(|path| state.durations.get(path).cloned());
+                let listened = match state.listened {
+                    Some((ref listened_path, millis)) if Some(listened_path) == path.as_ref() => {
+                        millis
+                    }
+                    _ => 0,
+                };
+                (listened, duration, state.stopped)
+            };
+            // A paused track is still the one listened to, its play counted or not
+            let paused = stopped && playlist.is_paused();
+            let playing = if stopped && !paused { None } else { path };
+            let counted = if paused {
+                None
+            } else {
+                stats
+                    .borrow_mut()
+                    .update(playing.clone(), listened, duration)
+            };
+            if let Some(path) = counted {
+                if config.lock().unwrap().stats_in_tags {
+                    timeout_pending_tags.borrow_mut().push(path.clone());
+                }
+                playlist.refresh_stats(&path);
+            }
+
+            timeout_pending_tags.borrow_mut().retain(|path| {
+                if Some(path) == playing.as_ref() {
+                    return true;
+                }
+                stats.borrow().write_tag(path);
+                false
+            });
+            Continue(true)
+        });
+
+        let stats = self.stats.clone();
+        let playlist = self.playlist.clone();
+        let config = self.config.clone();
+        self.playlist.view().connect_key_press_event(move |_, key| {
+            let rating = key.get_keyval().wrapping_sub(gdk::enums::key::_0);
+            if !key.get_state().contains(ModifierType::CONTROL_MASK) || rating > MAX_RATING as u32 {
+                return Inhibit(false);
+            }
+            let stats_in_tags = config.lock().unwrap().stats_in_tags;
+            for path in playlist.selected_paths() {
+                stats.borrow_mut().set_rating(&path, rating as u8);
+                if stats_in_tags && !pending_tags.borrow().contains(&path) {
+                    pending_tags.borrow_mut().push(path.clone());
+                }
+                playlist.refresh_stats(&path);
+            }
+            Inhibit(true)
+        });
+    }
+}
@@ -0,0 +1,201 @@
+use std::f32::consts::PI;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+// Semitones
+pub const MAX_PITCH: f32 = 12.0;
+
+// Seconds
+const SEGMENT_LENGTH: f64 = 0.02;
+const SEARCH_LENGTH: f64 = 0.008;
+// Samples skipped when comparing two segments, to bound the cost of the search
+const CORRELATION_STRIDE: usize = 4;
+
+// Pitch ratio of a shift in semitones
+pub fn pitch_ratio(semitones: f32) -> f32 {
+    2f32.powf(semitones / 12.0)
+}
+
+// Waveform similarity overlap-add: the input is cut in overlapping segments taken every
+// `segment * tempo` samples and laid out every `segment` samples. Each segment is moved
+// within the search range to the position where it best continues the previous one, so
+// that the pitch is kept and the joins do not cancel out.
+pub struct TimeStretch {
+    segment: usize,
+    search: usize,
+    // Fade in of the next segment over the tail of the previous one (raised cosine)
+    fade: Vec<f32>,
+    input: Vec<[f32; 2]>,
+    // Start of the previous segment in `input`
+    previous: usize,
+    // Where the next segment would start without the search
+    position: f64,
+}
+
+impl TimeStretch {
+    pub fn new(rate: u32) -> Self {
+        let segment = (rate as f64 * SEGMENT_LENGTH) as usize;
+        let search = (rate as f64 * SEARCH_LENGTH) as usize;
+        let fade = (0..segment)
+            .map(|index| 0.5 - 0.5 * (PI * index as f32 / segment as f32).cos())
+            .collect();
+
+        // Silence before the stream, the previous segment ending where the stream starts
+        TimeStretch {
+            segment,
+            search,
+            fade,
+            input: vec![[0.0; 2]; search + segment],
+            previous: search,
+            position: (search + segment) as f64,
+        }
+    }
+
+    // Normalized cross-correlation of the candidate with the natural continuation of the
+    // previous segment
+    fn similarity(&self, candidate: usize, target: usize) -> f32 {
+        let mut correlation = 0.0;
+        let mut energy = 0.0;
+        for index in (0..self.segment).step_by(CORRELATION_STRIDE) {
+            let a = self.input[candidate + index];
+            let b = self.input[target + index];
+            correlation += a[0] * b[0] + a[1] * b[1];
+            energy += a[0] * a[0] + a[1] * a[1];
+        }
+        if energy > 0.0 {
+            correlation / energy.sqrt()
+        } else {
+            0.0
+        }
+    }
+
+    // Append the samples that can be computed from the input received so far
+    pub fn process(&mut self, input: &[[f32; 2]], tempo: f32, output: &mut Vec<[f32; 2]>) {
+        self.input.extend_from_slice(input);
+
+        while self.position as usize + self.search + 2 * self.segment <= self.input.len() {
+            let target = self.previous + self.segment;
+            let position = self.position as usize;
+            let start = position.saturating_sub(self.search);
+            let best = (start..=position + self.search)
+                .map(|candidate| (candidate, self.similarity(candidate, target)))
+                .fold((target, f32::MIN), |best, candidate| {
+                    if candidate.1 > best.1 {
+                        candidate
+                    } else {
+                        best
+                    }
+                })
+                .0;
+
+            for (index, &fade) in self.fade.iter().enumerate() {
+                let tail = self.input[target + index];
+                let head = self.input[best + index];
+                output.push([
+                    tail[0] * (1.0 - fade) + head[0] * fade,
+                    tail[1] * (1.0 - fade) + head[1] * fade,
+                ]);
+            }
+
+            self.previous = best;
+            self.position += self.segment as f64 * tempo as f64;
+
+            // Only keep what the next segments need
+            let consumed = self
+                .previous
+                .min((self.position as usize).saturating_sub(self.search));
+            self.input.drain(..consumed);
+            self.previous -= consumed;
+            self.position -= consumed as f64;
+        }
+    }
+
+    // Push out the samples waiting for input after the end of the stream
+    pub fn flush(&mut self, tempo: f32, output: &mut Vec<[f32; 2]>) {
+        let silence = vec![[0.0; 2]; self.search + 2 * self.segment];
+        self.process(&silence, tempo, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pitch_ratio, TimeStretch};
+    use std::f32::consts::PI;
+
+    const RATE: u32 = 44100;
+
+    fn sine(frequency: f32, frames: usize) -> Vec<[f32; 2]> {
+        (0..frames)
+            .map(|index| {
+                let sample = 0.5 * (2.0 * PI * frequency * index as f32 / RATE as f32).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn stretch(input: &[[f32; 2]], tempo: f32) -> Vec<[f32; 2]> {
+        let mut stretch = TimeStretch::new(RATE);
+        let mut output = Vec::new();
+        for block in input.chunks(1000) {
+            stretch.process(block, tempo, &mut output);
+        }
+        stretch.flush(tempo, &mut output);
+        output
+    }
+
+    // Frequency from the upward zero crossings
+    fn frequency(samples: &[[f32; 2]]) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0][0] < 0.0 && pair[1][0] >= 0.0)
+            .count();
+        crossings as f32 * RATE as f32 / samples.len() as f32
+    }
+
+    fn rms(samples: &[[f32; 2]]) -> f32 {
+        let power = samples.iter().map(|frame| frame[0] * frame[0]).sum::<f32>();
+        (power / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn pitch_ratios() {
+        assert_eq!(pitch_ratio(0.0), 1.0);
+        assert!((pitch_ratio(12.0) - 2.0).abs() < 1e-6);
+        assert!((pitch_ratio(-12.0) - 0.5).abs() < 1e-6);
+        assert!((pitch_ratio(7.0) - 1.4983).abs() < 1e-3);
+    }
+
+    #[test]
+    fn duration_follows_tempo() {
+        let input = sine(440.0, RATE as usize * 2);
+        for &tempo in &[0.5, 1.0, 1.5, 3.0] {
+            let output = stretch(&input, tempo);
+            let expected = input.len() as f32 / tempo;
+            let error = (output.len() as f32 - expected).abs() / RATE as f32;
+            assert!(error < 0.1, "tempo {}: {} frames", tempo, output.len());
+        }
+    }
+
+    #[test]
+    fn pitch_and_level_kept() {
+        let input = sine(440.0, RATE as usize * 2);
+        for &tempo in &[0.5, 0.8, 1.5, 2.0] {
+            let output = stretch(&input, tempo);
+            let middle = &output[RATE as usize / 4..output.len() - RATE as usize / 4];
+            let frequency = frequency(middle);
+            assert!(
+                (frequency - 440.0).abs() < 5.0,
+                "tempo {}: {} Hz",
+                tempo,
+                frequency
+            );
+            let level = rms(middle);
+            assert!(
+                (level - rms(&input)).abs() < 0.05,
+                "tempo {}: {}",
+                tempo,
+                level
+            );
+        }
+    }
+}
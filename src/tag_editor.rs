@@ -0,0 +1,480 @@
+use gdk_pixbuf::{Pixbuf, PixbufLoader};
+
+use gtk::{
+    Align, ApplicationWindow, Button, ButtonExt, ButtonsType, CheckButton, ContainerExt, Dialog,
+    DialogExt, DialogFlags, EditableSignals, Entry, EntryExt, FileChooserAction, FileChooserDialog,
+    FileChooserExt, FileFilter, FileFilterExt, Grid, GridExt, Image, ImageExt, Label, LabelExt,
+    MessageDialog, MessageDialogExt, MessageType, Orientation, ToggleButtonExt, WidgetExt,
+};
+use id3::frame::{Comment, Picture, PictureType};
+use id3::{self, ErrorKind, Tag, Timestamp, Version};
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+use dialog::{RESPONSE_ACCEPT, RESPONSE_CANCEL};
+use metadata::{comment, cover_picture};
+
+const COVER_SIZE: i32 = 128;
+const COMMENT_LANGUAGE: &str = "eng";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Year,
+    Track,
+    TotalTracks,
+    Disc,
+    Comment,
+}
+
+pub const FIELDS: [Field; 10] = [
+    Field::Title,
+    Field::Artist,
+    Field::Album,
+    Field::AlbumArtist,
+    Field::Genre,
+    Field::Year,
+    Field::Track,
+    Field::TotalTracks,
+    Field::Disc,
+    Field::Comment,
+];
+
+impl Field {
+    pub fn title(&self) -> &'static str {
+        match *self {
+            Field::Title => "Title",
+            Field::Artist => "Artist",
+            Field::Album => "Album",
+            Field::AlbumArtist => "Album artist",
+            Field::Genre => "Genre",
+            Field::Year => "Year",
+            Field::Track => "Track",
+            Field::TotalTracks => "Total tracks",
+            Field::Disc => "Disc",
+            Field::Comment => "Comment",
+        }
+    }
+
+    // Name in the file name patterns
+    pub fn key(&self) -> &'static str {
+        match *self {
+            Field::Title => "title",
+            Field::Artist => "artist",
+            Field::Album => "album",
+            Field::AlbumArtist => "albumartist",
+            Field::Genre => "genre",
+            Field::Year => "year",
+            Field::Track => "track",
+            Field::TotalTracks => "totaltracks",
+            Field::Disc => "disc",
+            Field::Comment => "comment",
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(
+            *self,
+            Field::Year | Field::Track | Field::TotalTracks | Field::Disc
+        )
+    }
+
+    fn get(&self, tag: &Tag) -> Option<String> {
+        match *self {
+            Field::Title => tag.title().map(str::to_string),
+            Field::Artist => tag.artist().map(str::to_string),
+            Field::Album => tag.album().map(str::to_string),
+            Field::AlbumArtist => tag.album_artist().map(str::to_string),
+            Field::Genre => tag.genre().map(str::to_string),
+            // ID3v2.4 files store it in the recording time instead
+            Field::Year => tag
+                .year()
+                .or_else(|| tag.date_recorded().map(|date| date.year))
+                .map(|year| year.to_string()),
+            Field::Track => tag.track().map(|track| track.to_string()),
+            Field::TotalTracks => tag.total_tracks().map(|total| total.to_string()),
+            Field::Disc => tag.disc().map(|disc| disc.to_string()),
+            Field::Comment => comment(tag).map(|comment| comment.text.clone()),
+        }
+    }
+
+    // An empty value removes the field, numbers were checked beforehand
+    pub fn set(&self, tag: &mut Tag, value: &str, version: Version) {
+        let value = value.trim();
+        if value.is_empty() {
+            self.remove(tag);
+            return;
+        }
+
+        let number = value.parse::<u32>().unwrap_or(0);
+        match *self {
+            Field::Title => tag.set_title(value),
+            Field::Artist => tag.set_artist(value),
+            Field::Album => tag.set_album(value),
+            Field::AlbumArtist => tag.set_album_artist(value),
+            Field::Genre => tag.set_genre(value),
+            Field::Year => {
+                self.remove(tag);
+                if version == Version::Id3v24 {
+                    tag.set_date_recorded(Timestamp {
+                        year: number as i32,
+                        month: None,
+                        day: None,
+                        hour: None,
+                        minute: None,
+                        second: None,
+                    });
+                } else {
+                    tag.set_year(number as i32);
+                }
+            }
+            Field::Track => tag.set_track(number),
+            Field::TotalTracks => tag.set_total_tracks(number),
+            Field::Disc => tag.set_disc(number),
+            Field::Comment => {
+                tag.remove_comment(Some(""), None);
+                tag.add_comment(Comment {
+                    lang: COMMENT_LANGUAGE.to_string(),
+                    description: String::new(),
+                    text: value.to_string(),
+                });
+            }
+        }
+    }
+
+    fn remove(&self, tag: &mut Tag) {
+        match *self {
+            Field::Title => tag.remove_title(),
+            Field::Artist => tag.remove_artist(),
+            Field::Album => tag.remove_album(),
+            Field::AlbumArtist => tag.remove_album_artist(),
+            Field::Genre => tag.remove_genre(),
+            Field::Year => {
+                tag.remove("TYER");
+                tag.remove("TDRC");
+            }
+            Field::Track => tag.remove_track(),
+            Field::TotalTracks => tag.remove_total_tracks(),
+            Field::Disc => tag.remove_disc(),
+            Field::Comment => tag.remove_comment(Some(""), None),
+        }
+    }
+}
+
+enum CoverChange {
+    Keep,
+    Replace(Picture),
+    Remove,
+}
+
+// Files keep their ID3v2.3 tag, the others are written as ID3v2.4
+pub fn tag_version(path: &str) -> Version {
+    let mut header = [0; 4];
+    let read = File::open(path).and_then(|mut file| file.read_exact(&mut header));
+    if read.is_ok() && &header[..3] == b"ID3" && header[3] == 3 {
+        Version::Id3v23
+    } else {
+        Version::Id3v24
+    }
+}
+
+// The tag of the file, or an empty one when it has none. Tags that cannot be read are
+// errors, so that writing does not replace them.
+pub fn read_tag(path: &str) -> id3::Result<Tag> {
+    match Tag::read_from_path(path) {
+        Err(ref error) if matches!(error.kind, ErrorKind::NoTag) => Ok(Tag::new()),
+        tag => tag,
+    }
+}
+
+// Set the fields in the tag of the file, creating it if needed
+pub fn write_fields(path: &str, fields: &[(Field, String)]) -> id3::Result<()> {
+    let mut tag = read_tag(path)?;
+    let version = tag_version(path);
+    for (field, value) in fields {
+        field.set(&mut tag, value, version);
+    }
+    tag.write_to_path(path, version)
+}
+
+fn load_cover(data: &[u8]) -> Option<Pixbuf> {
+    let loader = PixbufLoader::new();
+    loader.set_size(COVER_SIZE, COVER_SIZE);
+    loader.loader_write(data).ok()?;
+    loader.close().ok()?;
+    loader.get_pixbuf()
+}
+
+fn read_picture(path: &Path) -> Option<Picture> {
+    let mut data = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut data).ok()?;
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let mime_type = match extension.as_deref() {
+        Some("png") => "image/png",
+        _ => "image/jpeg",
+    };
+    Some(Picture {
+        mime_type: mime_type.to_string(),
+        picture_type: PictureType::CoverFront,
+        description: String::new(),
+        data,
+    })
+}
+
+fn show_image_dialog(parent: &Dialog) -> Option<Picture> {
+    let dialog = FileChooserDialog::new(
+        Some("Select the cover image"),
+        Some(parent),
+        FileChooserAction::Open,
+    );
+    let filter = FileFilter::new();
+    filter.add_pixbuf_formats();
+    filter.set_name("Images");
+    dialog.add_filter(&filter);
+    dialog.add_button("Cancel", RESPONSE_CANCEL);
+    dialog.add_button("Accept", RESPONSE_ACCEPT);
+
+    let mut picture = None;
+    if dialog.run() == RESPONSE_ACCEPT {
+        picture = dialog.get_filename().and_then(|file| read_picture(&file));
+    }
+    dialog.destroy();
+    picture
+}
+
+pub fn show_error(parent: &Dialog, text: &str, secondary_text: &str) {
+    let dialog = MessageDialog::new(
+        Some(parent),
+        DialogFlags::MODAL,
+        MessageType::Error,
+        ButtonsType::Close,
+        text,
+    );
+    dialog.set_property_secondary_text(Some(secondary_text));
+    dialog.run();
+    dialog.destroy();
+}
+
+// A row per field. When several files are edited, a field whose values differ is left
+// empty and kept as it is in every file, unless it is edited or "Keep" is unchecked.
+// The files whose tag cannot be read are left out. Returns whether any file was written.
+pub fn show_tag_editor(parent: &ApplicationWindow, paths: &[String]) -> bool {
+    let mut unreadable = 0;
+    let mut tags = Vec::new();
+    let mut readable = Vec::new();
+    for path in paths {
+        match read_tag(path) {
+            Ok(tag) => {
+                tags.push(tag);
+                readable.push(path.clone());
+            }
+            Err(_) => unreadable += 1,
+        }
+    }
+    let paths = &readable[..];
+
+    let title = if paths.len() == 1 {
+        "Edit tags".to_string()
+    } else {
+        format!("Edit tags of {} files", paths.len())
+    };
+    let dialog = Dialog::new_with_buttons(
+        Some(title.as_str()),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", RESPONSE_CANCEL), ("Save", RESPONSE_ACCEPT)],
+    );
+    if paths.is_empty() {
+        show_error(
+            &dialog,
+            "Could not read the tags",
+            &format!("{} file(s) have a tag that cannot be edited", unreadable),
+        );
+        dialog.destroy();
+        return false;
+    }
+
+    let grid = Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+    grid.set_border_width(10);
+    dialog.get_content_area().add(&grid);
+
+    let mut rows = Vec::new();
+    for (row, &field) in FIELDS.iter().enumerate() {
+        let row = row as i32;
+        let values: Vec<Option<String>> = tags.iter().map(|tag| field.get(tag)).collect();
+        let mixed = values.iter().any(|value| *value != values[0]);
+
+        let label = Label::new(field.title());
+        label.set_halign(Align::Start);
+        grid.attach(&label, 0, row, 1, 1);
+
+        let entry = Entry::new();
+        entry.set_hexpand(true);
+        if mixed {
+            entry.set_placeholder_text("Multiple values");
+        } else {
+            entry.set_text(values[0].as_deref().unwrap_or_default());
+        }
+        grid.attach(&entry, 1, row, 1, 1);
+
+        let keep = CheckButton::new_with_label("Keep");
+        keep.set_active(true);
+        if paths.len() > 1 {
+            grid.attach(&keep, 2, row, 1, 1);
+        }
+        let keep_entry = keep.clone();
+        entry.connect_changed(move |_| keep_entry.set_active(false));
+
+        rows.push((field, entry, keep));
+    }
+
+    let pictures: Vec<Option<&Picture>> = tags.iter().map(cover_picture).collect();
+    let same_cover = pictures.iter().all(|picture| {
+        picture.map(|picture| &picture.data) == pictures[0].map(|picture| &picture.data)
+    });
+    let cover_row = FIELDS.len() as i32;
+    let cover_label = Label::new("Cover");
+    cover_label.set_halign(Align::Start);
+    grid.attach(&cover_label, 0, cover_row, 1, 1);
+
+    let cover_box = gtk::Box::new(Orientation::Horizontal, 6);
+    let cover = Image::new();
+    cover.set_size_request(COVER_SIZE, COVER_SIZE);
+    let current_cover = if same_cover {
+        pictures[0].and_then(|picture| load_cover(&picture.data))
+    } else {
+        None
+    };
+    cover.set_from_pixbuf(current_cover.as_ref());
+    cover_box.add(&cover);
+    let cover_status = Label::new(if same_cover {
+        None
+    } else {
+        Some("Multiple covers")
+    });
+    cover_box.add(&cover_status);
+    let choose_button = Button::new_with_label("Choose image…");
+    cover_box.add(&choose_button);
+    let remove_button = Button::new_with_label("Remove");
+    cover_box.add(&remove_button);
+    let keep_button = Button::new_with_label("Keep");
+    cover_box.add(&keep_button);
+    grid.attach(&cover_box, 1, cover_row, 2, 1);
+
+    let cover_change = Rc::new(RefCell::new(CoverChange::Keep));
+
+    let change = cover_change.clone();
+    let image = cover.clone();
+    let status = cover_status.clone();
+    let image_parent = dialog.clone();
+    choose_button.connect_clicked(move |_| {
+        if let Some(picture) = show_image_dialog(&image_parent) {
+            match load_cover(&picture.data) {
+                Some(pixbuf) => {
+                    image.set_from_pixbuf(Some(&pixbuf));
+                    status.set_text("");
+                    *change.borrow_mut() = CoverChange::Replace(picture);
+                }
+                None => show_error(&image_parent, "Could not load the image", ""),
+            }
+        }
+    });
+
+    let change = cover_change.clone();
+    let image = cover.clone();
+    let status = cover_status.clone();
+    remove_button.connect_clicked(move |_| {
+        image.set_from_pixbuf(None);
+        status.set_text("No cover");
+        *change.borrow_mut() = CoverChange::Remove;
+    });
+
+    let change = cover_change.clone();
+    let image = cover.clone();
+    let status = cover_status.clone();
+    keep_button.connect_clicked(move |_| {
+        image.set_from_pixbuf(current_cover.as_ref());
+        status.set_text(if same_cover { "" } else { "Multiple covers" });
+        *change.borrow_mut() = CoverChange::Keep;
+    });
+
+    dialog.show_all();
+
+    // Invalid numbers keep the dialog open
+    let changes = loop {
+        if dialog.run() != RESPONSE_ACCEPT {
+            break None;
+        }
+        let changes: Vec<(Field, String)> = rows
+            .iter()
+            .filter(|(_, _, keep)| !keep.get_active())
+            .map(|(field, entry, _)| (*field, entry.get_text().unwrap_or_default()))
+            .collect();
+        let invalid = changes.iter().find(|(field, value)| {
+            field.is_number() && !value.trim().is_empty() && value.trim().parse::<u32>().is_err()
+        });
+        match invalid {
+            Some(&(field, _)) => show_error(
+                &dialog,
+                &format!("Invalid {}", field.title().to_lowercase()),
+                "Enter a positive number, or leave it empty to remove it",
+            ),
+            None => break Some(changes),
+        }
+    };
+
+    let changes = match changes {
+        Some(changes) => changes,
+        None => {
+            dialog.destroy();
+            return false;
+        }
+    };
+    let cover_change = cover_change.replace(CoverChange::Keep);
+    if changes.is_empty() {
+        if let CoverChange::Keep = cover_change {
+            dialog.destroy();
+            return false;
+        }
+    }
+
+    let mut failures = 0;
+    for (path, mut tag) in paths.iter().zip(tags) {
+        let version = tag_version(path);
+        for &(field, ref value) in &changes {
+            field.set(&mut tag, value, version);
+        }
+        match cover_change {
+            CoverChange::Keep => (),
+            CoverChange::Replace(ref picture) => {
+                tag.remove_picture_by_type(PictureType::CoverFront);
+                tag.add_picture(picture.clone());
+            }
+            CoverChange::Remove => tag.remove("APIC"),
+        }
+        if tag.write_to_path(path, version).is_err() {
+            failures += 1;
+        }
+    }
+    if failures + unreadable > 0 {
+        show_error(
+            &dialog,
+            "Could not write the tags",
+            &format!("{} file(s) could not be written", failures + unreadable),
+        );
+    }
+    dialog.destroy();
+    true
+}
@@ -1,28 +1,48 @@
 use gtk::{
-    ApplicationWindow, ContainerExt, DialogExt, FileChooserAction, FileChooserDialog,
-    FileChooserExt, FileFilter, FileFilterExt, Image, ImageExt, LabelExt, SeparatorToolItem,
-    ToolButton, ToolButtonExt, Toolbar, WidgetExt,
+    ApplicationWindow, ButtonsType, ContainerExt, Continue, Dialog, DialogExt, DialogFlags,
+    FileChooserAction, FileChooserDialog, FileChooserExt, FileFilter, FileFilterExt, Image,
+    ImageExt, Label, LabelExt, MessageDialog, MessageDialogExt, MessageType, PolicyType,
+    ProgressBar, ProgressBarExt, ScrolledWindow, ScrolledWindowExt, SeparatorToolItem, ToolButton,
+    ToolButtonExt, Toolbar, WidgetExt,
 };
 
-use gtk_sys::{GTK_RESPONSE_ACCEPT, GTK_RESPONSE_CANCEL};
-use std::path::PathBuf;
+use std::mem;
+use std::path::{Path, PathBuf};
 
+use dialog::{RESPONSE_ACCEPT, RESPONSE_CANCEL};
+use equalizer_window::show_equalizer_window;
+use guess_dialog::show_guess_dialog;
+use library_window::show_library_window;
+use mini_player::show_mini_player;
+use now_playing::set_view;
 use playlist::Playlist;
+use preferences::show_preferences_dialog;
+use scan::{self, AlbumLoudness, TrackLoudness};
+use smart_playlist_window::show_smart_playlists_window;
+use tag_editor::show_tag_editor;
 use App;
 
 pub const PAUSE_ICON: &str = "gtk-media-pause";
 pub const PLAY_ICON: &str = "gtk-media-play";
-const RESPONSE_ACCEPT: i32 = GTK_RESPONSE_ACCEPT as i32;
-const RESPONSE_CANCEL: i32 = GTK_RESPONSE_CANCEL as i32;
 
 pub struct MusicToolbar {
+    edit_tags_button: ToolButton,
+    guess_tags_button: ToolButton,
+    equalizer_button: ToolButton,
     open_button: ToolButton,
-    next_button: ToolButton,
-    play_button: ToolButton,
+    pub next_button: ToolButton,
+    now_playing_button: ToolButton,
+    mini_player_button: ToolButton,
+    lyrics_button: ToolButton,
+    library_button: ToolButton,
+    pub play_button: ToolButton,
     pub play_image: Image,
-    previous_button: ToolButton,
+    preferences_button: ToolButton,
+    pub previous_button: ToolButton,
     quit_button: ToolButton,
     remove_button: ToolButton,
+    scan_button: ToolButton,
+    smart_playlists_button: ToolButton,
     stop_button: ToolButton,
     toolbar: Toolbar,
 }
@@ -50,22 +70,73 @@ impl MusicToolbar {
 
         toolbar.add(&SeparatorToolItem::new());
 
+        let (library_button, _) = new_tool_button("library");
+        library_button.set_tooltip_text("Library");
+        toolbar.add(&library_button);
+
+        let (smart_playlists_button, _) = new_tool_button("smart-playlists");
+        smart_playlists_button.set_tooltip_text("Smart playlists");
+        toolbar.add(&smart_playlists_button);
+
         let (remove_button, _) = new_tool_button("remove");
         toolbar.add(&remove_button);
 
+        let (edit_tags_button, _) = new_tool_button("edit-tags");
+        edit_tags_button.set_tooltip_text("Edit tags");
+        toolbar.add(&edit_tags_button);
+
+        let (guess_tags_button, _) = new_tool_button("guess-tags");
+        guess_tags_button.set_tooltip_text("Guess tags from file names");
+        toolbar.add(&guess_tags_button);
+
+        let (scan_button, _) = new_tool_button("scan-loudness");
+        scan_button.set_tooltip_text("Scan loudness");
+        toolbar.add(&scan_button);
+
+        toolbar.add(&SeparatorToolItem::new());
+
+        let (now_playing_button, _) = new_tool_button("now-playing");
+        now_playing_button.set_tooltip_text("Now playing");
+        toolbar.add(&now_playing_button);
+
+        let (mini_player_button, _) = new_tool_button("mini-player");
+        mini_player_button.set_tooltip_text("Mini player");
+        toolbar.add(&mini_player_button);
+
+        let (lyrics_button, _) = new_tool_button("lyrics");
+        lyrics_button.set_tooltip_text("Lyrics");
+        toolbar.add(&lyrics_button);
+
+        let (equalizer_button, _) = new_tool_button("equalizer");
+        equalizer_button.set_tooltip_text("Equalizer");
+        toolbar.add(&equalizer_button);
+
+        let (preferences_button, _) = new_tool_button("preferences");
+        toolbar.add(&preferences_button);
+
         toolbar.add(&SeparatorToolItem::new());
 
         let (quit_button, _) = new_tool_button("gtk-quit");
         toolbar.add(&quit_button);
 
         let toolbar = MusicToolbar {
+            edit_tags_button,
+            guess_tags_button,
+            equalizer_button,
             open_button,
             next_button,
+            now_playing_button,
+            mini_player_button,
+            lyrics_button,
+            library_button,
             play_button,
             play_image,
+            preferences_button,
             previous_button,
             quit_button,
             remove_button,
+            scan_button,
+            smart_playlists_button,
             stop_button,
             toolbar,
         };
@@ -85,6 +156,32 @@ impl App {
             window.destroy();
         });
 
+        // Switches between the playlist and the now playing view
+        let config = self.config.clone();
+        let views = self.views.clone();
+        self.toolbar.now_playing_button.connect_clicked(move |_| {
+            let mut config = config.lock().unwrap();
+            config.now_playing = !config.now_playing;
+            config.save();
+            set_view(&views, config.now_playing);
+        });
+
+        let config = self.config.clone();
+        let lyrics_pane = self.lyrics_pane.clone();
+        self.toolbar.lyrics_button.connect_clicked(move |_| {
+            let mut config = config.lock().unwrap();
+            config.show_lyrics = !config.show_lyrics;
+            config.save();
+            lyrics_pane.container().set_visible(config.show_lyrics);
+        });
+
+        let window = self.window.clone();
+        let mini_player = self.mini_player.clone();
+        let config = self.config.clone();
+        self.toolbar.mini_player_button.connect_clicked(move |_| {
+            show_mini_player(&window, &mini_player, &config);
+        });
+
         let playlist = self.playlist.clone();
         let play_image = self.toolbar.play_image.clone();
         let cover = self.cover.clone();
@@ -115,6 +212,101 @@ impl App {
             playlist.remove_selection();
         });
 
+        let parent = self.window.clone();
+        let playlist = self.playlist.clone();
+        let cover = self.cover.clone();
+        self.toolbar.edit_tags_button.connect_clicked(move |_| {
+            let paths = playlist.selected_paths();
+            if !paths.is_empty() && show_tag_editor(&parent, &paths) {
+                playlist.refresh(&paths);
+                if playlist.path().is_some() {
+                    set_cover(&cover, &playlist);
+                }
+            }
+        });
+
+        let parent = self.window.clone();
+        let config = self.config.clone();
+        let playlist = self.playlist.clone();
+        self.toolbar.guess_tags_button.connect_clicked(move |_| {
+            let paths = playlist.selected_paths();
+            if !paths.is_empty() && show_guess_dialog(&parent, &config, &paths) {
+                playlist.refresh(&paths);
+            }
+        });
+
+        let parent = self.window.clone();
+        let playlist = self.playlist.clone();
+        let progress_bar = self.scan_progress.clone();
+        self.toolbar.scan_button.connect_clicked(move |button| {
+            let tracks = playlist.selected_tracks();
+            if tracks.is_empty() {
+                return;
+            }
+
+            let progress = scan::scan(tracks);
+            button.set_sensitive(false);
+            progress_bar.set_fraction(0.0);
+            progress_bar.show();
+
+            let parent = parent.clone();
+            let button = button.clone();
+            let progress_bar = progress_bar.clone();
+            gtk::timeout_add(100, move || {
+                let (tracks, albums) = {
+                    let mut progress = progress.lock().unwrap();
+                    progress_bar.set_fraction(progress.done as f64 / progress.total as f64);
+                    progress_bar.set_text(
+                        format!("Scanning loudness {}/{}", progress.done, progress.total).as_str(),
+                    );
+                    if !progress.is_finished() {
+                        return Continue(true);
+                    }
+                    (mem::take(&mut progress.tracks), progress.albums())
+                };
+
+                progress_bar.hide();
+                if show_scan_results(&parent, &tracks, &albums) {
+                    write_scan_tags(&parent, &button, &progress_bar, tracks, albums);
+                } else {
+                    button.set_sensitive(true);
+                }
+                Continue(false)
+            });
+        });
+
+        let parent = self.window.clone();
+        let config = self.config.clone();
+        let playlist = self.playlist.clone();
+        self.toolbar.equalizer_button.connect_clicked(move |_| {
+            show_equalizer_window(&parent, &config, &playlist);
+        });
+
+        let parent = self.window.clone();
+        let config = self.config.clone();
+        let library = self.library.clone();
+        let playlist = self.playlist.clone();
+        self.toolbar.library_button.connect_clicked(move |_| {
+            show_library_window(&parent, &config, &library, &playlist);
+        });
+
+        let parent = self.window.clone();
+        let smart_playlists = self.smart_playlists.clone();
+        let library = self.library.clone();
+        let stats = self.stats.clone();
+        let playlist = self.playlist.clone();
+        self.toolbar
+            .smart_playlists_button
+            .connect_clicked(move |_| {
+                show_smart_playlists_window(&parent, &smart_playlists, &library, &stats, &playlist);
+            });
+
+        let parent = self.window.clone();
+        let config = self.config.clone();
+        self.toolbar.preferences_button.connect_clicked(move |_| {
+            show_preferences_dialog(&parent, &config);
+        });
+
         let current_time_label = self.current_time_label.clone();
         let duration_label = self.duration_label.clone();
         let playlist = self.playlist.clone();
@@ -128,10 +320,16 @@ impl App {
             set_image_icon(&play_image, PLAY_ICON);
         });
 
+        // Leaving the track before its play is counted is a skip
         let playlist = self.playlist.clone();
         let play_image = self.toolbar.play_image.clone();
         let cover = self.cover.clone();
+        let stats = self.stats.clone();
         self.toolbar.next_button.connect_clicked(move |_| {
+            let skipped = stats.borrow_mut().skip();
+            if let Some(path) = skipped {
+                playlist.refresh_stats(&path);
+            }
             if playlist.next() {
                 set_image_icon(&play_image, PAUSE_ICON);
                 set_cover(&cover, &playlist);
@@ -175,7 +373,111 @@ fn show_open_dialog(parent: &ApplicationWindow) -> Option<PathBuf> {
     file
 }
 
-fn set_cover(cover: &Image, playlist: &Playlist) {
+// Returns whether the tags are to be written
+fn show_scan_results(
+    parent: &ApplicationWindow,
+    tracks: &[TrackLoudness],
+    albums: &[AlbumLoudness],
+) -> bool {
+    let mut lines = Vec::new();
+    for track in tracks {
+        let name = Path::new(&track.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        lines.push(match (track.loudness, track.gain()) {
+            (Some(loudness), Some(gain)) => format!(
+                "{}: {:.1} LUFS, gain {:+.2} dB, peak {:.3}",
+                name, loudness, gain, track.peak
+            ),
+            _ => format!("{}: silent", name),
+        });
+    }
+    for album in albums {
+        if let (Some(loudness), Some(gain)) = (album.loudness, album.gain()) {
+            let name = if album.artist.is_empty() {
+                album.album.clone()
+            } else {
+                format!("{} - {}", album.artist, album.album)
+            };
+            lines.push(format!(
+                "Album {}: {:.1} LUFS, gain {:+.2} dB, peak {:.3}",
+                name, loudness, gain, album.peak
+            ));
+        }
+    }
+
+    let dialog = Dialog::new_with_buttons(
+        Some("Loudness scan"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Close", RESPONSE_CANCEL), ("Write tags", RESPONSE_ACCEPT)],
+    );
+    let label = Label::new(lines.join("\n").as_str());
+    label.set_selectable(true);
+    let scrolled_window = ScrolledWindow::new(None, None);
+    scrolled_window.set_policy(PolicyType::Never, PolicyType::Automatic);
+    scrolled_window.set_min_content_height(200);
+    scrolled_window.add(&label);
+    dialog.get_content_area().add(&scrolled_window);
+    dialog.show_all();
+
+    let write = dialog.run() == RESPONSE_ACCEPT;
+    dialog.destroy();
+    write
+}
+
+// In the background, the progress bar showing how far it went
+fn write_scan_tags(
+    parent: &ApplicationWindow,
+    button: &ToolButton,
+    progress_bar: &ProgressBar,
+    tracks: Vec<TrackLoudness>,
+    albums: Vec<AlbumLoudness>,
+) {
+    let progress = scan::write_all_tags(tracks, albums);
+    progress_bar.set_fraction(0.0);
+    progress_bar.show();
+
+    let parent = parent.clone();
+    let button = button.clone();
+    let progress_bar = progress_bar.clone();
+    gtk::timeout_add(100, move || {
+        let progress = progress.lock().unwrap();
+        progress_bar.set_fraction(progress.done as f64 / progress.total.max(1) as f64);
+        progress_bar.set_text(
+            format!(
+                "Writing ReplayGain tags {}/{}",
+                progress.done, progress.total
+            )
+            .as_str(),
+        );
+        if !progress.is_finished() {
+            return Continue(true);
+        }
+
+        progress_bar.hide();
+        button.set_sensitive(true);
+        if progress.failures > 0 {
+            let dialog = MessageDialog::new(
+                Some(&parent),
+                DialogFlags::MODAL,
+                MessageType::Error,
+                ButtonsType::Close,
+                "Could not write the ReplayGain tags",
+            );
+            dialog.set_property_secondary_text(Some(&format!(
+                "{} file(s) could not be written",
+                progress.failures
+            )));
+            dialog.run();
+            dialog.destroy();
+        }
+        Continue(false)
+    });
+}
+
+pub fn set_cover(cover: &Image, playlist: &Playlist) {
     cover.set_from_pixbuf(playlist.pixbuf().as_ref());
     cover.show();
 }
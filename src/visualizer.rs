@@ -0,0 +1,397 @@
+use cairo::Context;
+
+use gtk::{ComboBoxExt, ComboBoxText, ComboBoxTextExt, Continue, DrawingArea, Inhibit, WidgetExt};
+
+use std::cell::{Cell, RefCell};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::Instant;
+
+use output::MAX_RATE;
+use ring::SampleRing;
+use App;
+
+// Seconds of audio PulseAudio buffers at most by default
+const MAX_LATENCY: usize = 2;
+// Samples kept for the visualizer: what the output has not played yet at the highest rate,
+// and what any view reads
+pub const RING_CAPACITY: usize = MAX_LATENCY * MAX_RATE as usize + FFT_SIZE;
+
+const MAX_FPS: u32 = 30;
+const FFT_SIZE: usize = 2048;
+const SPECTRUM_BARS: usize = 32;
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+// dB shown, from full scale down
+const SPECTRUM_RANGE: f32 = 70.0;
+const METER_RANGE: f32 = 60.0;
+// Fraction of the height the bars and the peaks fall by each frame
+const FALL_SPEED: f32 = 0.04;
+const PEAK_FALL_SPEED: f32 = 0.01;
+const SCOPE_SIZE: usize = 1024;
+// Seconds over which the meter integrates
+const METER_WINDOW: f32 = 0.05;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VisualizerMode {
+    Off,
+    Spectrum,
+    Scope,
+    Meter,
+}
+
+impl VisualizerMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            VisualizerMode::Off => "off",
+            VisualizerMode::Spectrum => "spectrum",
+            VisualizerMode::Scope => "scope",
+            VisualizerMode::Meter => "meter",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<VisualizerMode> {
+        match name {
+            "off" => Some(VisualizerMode::Off),
+            "spectrum" => Some(VisualizerMode::Spectrum),
+            "scope" => Some(VisualizerMode::Scope),
+            "meter" => Some(VisualizerMode::Meter),
+            _ => None,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match *self {
+            VisualizerMode::Off => "No visualizer",
+            VisualizerMode::Spectrum => "Spectrum",
+            VisualizerMode::Scope => "Oscilloscope",
+            VisualizerMode::Meter => "VU meter",
+        }
+    }
+}
+
+// In place radix-2 transform, the length being a power of two
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let size = real.len();
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= size {
+        let angle = -2.0 * PI / length as f32;
+        for start in (0..size).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let even = start + k;
+                let odd = even + length / 2;
+                let odd_real = real[odd] * cos - imaginary[odd] * sin;
+                let odd_imaginary = real[odd] * sin + imaginary[odd] * cos;
+                real[odd] = real[even] - odd_real;
+                imaginary[odd] = imaginary[even] - odd_imaginary;
+                real[even] += odd_real;
+                imaginary[even] += odd_imaginary;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+fn to_db(value: f32) -> f32 {
+    20.0 * value.max(1e-9).log10()
+}
+
+// Height from 0 to 1 of a level in dB, over the range shown
+fn level_height(db: f32, range: f32) -> f32 {
+    ((db + range) / range).clamp(0.0, 1.0)
+}
+
+pub struct Visualizer {
+    area: DrawingArea,
+    mode_combo: ComboBoxText,
+    mode: Cell<VisualizerMode>,
+    samples: RefCell<Vec<[f32; 2]>>,
+    window: Vec<f32>,
+    // Heights of the spectrum bars, or of the two meters, falling slowly
+    bars: RefCell<Vec<f32>>,
+    peaks: RefCell<Vec<f32>>,
+    // Samples written by the last draw and when it saw them change
+    clock: Cell<(usize, Instant)>,
+    // Everything written was played by the last draw
+    drained: Cell<bool>,
+}
+
+impl Visualizer {
+    pub fn new(mode: VisualizerMode) -> Self {
+        let area = DrawingArea::new();
+        area.set_size_request(-1, 120);
+
+        let mode_combo = ComboBoxText::new();
+        for mode in &[
+            VisualizerMode::Off,
+            VisualizerMode::Spectrum,
+            VisualizerMode::Scope,
+            VisualizerMode::Meter,
+        ] {
+            mode_combo.append(mode.name(), mode.title());
+        }
+        mode_combo.set_active_id(mode.name());
+
+        // Hann
+        let window = (0..FFT_SIZE)
+            .map(|index| 0.5 - 0.5 * (2.0 * PI * index as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        Visualizer {
+            area,
+            mode_combo,
+            mode: Cell::new(mode),
+            samples: RefCell::new(vec![[0.0; 2]; FFT_SIZE]),
+            window,
+            bars: RefCell::new(Vec::new()),
+            peaks: RefCell::new(Vec::new()),
+            clock: Cell::new((0, Instant::now())),
+            drained: Cell::new(true),
+        }
+    }
+
+    pub fn area(&self) -> &DrawingArea {
+        &self.area
+    }
+
+    pub fn mode_combo(&self) -> &ComboBoxText {
+        &self.mode_combo
+    }
+
+    // Hidden when off, so that nothing is drawn
+    pub fn set_mode(&self, mode: VisualizerMode) {
+        self.mode.set(mode);
+        self.bars.borrow_mut().clear();
+        self.peaks.borrow_mut().clear();
+        self.area.set_visible(mode != VisualizerMode::Off);
+    }
+
+    fn draw(&self, context: &Context, ring: &SampleRing) {
+        let width = self.area.get_allocated_width() as f64;
+        let height = self.area.get_allocated_height() as f64;
+        context.set_source_rgb(0.1, 0.1, 0.12);
+        context.paint();
+
+        let delay = self.delay(ring);
+        self.drained.set(delay.is_none());
+        match self.mode.get() {
+            VisualizerMode::Off => (),
+            VisualizerMode::Spectrum => {
+                let heights = self.spectrum(ring, delay);
+                self.draw_bars(context, &heights, width, height);
+            }
+            VisualizerMode::Scope => self.draw_scope(context, ring, delay, width, height),
+            VisualizerMode::Meter => {
+                let heights = self.meter(ring, delay);
+                self.draw_bars(context, &heights, width, height);
+            }
+        }
+    }
+
+    // Samples written but not heard yet, what the output had queued less what it played
+    // since, or None once everything written has been played
+    fn delay(&self, ring: &SampleRing) -> Option<usize> {
+        let written = ring.written();
+        let (last_written, mut since) = self.clock.get();
+        if written != last_written {
+            since = Instant::now();
+            self.clock.set((written, since));
+        }
+        let elapsed = since.elapsed();
+        let played =
+            (elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1e9) * ring.rate() as f32;
+        let queued = ring.queued() as f32;
+        if played < queued {
+            Some((queued - played) as usize)
+        } else {
+            None
+        }
+    }
+
+    // The samples heard now, silence once the output has drained so that the bars fall
+    fn read(&self, samples: &mut [[f32; 2]], ring: &SampleRing, delay: Option<usize>) {
+        match delay {
+            Some(delay) => ring.read(samples, delay),
+            None => {
+                for sample in samples.iter_mut() {
+                    *sample = [0.0; 2];
+                }
+            }
+        }
+    }
+
+    // Nothing left to animate until new samples are written
+    fn settled(&self) -> bool {
+        self.drained.get()
+            && self
+                .bars
+                .borrow()
+                .iter()
+                .chain(self.peaks.borrow().iter())
+                .all(|&height| height <= 0.0)
+    }
+
+    // Bars on a logarithmic frequency scale, each the loudest bin it covers
+    fn spectrum(&self, ring: &SampleRing, delay: Option<usize>) -> Vec<f32> {
+        let mut samples = self.samples.borrow_mut();
+        samples.resize(FFT_SIZE, [0.0; 2]);
+        self.read(&mut samples, ring, delay);
+
+        let mut real: Vec<f32> = samples
+            .iter()
+            .zip(self.window.iter())
+            .map(|(sample, window)| (sample[0] + sample[1]) * 0.5 * window)
+            .collect();
+        let mut imaginary = vec![0.0; FFT_SIZE];
+        fft(&mut real, &mut imaginary);
+
+        // A full scale sine reaches 0 dB
+        let scale = 4.0 / FFT_SIZE as f32;
+        let rate = ring.rate().max(1) as f32;
+        let max_frequency = MAX_FREQUENCY.min(rate / 2.0);
+        let ratio = (max_frequency / MIN_FREQUENCY).powf(1.0 / SPECTRUM_BARS as f32);
+
+        (0..SPECTRUM_BARS)
+            .map(|bar| {
+                let low = MIN_FREQUENCY * ratio.powi(bar as i32);
+                let high = low * ratio;
+                let first = (low * FFT_SIZE as f32 / rate) as usize;
+                let last =
+                    ((high * FFT_SIZE as f32 / rate) as usize).clamp(first + 1, FFT_SIZE / 2);
+                let magnitude = (first..last)
+                    .map(|bin| (real[bin] * real[bin] + imaginary[bin] * imaginary[bin]).sqrt())
+                    .fold(0.0, f32::max);
+                level_height(to_db(magnitude * scale), SPECTRUM_RANGE)
+            })
+            .collect()
+    }
+
+    // RMS of the left and right channels
+    fn meter(&self, ring: &SampleRing, delay: Option<usize>) -> Vec<f32> {
+        let length = (ring.rate() as f32 * METER_WINDOW) as usize;
+        let mut samples = self.samples.borrow_mut();
+        samples.resize(length.clamp(1, RING_CAPACITY), [0.0; 2]);
+        self.read(&mut samples, ring, delay);
+
+        (0..2)
+            .map(|channel| {
+                let power = samples
+                    .iter()
+                    .map(|sample| sample[channel] * sample[channel])
+                    .sum::<f32>()
+                    / samples.len() as f32;
+                level_height(10.0 * power.max(1e-18).log10(), METER_RANGE)
+            })
+            .collect()
+    }
+
+    // The bars fall at a limited speed, with a peak marker held above them
+    fn draw_bars(&self, context: &Context, heights: &[f32], width: f64, height: f64) {
+        let mut bars = self.bars.borrow_mut();
+        let mut peaks = self.peaks.borrow_mut();
+        bars.resize(heights.len(), 0.0);
+        peaks.resize(heights.len(), 0.0);
+
+        let bar_width = width / heights.len() as f64;
+        for (index, &level) in heights.iter().enumerate() {
+            bars[index] = level.max(bars[index] - FALL_SPEED);
+            peaks[index] = bars[index].max(peaks[index] - PEAK_FALL_SPEED);
+
+            let x = index as f64 * bar_width + 1.0;
+            let bar_height = bars[index] as f64 * height;
+            context.set_source_rgb(0.3, 0.7, 0.4);
+            context.rectangle(x, height - bar_height, bar_width - 2.0, bar_height);
+            context.fill();
+
+            let peak_y = height - peaks[index] as f64 * height;
+            context.set_source_rgb(0.9, 0.5, 0.2);
+            context.rectangle(x, peak_y - 2.0, bar_width - 2.0, 2.0);
+            context.fill();
+        }
+    }
+
+    fn draw_scope(
+        &self,
+        context: &Context,
+        ring: &SampleRing,
+        delay: Option<usize>,
+        width: f64,
+        height: f64,
+    ) {
+        let mut samples = self.samples.borrow_mut();
+        samples.resize(SCOPE_SIZE, [0.0; 2]);
+        self.read(&mut samples, ring, delay);
+
+        context.set_line_width(1.0);
+        for (channel, &(red, green, blue)) in [(0.3, 0.7, 0.4), (0.4, 0.5, 0.9)].iter().enumerate()
+        {
+            context.set_source_rgb(red, green, blue);
+            for (index, sample) in samples.iter().enumerate() {
+                let x = index as f64 * width / SCOPE_SIZE as f64;
+                let y = height / 2.0 - sample[channel].clamp(-1.0, 1.0) as f64 * height / 2.0;
+                if index == 0 {
+                    context.move_to(x, y);
+                } else {
+                    context.line_to(x, y);
+                }
+            }
+            context.stroke();
+        }
+    }
+}
+
+impl App {
+    // Redrawn at most MAX_FPS times per second, while samples are played and until the bars
+    // have fallen once they stop
+    pub fn connect_visualizer_events(&self) {
+        let ring = self.playlist.tap();
+        let visualizer = self.visualizer.clone();
+        let config = self.config.clone();
+        self.visualizer.mode_combo.connect_changed(move |combo| {
+            if let Some(mode) = combo
+                .get_active_id()
+                .and_then(|id| VisualizerMode::from_name(&id))
+            {
+                visualizer.set_mode(mode);
+                let mut config = config.lock().unwrap();
+                config.visualizer = mode;
+                config.save();
+            }
+        });
+
+        let visualizer = self.visualizer.clone();
+        let draw_ring: Arc<SampleRing> = ring.clone();
+        self.visualizer.area.connect_draw(move |_, context| {
+            visualizer.draw(context, &draw_ring);
+            Inhibit(false)
+        });
+
+        let visualizer = self.visualizer.clone();
+        let mut last_written = 0;
+        gtk::timeout_add(1000 / MAX_FPS, move || {
+            let written = ring.written();
+            if visualizer.mode.get() != VisualizerMode::Off
+                && (written != last_written || !visualizer.settled())
+            {
+                visualizer.area.queue_draw();
+                last_written = written;
+            }
+            Continue(true)
+        });
+    }
+}
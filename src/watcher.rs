@@ -0,0 +1,305 @@
+use crossbeam::sync::SegQueue;
+use gtk::Continue;
+use libc::{self, c_int, c_void};
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use library::scan;
+use App;
+
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+const BUFFER_SIZE: usize = 64 * 1024;
+// Watch descriptor, mask, cookie and name length, followed by the name
+const EVENT_HEADER_SIZE: usize = 16;
+
+pub enum Change {
+    // Written, or moved into a watched directory
+    Modified(PathBuf),
+    // A file or a directory
+    Removed(PathBuf),
+    // A file or a directory moved from a watched directory to another, or renamed
+    Renamed(PathBuf, PathBuf),
+    // Created or moved into a watched directory
+    Directory(PathBuf),
+}
+
+impl Change {
+    pub fn paths(&self) -> Vec<&Path> {
+        match *self {
+            Change::Modified(ref path)
+            | Change::Removed(ref path)
+            | Change::Directory(ref path) => {
+                vec![path]
+            }
+            Change::Renamed(ref from, ref to) => vec![from, to],
+        }
+    }
+}
+
+// Watched directories by descriptor, and the other way round
+#[derive(Default)]
+struct Watches {
+    directories: HashMap<c_int, PathBuf>,
+    descriptors: HashMap<PathBuf, c_int>,
+}
+
+impl Watches {
+    fn insert(&mut self, descriptor: c_int, directory: PathBuf) {
+        // The same directory reached by another path
+        if let Some(previous) = self.directories.insert(descriptor, directory.clone()) {
+            self.descriptors.remove(&previous);
+        }
+        self.descriptors.insert(directory, descriptor);
+    }
+
+    fn remove(&mut self, descriptor: c_int) {
+        if let Some(directory) = self.directories.remove(&descriptor) {
+            self.descriptors.remove(&directory);
+        }
+    }
+
+    // Descriptors of the directory and of the ones in it
+    fn tree(&self, directory: &Path) -> Vec<c_int> {
+        self.directories
+            .iter()
+            .filter(|(_, path)| path.starts_with(directory))
+            .map(|(&descriptor, _)| descriptor)
+            .collect()
+    }
+
+    // inotify follows a renamed directory, only its path changes
+    fn rename(&mut self, from: &Path, to: &Path) -> Vec<c_int> {
+        let descriptors = self.tree(from);
+        for &descriptor in &descriptors {
+            let renamed = match self.directories[&descriptor].strip_prefix(from) {
+                Ok(rest) if rest.as_os_str().is_empty() => to.to_path_buf(),
+                Ok(rest) => to.join(rest),
+                Err(_) => continue,
+            };
+            self.remove(descriptor);
+            self.insert(descriptor, renamed);
+        }
+        descriptors
+    }
+}
+
+// Changes to the files in the directories of the playlist and the library, reported by
+// inotify to a thread waiting for them
+pub struct Watcher {
+    // Negative when inotify is not available, nothing is watched then
+    fd: c_int,
+    watches: Arc<Mutex<Watches>>,
+    changes: Arc<SegQueue<Change>>,
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        let watches = Arc::new(Mutex::new(Watches::default()));
+        let changes = Arc::new(SegQueue::new());
+
+        if fd >= 0 {
+            let watches = watches.clone();
+            let changes = changes.clone();
+            thread::spawn(move || read_events(fd, &watches, &changes));
+        }
+
+        Watcher {
+            fd,
+            watches,
+            changes,
+        }
+    }
+
+    // Until the end, or until the directory is removed or moved out of the watched ones
+    pub fn watch(&self, directory: &Path) {
+        if self.fd < 0 {
+            return;
+        }
+        // Locked first, so that the events of the new watch find its directory
+        let mut watches = self.watches.lock().unwrap();
+        if watches.descriptors.contains_key(directory) {
+            return;
+        }
+        let path = match CString::new(directory.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let descriptor = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), WATCH_MASK) };
+        if descriptor >= 0 {
+            watches.insert(descriptor, directory.to_path_buf());
+        }
+    }
+
+    pub fn changes(&self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        while let Some(change) = self.changes.try_pop() {
+            changes.push(change);
+        }
+        changes
+    }
+}
+
+// The watches are dropped by inotify once removed, with an IN_IGNORED event
+fn unwatch(fd: c_int, descriptors: Vec<c_int>) {
+    for descriptor in descriptors {
+        unsafe { libc::inotify_rm_watch(fd, descriptor) };
+    }
+}
+
+fn read_events(fd: c_int, watches: &Mutex<Watches>, changes: &SegQueue<Change>) {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len()) };
+        if read < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        if read <= 0 {
+            return;
+        }
+        let events = &buffer[..read as usize];
+
+        let mut watches = watches.lock().unwrap();
+        // A file moved out, until the event moving it in, with the same cookie, is found
+        let mut moved_from: Option<(u32, PathBuf)> = None;
+        // Directories renamed in this batch, whose IN_MOVE_SELF is expected
+        let mut renamed = HashSet::new();
+        let mut offset = 0;
+        while offset + EVENT_HEADER_SIZE <= events.len() {
+            let field = |at: usize| {
+                let start = offset + at;
+                [
+                    events[start],
+                    events[start + 1],
+                    events[start + 2],
+                    events[start + 3],
+                ]
+            };
+            let descriptor = c_int::from_ne_bytes(field(0));
+            let mask = u32::from_ne_bytes(field(4));
+            let cookie = u32::from_ne_bytes(field(8));
+            let length = u32::from_ne_bytes(field(12)) as usize;
+            let name = &events[offset + EVENT_HEADER_SIZE..offset + EVENT_HEADER_SIZE + length];
+            offset += EVENT_HEADER_SIZE + length;
+
+            // The name is padded with null bytes
+            let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(length)];
+
+            // The watched directory itself: removed, or moved somewhere its path is unknown
+            if mask & libc::IN_IGNORED != 0 {
+                watches.remove(descriptor);
+                continue;
+            }
+            if mask & libc::IN_MOVE_SELF != 0 && !renamed.contains(&descriptor) {
+                let moved = watches.directories.get(&descriptor).cloned();
+                if let Some(directory) = moved {
+                    unwatch(fd, watches.tree(&directory));
+                }
+                continue;
+            }
+            let directory = match watches.directories.get(&descriptor) {
+                Some(directory) if !name.is_empty() => directory,
+                _ => continue,
+            };
+            let path = directory.join(OsStr::from_bytes(name));
+            let is_directory = mask & libc::IN_ISDIR != 0;
+
+            if mask & libc::IN_MOVED_FROM != 0 {
+                if let Some((_, from)) = moved_from.replace((cookie, path)) {
+                    unwatch(fd, watches.tree(&from));
+                    changes.push(Change::Removed(from));
+                }
+            } else if mask & libc::IN_MOVED_TO != 0 {
+                match moved_from.take() {
+                    Some((from_cookie, from)) if from_cookie == cookie => {
+                        if is_directory {
+                            renamed.extend(watches.rename(&from, &path));
+                        }
+                        changes.push(Change::Renamed(from, path));
+                    }
+                    other => {
+                        if let Some((_, from)) = other {
+                            unwatch(fd, watches.tree(&from));
+                            changes.push(Change::Removed(from));
+                        }
+                        changes.push(if is_directory {
+                            Change::Directory(path)
+                        } else {
+                            Change::Modified(path)
+                        });
+                    }
+                }
+            } else if is_directory {
+                if mask & libc::IN_CREATE != 0 {
+                    changes.push(Change::Directory(path));
+                } else if mask & libc::IN_DELETE != 0 {
+                    changes.push(Change::Removed(path));
+                }
+            } else if mask & libc::IN_CLOSE_WRITE != 0 {
+                changes.push(Change::Modified(path));
+            } else if mask & libc::IN_DELETE != 0 {
+                changes.push(Change::Removed(path));
+            }
+        }
+        // Moved out of the watched directories
+        if let Some((_, from)) = moved_from {
+            unwatch(fd, watches.tree(&from));
+            changes.push(Change::Removed(from));
+        }
+    }
+}
+
+impl App {
+    // Rows follow their files, and the library is scanned again when its folders change
+    pub fn connect_watcher_events(&self) {
+        let watcher = self.watcher.clone();
+        let playlist = self.playlist.clone();
+        let library = self.library.clone();
+        let config = self.config.clone();
+        let mut library_revision = None;
+        let mut library_changed = false;
+        gtk::timeout_add(500, move || {
+            let folders = config.lock().unwrap().library_folders.clone();
+            for change in watcher.changes() {
+                match change {
+                    Change::Directory(ref directory) => watcher.watch(directory),
+                    Change::Renamed(_, ref to) if to.is_dir() => watcher.watch(to),
+                    _ => (),
+                }
+                playlist.apply_change(&change);
+                library_changed |= change
+                    .paths()
+                    .iter()
+                    .any(|path| folders.iter().any(|folder| path.starts_with(folder)));
+            }
+
+            let (revision, scanning) = {
+                let library = library.lock().unwrap();
+                (library.revision(), library.is_scanning())
+            };
+            if library_changed && !scanning {
+                scan(&library, folders);
+                library_changed = false;
+            }
+            if library_revision != Some(revision) {
+                for directory in library.lock().unwrap().directories() {
+                    watcher.watch(directory);
+                }
+                library_revision = Some(revision);
+            }
+            Continue(true)
+        });
+    }
+}
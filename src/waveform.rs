@@ -0,0 +1,252 @@
+use cairo::Context;
+
+use gtk::{DrawingArea, Inhibit, WidgetExt};
+
+use std::cell::{Cell, RefCell};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use cache::{cache_dir, file_key, stable_hash};
+use mp3::Mp3Decoder;
+use App;
+
+// Slices of the track drawn, whatever its length
+const POINTS: usize = 1000;
+// Frames reduced to their peak while decoding
+const BLOCK_SIZE: usize = 1024;
+const WAVEFORMS_DIR: &str = "waveforms";
+
+fn cache_prefix(path: &str) -> String {
+    format!("{:016x}-", stable_hash(path.as_bytes()))
+}
+
+// One file per track, named after its path, modification time and size, holding the
+// peaks quantized to a byte each
+fn cache_file(path: &str) -> Option<PathBuf> {
+    let (mtime, size) = file_key(path)?;
+    let dir = cache_dir()?.join(WAVEFORMS_DIR);
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir.join(format!("{}{}-{}", cache_prefix(path), mtime, size)))
+}
+
+// Envelopes of previous versions of the track, left when it was edited
+fn remove_stale_files(path: &str, current: &Path) {
+    let prefix = cache_prefix(path);
+    let entries = match current.parent().and_then(|dir| fs::read_dir(dir).ok()) {
+        Some(entries) => entries,
+        None => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let stale =
+            entry.file_name().to_string_lossy().starts_with(&prefix) && entry.path() != *current;
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn load_envelope(path: &str) -> Option<Vec<f32>> {
+    let mut bytes = Vec::with_capacity(POINTS);
+    File::open(cache_file(path)?)
+        .ok()?
+        .read_to_end(&mut bytes)
+        .ok()?;
+    if bytes.len() != POINTS {
+        return None;
+    }
+    Some(bytes.iter().map(|&byte| byte as f32 / 255.0).collect())
+}
+
+fn save_envelope(path: &str, envelope: &[f32]) {
+    let bytes: Vec<u8> = envelope
+        .iter()
+        .map(|peak| (peak.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect();
+    let file = match cache_file(path) {
+        Some(file) => file,
+        None => return,
+    };
+    remove_stale_files(path, &file);
+    if let Ok(mut file) = File::create(file) {
+        let _ = file.write_all(&bytes);
+    }
+}
+
+fn compute_envelope(path: &str) -> Option<Vec<f32>> {
+    let file = File::open(path).ok()?;
+    let decoder = Mp3Decoder::new(BufReader::new(file)).ok()?;
+    let block_size = BLOCK_SIZE * decoder.channels();
+
+    let mut blocks = Vec::new();
+    let mut peak: f32 = 0.0;
+    for (index, sample) in decoder.enumerate() {
+        peak = peak.max(sample.abs());
+        if (index + 1) % block_size == 0 {
+            blocks.push(peak);
+            peak = 0.0;
+        }
+    }
+    blocks.push(peak);
+
+    Some(
+        (0..POINTS)
+            .map(|point| {
+                let first = point * blocks.len() / POINTS;
+                let last = ((point + 1) * blocks.len() / POINTS).max(first + 1);
+                blocks[first..last.min(blocks.len())]
+                    .iter()
+                    .cloned()
+                    .fold(0.0, f32::max)
+            })
+            .collect(),
+    )
+}
+
+// Peak of each slice of the track from 0 to 1, decoding it the first time
+fn envelope(path: &str) -> Option<Vec<f32>> {
+    if let Some(envelope) = load_envelope(path) {
+        return Some(envelope);
+    }
+    let envelope = compute_envelope(path)?;
+    save_envelope(path, &envelope);
+    Some(envelope)
+}
+
+// Amplitude envelope of the current track, the played part highlighted
+pub struct WaveformBar {
+    area: DrawingArea,
+    path: RefCell<Option<String>>,
+    envelope: RefCell<Option<Vec<f32>>>,
+    // Worker computing the envelopes, skipping the tracks already left
+    requests: Sender<String>,
+    results: Receiver<(String, Vec<f32>)>,
+    // From 0 to 1
+    progress: Cell<f64>,
+}
+
+impl WaveformBar {
+    pub fn new() -> Self {
+        let area = DrawingArea::new();
+        area.set_size_request(-1, 60);
+        area.add_events(gdk::EventMask::BUTTON_PRESS_MASK.bits() as i32);
+
+        let (requests, receiver) = channel::<String>();
+        let (sender, results) = channel();
+        thread::spawn(move || {
+            while let Ok(mut path) = receiver.recv() {
+                while let Ok(newer) = receiver.try_recv() {
+                    path = newer;
+                }
+                if let Some(envelope) = envelope(&path) {
+                    if sender.send((path, envelope)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        WaveformBar {
+            area,
+            path: RefCell::new(None),
+            envelope: RefCell::new(None),
+            requests,
+            results,
+            progress: Cell::new(0.0),
+        }
+    }
+
+    pub fn area(&self) -> &DrawingArea {
+        &self.area
+    }
+
+    pub fn set_track(&self, path: Option<String>) {
+        if *self.path.borrow() == path {
+            return;
+        }
+        *self.path.borrow_mut() = path.clone();
+        *self.envelope.borrow_mut() = None;
+        self.area.queue_draw();
+
+        if let Some(path) = path {
+            let _ = self.requests.send(path);
+        }
+    }
+
+    pub fn update(&self, progress: f64) {
+        while let Ok((path, envelope)) = self.results.try_recv() {
+            // A late result for a previous track is dropped
+            if self.path.borrow().as_ref() == Some(&path) {
+                *self.envelope.borrow_mut() = Some(envelope);
+                self.area.queue_draw();
+            }
+        }
+
+        let progress = progress.clamp(0.0, 1.0);
+        let width = self.area.get_allocated_width() as f64;
+        // Only redrawn once the position moved by a pixel
+        if ((progress - self.progress.get()) * width).abs() >= 1.0 {
+            self.progress.set(progress);
+            self.area.queue_draw();
+        }
+    }
+
+    fn draw(&self, context: &Context) {
+        let width = self.area.get_allocated_width() as f64;
+        let height = self.area.get_allocated_height() as f64;
+        let played = self.progress.get() * width;
+
+        let envelope = self.envelope.borrow();
+        let envelope = match *envelope {
+            Some(ref envelope) => envelope,
+            None => return,
+        };
+
+        let point_width = width / envelope.len() as f64;
+        for (index, &peak) in envelope.iter().enumerate() {
+            let x = index as f64 * point_width;
+            if x < played {
+                context.set_source_rgb(0.3, 0.7, 0.4);
+            } else {
+                context.set_source_rgb(0.6, 0.6, 0.6);
+            }
+            let bar_height = (peak as f64 * height).max(1.0);
+            context.rectangle(
+                x,
+                (height - bar_height) / 2.0,
+                point_width.max(1.0),
+                bar_height,
+            );
+            context.fill();
+        }
+    }
+}
+
+impl App {
+    // Clicking the waveform seeks to that fraction of the track
+    pub fn connect_waveform_events(&self) {
+        let waveform = self.waveform.clone();
+        self.waveform.area.connect_draw(move |_, context| {
+            waveform.draw(context);
+            Inhibit(false)
+        });
+
+        let playlist = self.playlist.clone();
+        let state = self.state.clone();
+        self.waveform
+            .area
+            .connect_button_press_event(move |area, event| {
+                let duration = playlist
+                    .path()
+                    .and_then(|path| state.lock().unwrap().durations.get(&path).cloned());
+                if let Some(duration) = duration {
+                    let (x, _) = event.get_position();
+                    let fraction = x / area.get_allocated_width().max(1) as f64;
+                    playlist.seek((fraction.clamp(0.0, 1.0) * duration as f64) as u64);
+                }
+                Inhibit(true)
+            });
+    }
+}